@@ -0,0 +1,82 @@
+// Building and taking apart lists, vectors, maps and sets, at two sizes: with persistent
+// collections each operation is O(1) or O(log n), so the time per element
+// should barely change between them.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use lisp_rs::interpreter::Interpreter;
+
+const DEFS: &str = "(do
+  (defn build (n) (loop ((i 0) (acc ())) (if (< i n) (recur (+ i 1) (cons i acc)) acc)))
+  (defn walk (l) (loop ((l l) (n 0)) (if (empty? l) n (recur (rest l) (+ n 1)))))
+  (defn fill (n) (loop ((i 0) (m {})) (if (< i n) (recur (+ i 1) (assoc m i i)) m)))
+  (defn drain (m n) (loop ((i 0) (m m)) (if (< i n) (recur (+ i 1) (dissoc m i)) m)))
+  (defn push (n) (loop ((i 0) (v [])) (if (< i n) (recur (+ i 1) (conj v i)) v)))
+  (defn index (v n) (loop ((i 0) (s 0)) (if (< i n) (recur (+ i 1) (+ s (nth v i))) s)))
+  (defn update (v n) (loop ((i 0) (v v)) (if (< i n) (recur (+ i 1) (assoc v i 0)) v)))
+  (defn adjoin (n) (loop ((i 0) (s #{})) (if (< i n) (recur (+ i 1) (conj s i)) s)))
+  (defn remove (s n) (loop ((i 0) (s s)) (if (< i n) (recur (+ i 1) (disj s i)) s))))";
+
+const SIZES: [usize; 2] = [1_000, 10_000];
+
+fn bench(c: &mut Criterion, name: &str, setup: impl Fn(usize) -> String, expr: &str) {
+    let mut group = c.benchmark_group(name);
+    for n in SIZES {
+        let interp = Interpreter::new().unwrap();
+        interp.eval_str(DEFS).unwrap();
+        interp.eval_str(&setup(n)).unwrap();
+        group.bench_with_input(BenchmarkId::from_parameter(n), &n, |b, _| {
+            b.iter(|| interp.eval_str(expr).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn lists(c: &mut Criterion) {
+    bench(c, "cons", |n| format!("(def! n {})", n), "(build n)");
+    bench(c, "rest", |n| format!("(def! l (build {}))", n), "(walk l)");
+    bench(
+        c,
+        "concat",
+        |n| format!("(def! l (build {}))", n),
+        "(concat (list 1 2 3) l)",
+    );
+}
+
+fn maps(c: &mut Criterion) {
+    bench(c, "assoc", |n| format!("(def! n {})", n), "(fill n)");
+    bench(
+        c,
+        "dissoc",
+        |n| format!("(do (def! n {}) (def! m (fill n)))", n),
+        "(drain m n)",
+    );
+}
+
+fn vectors(c: &mut Criterion) {
+    bench(c, "vector-conj", |n| format!("(def! n {})", n), "(push n)");
+    bench(
+        c,
+        "vector-nth",
+        |n| format!("(do (def! n {}) (def! v (push n)))", n),
+        "(index v n)",
+    );
+    bench(
+        c,
+        "vector-assoc",
+        |n| format!("(do (def! n {}) (def! v (push n)))", n),
+        "(update v n)",
+    );
+}
+
+fn sets(c: &mut Criterion) {
+    bench(c, "set-conj", |n| format!("(def! n {})", n), "(adjoin n)");
+    bench(
+        c,
+        "disj",
+        |n| format!("(do (def! n {}) (def! s (adjoin n)))", n),
+        "(remove s n)",
+    );
+}
+
+criterion_group!(benches, lists, vectors, maps, sets);
+criterion_main!(benches);
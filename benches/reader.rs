@@ -0,0 +1,31 @@
+// Reader throughput on a multi-megabyte source file.
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use lisp_rs::reader::read_all;
+
+const CHUNK: &str = r#"
+;; a definition with a docstring, keywords, a map and some arithmetic
+(defn area (shape)
+  "the area of `shape`, a map with a :kind"
+  (case (get shape :kind)
+    :square (* (get shape :side) (get shape :side))
+    :rect (* (get shape :w) (get shape :h))
+    (throw "unknown shape" (pr-str shape))))
+(def! shapes (list {:kind :square :side 12} {:kind :rect :w -3 :h 40}))
+`(1 2 ~(+ 1 2) ~@(list 4 5) "a line\nand \"quotes\"")
+"#;
+
+// about 4MB
+const REPEAT: usize = 10_000;
+
+fn read(c: &mut Criterion) {
+    let src = CHUNK.repeat(REPEAT);
+    let mut group = c.benchmark_group("reader");
+    group.throughput(Throughput::Bytes(src.len() as u64));
+    group.sample_size(10);
+    group.bench_function("read_all", |b| b.iter(|| read_all(&src).unwrap()));
+    group.finish();
+}
+
+criterion_group!(benches, read);
+criterion_main!(benches);
@@ -0,0 +1,45 @@
+// The tree-walker and the VM on the same call-heavy and macro-heavy
+// programs.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use lisp_rs::{interpreter::Interpreter, Backend};
+
+const FIB: &str = "(defn fib (n) (if (< n 2) n (+ (fib (- n 1)) (fib (- n 2)))))";
+const TAK: &str = "(defn tak (x y z)
+                     (if (not (< y x))
+                         z
+                         (tak (tak (- x 1) y z) (tak (- y 1) z x) (tak (- z 1) x y))))";
+
+fn bench_backends(c: &mut Criterion, name: &str, defs: &str, expr: &str) {
+    let mut group = c.benchmark_group(name);
+    for (label, backend) in [("tree-walker", Backend::TreeWalker), ("vm", Backend::Vm)] {
+        let interp = Interpreter::with_backend(backend).unwrap();
+        interp.eval_str(defs).unwrap();
+        group.bench_function(label, |b| b.iter(|| interp.eval_str(expr).unwrap()));
+    }
+    group.finish();
+}
+
+fn fib(c: &mut Criterion) {
+    bench_backends(c, "fib", FIB, "(fib 20)");
+}
+
+fn tak(c: &mut Criterion) {
+    bench_backends(c, "tak", TAK, "(tak 18 12 6)");
+}
+
+// every evaluation expands the macros again: `defn`, `let`, `cond`, `->`,
+// `case` and the `and`/`or` it expands to
+fn macros(c: &mut Criterion) {
+    bench_backends(
+        c,
+        "macros",
+        "",
+        "(do (defn sign (x) (let ((y (-> x (* 2) (+ 1))))
+                            (cond (< y 0) :neg (= y 1) :zero true (case y (3 5) :small :big))))
+             (sign 2))",
+    );
+}
+
+criterion_group!(benches, fib, tak, macros);
+criterion_main!(benches);
@@ -0,0 +1,563 @@
+// Syntax analysis. A form is turned into a tree of `Node`s once, with its
+// macros expanded, its special forms checked and resolved, and constant
+// expressions folded, and `exec` runs the tree. The body of a `fn*` is
+// analyzed along with the `fn*` form, so a macro used in a function is
+// expanded once per definition instead of on every call. The VM compiles
+// the same tree.
+//
+// Locals are resolved to the slot of the scope binding them. A `let*` or
+// `loop` value sees the names bound before it, or else the outer ones, and
+// a function it makes sees all of the form's names, as it runs later.
+//
+// Macros are expanded as the analyzer meets them, in the env the form is
+// analyzed in; a local binding hides a macro of the same name. A macro has
+// to be defined before the code using it is analyzed, which is why a
+// top-level `do` is analyzed and run one form at a time.
+
+use std::{rc::Rc, sync::OnceLock};
+
+use anyhow::{bail, Result};
+
+use crate::{
+    core,
+    env::{arity, get_env, new_env, set_env, walk_pattern, Env, Part},
+    expand::{macroexpand_1, symbols_in},
+    fn_arities,
+    hamt::Hamt,
+    implicit_do,
+    printer::print_readably,
+    quasiquote,
+    symbol::{self, Symbol},
+    types::{
+        MalFn,
+        MalVal::{self, Bool, List, Map, Nil, Num, RustFunc, Set, Sym, Vector},
+    },
+};
+
+#[derive(Debug)]
+pub enum Node {
+    Const(MalVal),
+    // a global, looked up by name
+    Var(Symbol),
+    // a local: its name, and the (depth, index) of its slot, `depth`
+    // scopes up from the innermost one
+    Local(Symbol, usize, usize),
+    // a map literal with values to evaluate, by encoded key
+    Map(Vec<(String, Rc<Node>)>),
+    Def(Symbol, Rc<Node>),
+    DefMacro(Symbol, Rc<Node>),
+    // the names of its slots, the (pattern, value) bindings and the body
+    Let(Vec<Symbol>, Vec<(Pattern, Rc<Node>)>, Rc<Node>),
+    Loop(Rc<Loop>),
+    Recur(Vec<Rc<Node>>),
+    // body, catch pattern, the names of the handler's slots and the handler
+    Try(Rc<Node>, Pattern, Vec<Symbol>, Rc<Node>),
+    Do(Vec<Rc<Node>>),
+    If(Rc<Node>, Rc<Node>, Rc<Node>),
+    Fn(Rc<Vec<(Pattern, Rc<Node>)>>),
+    DefineSyntax(Symbol, MalVal),
+    // `macroexpand`, `macroexpand-1` or `macroexpand-all` of a form
+    Expand(Symbol, MalVal),
+    Call(Rc<Node>, Vec<Rc<Node>>),
+}
+
+#[derive(Debug)]
+pub struct Loop {
+    pub slots: Vec<Symbol>,
+    pub binds: Vec<(Pattern, Rc<Node>)>,
+    pub body: Rc<Node>,
+}
+
+// a binding pattern, with its defaults analyzed in the order
+// `env::walk_pattern` meets them
+#[derive(Debug)]
+pub struct Pattern {
+    pub form: MalVal,
+    pub defaults: Vec<Rc<Node>>,
+}
+
+// the slots of a `fn*` arity, `let*`, `loop` or `catch*`: the names of
+// its patterns in order, then those `def!`d in it. The VM gives the scope a
+// frame with these slots; the tree-walker's env for it binds the names in
+// about that order.
+#[derive(Default)]
+struct Scope {
+    names: Vec<Symbol>,
+    // slots of `let*` and `loop` names whose value isn't bound yet. The
+    // values before them don't see them, but the functions they make do,
+    // as they run later
+    unbound: Vec<usize>,
+    // the scope of a `fn*` arity
+    function: bool,
+}
+
+struct Analyzer<'a> {
+    env: &'a Env,
+    // innermost last
+    scopes: Vec<Scope>,
+    // globals `def!`d in the form so far
+    defined: Vec<Symbol>,
+}
+
+// analyze `ast`, to be evaluated in `env`
+pub fn analyze(ast: &MalVal, env: &Env) -> Result<Rc<Node>> {
+    Analyzer {
+        env,
+        scopes: vec![],
+        defined: vec![],
+    }
+    .analyze(ast, None)
+}
+
+fn is_special(s: Symbol) -> bool {
+    matches!(
+        s,
+        symbol::DEF
+            | symbol::LET
+            | symbol::LOOP
+            | symbol::RECUR
+            | symbol::TRY
+            | symbol::QUOTE
+            | symbol::QUASIQUOTE
+            | symbol::QUASIQUOTEEXPAND
+            | symbol::DO
+            | symbol::IF
+            | symbol::FN
+            | symbol::DEFMACRO
+            | symbol::DEFINE_SYNTAX
+            | symbol::MACROEXPAND
+            | symbol::MACROEXPAND_1
+            | symbol::MACROEXPAND_ALL
+    )
+}
+
+fn constant(node: &Node) -> Option<&MalVal> {
+    match node {
+        Node::Const(val) => Some(val),
+        _ => None,
+    }
+}
+
+// `node`, whose value is given `meta` as it is made
+fn with_meta(node: Rc<Node>, meta: &Rc<MalVal>) -> Rc<Node> {
+    let meta = Rc::new(Node::Const((**meta).clone()));
+    Rc::new(Node::Call(
+        Rc::new(Node::Const(RustFunc(core::with_meta, None))),
+        vec![node, meta],
+    ))
+}
+
+// a vector or set literal: the collection if its elements are constants,
+// and otherwise a call to `make` with their values
+fn collection(elts: Vec<Rc<Node>>, make: MalFn, meta: Option<&Rc<MalVal>>) -> Result<Rc<Node>> {
+    let consts: Option<Vec<MalVal>> = elts.iter().map(|elt| constant(elt).cloned()).collect();
+    if let Some(consts) = consts {
+        let val = make(consts)?;
+        return Ok(Rc::new(Node::Const(match meta {
+            Some(meta) => val.with_meta(meta)?,
+            None => val,
+        })));
+    }
+    let node = Rc::new(Node::Call(Rc::new(Node::Const(RustFunc(make, None))), elts));
+    Ok(match meta {
+        Some(meta) => with_meta(node, meta),
+        None => node,
+    })
+}
+
+// the builtins `fold` computes
+const FOLDED: &[&str] = &["+", "-", "*", "=", "<", "<=", ">", ">="];
+
+// the builtin `fold` computes for `name`
+fn foldable(name: Symbol) -> Option<MalFn> {
+    static FOLDABLE: OnceLock<Vec<(&str, MalFn)>> = OnceLock::new();
+    let builtins = FOLDABLE.get_or_init(|| {
+        core::ns()
+            .into_iter()
+            .filter(|(n, _)| FOLDED.contains(n))
+            .collect()
+    });
+    builtins.iter().find(|(n, _)| name == *n).map(|&(_, f)| f)
+}
+
+// the value of a call to the builtin `name` with constant `args`, when it
+// can't fail
+fn fold(name: &str, args: &[&MalVal]) -> Option<MalVal> {
+    let [a, b] = args else {
+        return None;
+    };
+    if name == "=" {
+        return Some(Bool(a == b));
+    }
+    let (Num(a), Num(b)) = (a, b) else {
+        return None;
+    };
+    match name {
+        "+" => a.checked_add(*b).map(Num),
+        "-" => a.checked_sub(*b).map(Num),
+        "*" => a.checked_mul(*b).map(Num),
+        "<" => Some(Bool(a < b)),
+        "<=" => Some(Bool(a <= b)),
+        ">" => Some(Bool(a > b)),
+        ">=" => Some(Bool(a >= b)),
+        _ => None,
+    }
+}
+
+impl Analyzer<'_> {
+    // the (depth, index) of the slot `name` refers to here, if it is local
+    fn resolve(&self, name: Symbol) -> Option<(usize, usize)> {
+        // whether the reference is in a function made in the scope
+        let mut later = false;
+        for (depth, scope) in self.scopes.iter().rev().enumerate() {
+            let idx = (0..scope.names.len())
+                .rev()
+                .find(|&i| scope.names[i] == name && (later || !scope.unbound.contains(&i)));
+            if let Some(idx) = idx {
+                return Some((depth, idx));
+            }
+            later |= scope.function;
+        }
+        None
+    }
+
+    fn is_local(&self, name: Symbol) -> bool {
+        self.resolve(name).is_some()
+    }
+
+    // run `f` in a new scope; returns its result and the scope's names
+    fn scoped<T>(
+        &mut self,
+        function: bool,
+        f: impl FnOnce(&mut Self) -> Result<T>,
+    ) -> Result<(T, Vec<Symbol>)> {
+        self.scopes.push(Scope {
+            function,
+            ..Scope::default()
+        });
+        let ret = f(self);
+        let scope = self.scopes.pop().unwrap();
+        Ok((ret?, scope.names))
+    }
+
+    // give the names in `pattern` slots in the innermost scope; returns
+    // the first
+    fn declare(&mut self, pattern: &MalVal) -> Result<usize> {
+        if !matches!(pattern, Sym(..) | List(_) | Map(_)) {
+            bail!("invalid binding pattern `{}`", print_readably(pattern));
+        }
+        let scope = self.scopes.last_mut().unwrap();
+        let first = scope.names.len();
+        symbols_in(pattern, &mut scope.names);
+        Ok(first)
+    }
+
+    // analyze the defaults of `pattern`, whose names have the slots from
+    // `first` on. Each default sees the names bound before it, or else the
+    // outer ones
+    fn pattern(&mut self, form: &MalVal, first: usize) -> Result<Pattern> {
+        let mut names = vec![];
+        symbols_in(form, &mut names);
+        let slots = first..first + names.len();
+        self.scopes
+            .last_mut()
+            .unwrap()
+            .unbound
+            .extend(slots.clone());
+        let mut defaults = vec![];
+        walk_pattern(form, &mut |part| {
+            match part {
+                Part::Name(name) => {
+                    let scope = self.scopes.last_mut().unwrap();
+                    scope
+                        .unbound
+                        .retain(|&idx| !slots.contains(&idx) || names[idx - first] != name);
+                }
+                Part::Default(default) => defaults.push(self.analyze(default, None)?),
+            }
+            Ok(())
+        })?;
+        let scope = self.scopes.last_mut().unwrap();
+        scope.unbound.retain(|idx| !slots.contains(idx));
+        Ok(Pattern {
+            form: form.clone(),
+            defaults,
+        })
+    }
+
+    // `recur` is allowed where `recur` is the arity of the innermost `loop`
+    // whose body the form is in tail position of
+    fn analyze(&mut self, ast: &MalVal, recur: Option<usize>) -> Result<Rc<Node>> {
+        let node = match ast {
+            Sym(s, _) => match self.resolve(*s) {
+                Some((depth, idx)) => Node::Local(*s, depth, idx),
+                None => Node::Var(*s),
+            },
+            Map(map) => {
+                let mut vals = vec![];
+                for (k, v) in map.iter() {
+                    vals.push((k.clone(), self.analyze(v, None)?));
+                }
+                let consts: Option<Hamt> = vals
+                    .iter()
+                    .map(|(k, v)| Some((k.clone(), constant(v)?.clone())))
+                    .collect();
+                match (consts, map.meta()) {
+                    (Some(consts), meta) => Node::Const(Map(consts.with_meta(meta.clone()))),
+                    (None, None) => Node::Map(vals),
+                    (None, Some(meta)) => return Ok(with_meta(Rc::new(Node::Map(vals)), meta)),
+                }
+            }
+            Vector(v) => {
+                let elts = v.iter().map(|elt| self.analyze(elt, None));
+                return collection(elts.collect::<Result<_>>()?, core::vector, ast.meta());
+            }
+            Set(set) => {
+                let elts = set.values().map(|elt| self.analyze(elt, None));
+                return collection(elts.collect::<Result<_>>()?, core::hash_set, ast.meta());
+            }
+            List(list) if !list.is_empty() => {
+                let node = self.analyze_list(ast, list, recur)?;
+                // `^meta (fn* ...)` makes functions with that metadata
+                return Ok(match (&*node, ast.meta()) {
+                    (Node::Fn(_), Some(meta)) => with_meta(node, meta),
+                    _ => node,
+                });
+            }
+            _ => Node::Const(ast.clone()),
+        };
+        Ok(Rc::new(node))
+    }
+
+    fn analyze_list(
+        &mut self,
+        ast: &MalVal,
+        list: &[MalVal],
+        recur: Option<usize>,
+    ) -> Result<Rc<Node>> {
+        let special = match &list[0] {
+            Sym(s, _) if is_special(*s) => Some(*s),
+            Sym(s, _) if !self.is_local(*s) => {
+                if let Some(expanded) = self.macroexpand(ast)? {
+                    return self.analyze(&expanded, recur);
+                }
+                None
+            }
+            _ => None,
+        };
+        let args = &list[1..];
+        let Some(head) = special else {
+            let func = self.analyze(&list[0], None)?;
+            let args = self.analyze_all(args)?;
+            return Ok(Rc::new(match self.fold_call(&func, &args) {
+                Some(val) => Node::Const(val),
+                None => Node::Call(func, args),
+            }));
+        };
+        let node = match head {
+            symbol::DEF | symbol::DEFMACRO => {
+                let [Sym(name, _), val] = args else {
+                    bail!("{} expects a symbol and a value", head);
+                };
+                // declared first, so the value can refer to itself
+                match self.scopes.last_mut() {
+                    Some(scope) if !scope.names.contains(name) => scope.names.push(*name),
+                    Some(_) => (),
+                    None => self.defined.push(*name),
+                }
+                let val = self.analyze(val, None)?;
+                match head {
+                    symbol::DEF => Node::Def(*name, val),
+                    _ => Node::DefMacro(*name, val),
+                }
+            }
+            symbol::LET => {
+                let [List(binds), body] = args else {
+                    bail!("let* expects a list of bindings and a body");
+                };
+                let ((binds, body), slots) = self.scoped(false, |a| {
+                    let binds = a.bindings(binds, "let*")?;
+                    Ok((binds, a.analyze(body, recur)?))
+                })?;
+                Node::Let(slots, binds, body)
+            }
+            symbol::LOOP => {
+                let Some(List(binds)) = args.first() else {
+                    bail!("loop expects a list of bindings");
+                };
+                let body = implicit_do(&args[1..]);
+                let ((binds, body), slots) = self.scoped(false, |a| {
+                    let binds = a.bindings(binds, "loop")?;
+                    let body = a.analyze(&body, Some(binds.len()))?;
+                    Ok((binds, body))
+                })?;
+                Node::Loop(Rc::new(Loop { slots, binds, body }))
+            }
+            symbol::RECUR => match recur {
+                Some(n) if n == args.len() => Node::Recur(self.analyze_all(args)?),
+                Some(n) => bail!("recur expects {} args, got {}", n, args.len()),
+                None => bail!("`recur` must be in tail position of a `loop`"),
+            },
+            symbol::TRY => match args {
+                [body] => return self.analyze(body, None),
+                [body, List(catch)]
+                    if catch.len() == 3 && matches!(catch[0], Sym(symbol::CATCH, _)) =>
+                {
+                    let body = self.analyze(body, None)?;
+                    let ((pattern, handler), slots) = self.scoped(false, |a| {
+                        let first = a.declare(&catch[1])?;
+                        let pattern = a.pattern(&catch[1], first)?;
+                        Ok((pattern, a.analyze(&catch[2], None)?))
+                    })?;
+                    Node::Try(body, pattern, slots, handler)
+                }
+                _ => bail!("try* expects a body and an optional (catch* pattern handler)"),
+            },
+            symbol::QUOTE | symbol::QUASIQUOTE | symbol::QUASIQUOTEEXPAND => {
+                let [arg] = args else {
+                    bail!("{} expects one argument", head);
+                };
+                match head {
+                    symbol::QUOTE => Node::Const(arg.clone()),
+                    symbol::QUASIQUOTE => return self.analyze(&quasiquote(arg), recur),
+                    _ => Node::Const(quasiquote(arg)),
+                }
+            }
+            symbol::DO => match args {
+                [] => Node::Const(Nil),
+                [.., last] => {
+                    let mut forms = vec![];
+                    for form in args[..args.len() - 1].iter() {
+                        let form = self.analyze(form, None)?;
+                        // a constant whose value is dropped does nothing
+                        if constant(&form).is_none() {
+                            forms.push(form);
+                        }
+                    }
+                    let last = self.analyze(last, recur)?;
+                    if forms.is_empty() {
+                        return Ok(last);
+                    }
+                    forms.push(last);
+                    Node::Do(forms)
+                }
+            },
+            symbol::IF => {
+                let (test, then, other) = match args {
+                    [test, then] => (test, then, &Nil),
+                    [test, then, other] => (test, then, other),
+                    _ => bail!("if expects a test, a branch and an optional else branch"),
+                };
+                let test = self.analyze(test, None)?;
+                match constant(&test) {
+                    Some(Nil | Bool(false)) => return self.analyze(other, recur),
+                    Some(_) => return self.analyze(then, recur),
+                    None => Node::If(
+                        test,
+                        self.analyze(then, recur)?,
+                        self.analyze(other, recur)?,
+                    ),
+                }
+            }
+            symbol::FN => {
+                let mut arities = vec![];
+                for (params, body) in fn_arities(args)? {
+                    if !matches!(params, Sym(..) | List(_)) {
+                        bail!("invalid parameter list `{}`", print_readably(&params));
+                    }
+                    arity(&params)?;
+                    let ((params, body), _) = self.scoped(true, |a| {
+                        let first = a.declare(&params)?;
+                        let params = a.pattern(&params, first)?;
+                        Ok((params, a.analyze(&body, None)?))
+                    })?;
+                    arities.push((params, body));
+                }
+                Node::Fn(Rc::new(arities))
+            }
+            symbol::DEFINE_SYNTAX => {
+                let [Sym(name, _), rules] = args else {
+                    bail!("define-syntax expects a symbol and a syntax-rules form");
+                };
+                Node::DefineSyntax(*name, rules.clone())
+            }
+            symbol::MACROEXPAND | symbol::MACROEXPAND_1 | symbol::MACROEXPAND_ALL => {
+                let [form] = args else {
+                    bail!("{} expects one form", head);
+                };
+                Node::Expand(head, form.clone())
+            }
+            _ => unreachable!(),
+        };
+        Ok(Rc::new(node))
+    }
+
+    fn analyze_all(&mut self, forms: &[MalVal]) -> Result<Vec<Rc<Node>>> {
+        forms.iter().map(|form| self.analyze(form, None)).collect()
+    }
+
+    // `((pattern value) ...)`. Every name gets its slot first; each value
+    // sees the names bound before it, and the functions it makes see them
+    // all.
+    fn bindings(&mut self, binds: &[MalVal], form: &str) -> Result<Vec<(Pattern, Rc<Node>)>> {
+        let mut patterns = vec![];
+        for bind in binds.iter() {
+            match bind {
+                List(b) if b.len() == 2 => {
+                    let first = self.declare(&b[0])?;
+                    patterns.push((&b[0], &b[1], first));
+                }
+                _ => bail!("invalid binding `{}` in {}", print_readably(bind), form),
+            }
+        }
+        let scope = self.scopes.last_mut().unwrap();
+        let declared = scope.names.len();
+        scope.unbound = (0..declared).collect();
+        let mut res = vec![];
+        for &(pattern, val, first) in patterns.iter() {
+            let val = self.analyze(val, None)?;
+            res.push((self.pattern(pattern, first)?, val));
+        }
+        Ok(res)
+    }
+
+    // expand `ast` once if it is a macro call, hiding the macros shadowed
+    // by locals
+    fn macroexpand(&self, ast: &MalVal) -> Result<Option<MalVal>> {
+        if self.scopes.iter().all(|scope| scope.names.is_empty()) {
+            return macroexpand_1(ast, self.env);
+        }
+        let scratch = new_env(Some(self.env.clone()));
+        for scope in self.scopes.iter() {
+            for &name in scope.names.iter().filter(|&&name| self.is_local(name)) {
+                set_env(&scratch, Sym(name, None), Nil)?;
+            }
+        }
+        macroexpand_1(ast, &scratch)
+    }
+
+    // calls to the arithmetic builtins with constant args are computed now,
+    // when the global is still the builtin and nothing can rebind it before
+    // the call runs: not in a function, which runs later, nor after a
+    // `def!` of the name in the same form
+    fn fold_call(&self, func: &Node, args: &[Rc<Node>]) -> Option<MalVal> {
+        let Node::Var(name) = func else {
+            return None;
+        };
+        if self.scopes.iter().any(|scope| scope.function) || self.defined.contains(name) {
+            return None;
+        }
+        let args: Vec<&MalVal> = args
+            .iter()
+            .map(|arg| constant(arg))
+            .collect::<Option<_>>()?;
+        let builtin = foldable(*name)?;
+        let RustFunc(f, _) = get_env(self.env, &Sym(*name, None)).ok()? else {
+            return None;
+        };
+        if !std::ptr::fn_addr_eq(f, builtin) {
+            return None;
+        }
+        fold(name.as_str(), &args)
+    }
+}
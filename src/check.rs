@@ -0,0 +1,50 @@
+// `lisp_rs check`: report every syntax error in source files, one per line,
+// as `file:line:col: message`, e.g. for an editor to show.
+
+use std::{
+    fs,
+    io::{self, Read},
+};
+
+use anyhow::{bail, Context, Result};
+
+use crate::reader::read_all_recovering;
+
+const USAGE: &str = "usage: lisp_rs check [<file>...]";
+
+// run the `check` subcommand and return the process exit code: 1 if any
+// file has syntax errors
+pub fn run(args: &[String]) -> Result<i32> {
+    let mut files = vec![];
+    for arg in args {
+        match &arg[..] {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return Ok(0);
+            }
+            _ if arg.starts_with("--") => bail!("unknown option `{}`\n{}", arg, USAGE),
+            _ => files.push(arg),
+        }
+    }
+
+    if files.is_empty() {
+        let mut src = String::new();
+        io::stdin().read_to_string(&mut src)?;
+        return check("<stdin>", &src);
+    }
+
+    let mut code = 0;
+    for file in files {
+        let src = fs::read_to_string(file).with_context(|| format!("{}: failed to read", file))?;
+        code = code.max(check(file, &src)?);
+    }
+    Ok(code)
+}
+
+fn check(name: &str, src: &str) -> Result<i32> {
+    let (_, diags) = read_all_recovering(src).with_context(|| name.to_owned())?;
+    for diag in diags.iter() {
+        println!("{}:{}", name, diag.describe(src));
+    }
+    Ok(if diags.is_empty() { 0 } else { 1 })
+}
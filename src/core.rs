@@ -1,14 +1,26 @@
-use std::rc::Rc;
-
-use anyhow::{anyhow, bail, Ok};
+use std::{
+    cell::RefCell,
+    fs,
+    rc::Rc,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 use crate::{
-    printer::print,
+    apply, gc,
+    hamt::Hamt,
+    limits::allocate,
+    pretty::{self, DEFAULT_WIDTH},
+    printer::{print_with, PrintConfig},
+    reader::read_str,
+    seq::Seq,
+    symbol::Symbol,
     types::{
-        MalRet,
-        MalVal::{self, Bool, List, Nil, Num},
+        map_entries, map_key, set_elements, set_key, MalFn, MalRet,
+        MalVal::{self, Atom, Bool, Char, Kw, List, Map, Nil, Num, Set, Str, Sym, Vector},
     },
+    vector,
 };
+use anyhow::{anyhow, bail, Context, Ok, Result};
 
 macro_rules! binary {
     ($type: ident, $ret:ident, $fn:expr) => {
@@ -17,74 +29,691 @@ macro_rules! binary {
                 bail!("expecting two args");
             }
             match (a[0].clone(), a[1].clone()) {
-                ($type(a0), $type(a1)) => Ok($ret($fn(a0, a1))),
+                ($type(a0), $type(a1)) => match $fn(a0, a1) {
+                    Some(ret) => Ok($ret(ret)),
+                    None => bail!("integer overflow or division by zero"),
+                },
                 _ => Err(anyhow!("invalid type of args")),
             }
         }
     };
 }
 
+// a new list or string, counted against the allocation limit
+fn new_list(elts: Vec<MalVal>) -> MalRet {
+    allocate(elts.len())?;
+    Ok(List(elts.into()))
+}
+
+// the elements of a list, vector or set, or none for nil
+fn elements(val: &MalVal) -> Option<Vec<MalVal>> {
+    match val {
+        List(l) => Some(l.iter().cloned().collect()),
+        Vector(v) => Some(v.iter().cloned().collect()),
+        Set(s) => Some(set_elements(s)),
+        Nil => Some(vec![]),
+        _ => None,
+    }
+}
+
+fn new_str(s: String) -> MalRet {
+    allocate(1 + s.len() / 8)?;
+    Ok(Str(s))
+}
+
+fn join(args: &[MalVal], readably: bool, sep: &str) -> Result<String> {
+    let config = PrintConfig {
+        readably,
+        ..PrintConfig::default()
+    };
+    let printed = args
+        .iter()
+        .map(|arg| print_with(arg, &config))
+        .collect::<Result<Vec<String>>>()?;
+    Ok(printed.join(sep))
+}
+
+fn pr_str(args: Vec<MalVal>) -> MalRet {
+    new_str(join(&args, true, " ")?)
+}
+
+fn str(args: Vec<MalVal>) -> MalRet {
+    new_str(join(&args, false, "")?)
+}
+
 fn prn(args: Vec<MalVal>) -> MalRet {
-    for arg in args {
-        println!("{}", print(&arg));
+    println!("{}", join(&args, true, " ")?);
+    Ok(Nil)
+}
+
+fn println(args: Vec<MalVal>) -> MalRet {
+    println!("{}", join(&args, false, " ")?);
+    Ok(Nil)
+}
+
+fn pprint(args: Vec<MalVal>) -> MalRet {
+    let width = match args.get(1) {
+        Some(Num(n)) if *n > 0 => *n as usize,
+        Some(_) => bail!("pprint width must be a positive number"),
+        None => DEFAULT_WIDTH,
+    };
+    match args.first() {
+        Some(val) => println!("{}", pretty::pprint(val, &PrintConfig::default(), width)?),
+        None => bail!("pprint expects a value"),
     }
     Ok(Nil)
 }
 
+fn slurp(args: Vec<MalVal>) -> MalRet {
+    match args.first() {
+        Some(Str(path)) => {
+            let contents = fs::read_to_string(path).map_err(|e| anyhow!("{}: {}", path, e))?;
+            new_str(contents)
+        }
+        _ => Err(anyhow!("slurp expects a file name")),
+    }
+}
+
+fn read_string(args: Vec<MalVal>) -> MalRet {
+    match args.first() {
+        Some(Str(s)) => {
+            allocate(s.len())?;
+            read_str(s)
+        }
+        _ => Err(anyhow!("read-string expects a string")),
+    }
+}
+
+fn equal(args: Vec<MalVal>) -> MalRet {
+    if args.len() != 2 {
+        bail!("expecting two args");
+    }
+    Ok(Bool(args[0] == args[1]))
+}
+
+// a fresh symbol named after `prefix`
+pub fn gensym(prefix: Symbol) -> MalVal {
+    Sym(Symbol::gensym(prefix), None)
+}
+
+fn gensym_builtin(args: Vec<MalVal>) -> MalRet {
+    match args.first() {
+        Some(Str(prefix)) => Ok(gensym(Symbol::intern(prefix)?)),
+        Some(_) => Err(anyhow!("gensym prefix must be a string")),
+        None => Ok(gensym(Symbol::new("G"))),
+    }
+}
+
+fn gc_builtin(_args: Vec<MalVal>) -> MalRet {
+    Ok(Num(gc::collect() as i64))
+}
+
+fn heap_stats(_args: Vec<MalVal>) -> MalRet {
+    let stats = gc::stats();
+    hash_map(vec![
+        Kw("envs".to_owned()),
+        Num(stats.envs as i64),
+        Kw("collections".to_owned()),
+        Num(stats.collections as i64),
+        Kw("freed".to_owned()),
+        Num(stats.freed as i64),
+    ])
+}
+
+// milliseconds since the Unix epoch
+fn time_ms(_args: Vec<MalVal>) -> MalRet {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
+    Ok(Num(now.as_millis() as i64))
+}
+
+// a list of the value followed by the elements of a list, vector or set
 fn cons(args: Vec<MalVal>) -> MalRet {
-    let car = args[0].clone();
-    let cdr = args[1].clone();
-    match cdr {
-        List(v) => {
-            let mut new_v = vec![car];
-            new_v.extend_from_slice(&v);
-            Ok(List(Rc::new(new_v.to_vec())))
+    match (args.first(), args.get(1)) {
+        (Some(car), Some(List(cdr))) => {
+            allocate(1)?;
+            Ok(List(cdr.cons(car.clone())))
         }
-        _ => Err(anyhow!("non-seq passed to concat")),
+        (Some(car), Some(coll)) => match elements(coll) {
+            Some(mut elts) => {
+                elts.insert(0, car.clone());
+                new_list(elts)
+            }
+            None => bail!("cons expects a value and a list"),
+        },
+        _ => Err(anyhow!("cons expects a value and a list")),
     }
 }
 
+// a list of the elements of each argument; the last list is shared, the
+// others are copied in front of it
 fn concat(args: Vec<MalVal>) -> MalRet {
-    let mut new_v = vec![];
-    for seq in args.iter() {
-        match seq {
-            List(v) => new_v.extend_from_slice(v),
-            _ => bail!("non-seq passed to concat"),
+    let mut res = Seq::default();
+    for (i, seq) in args.iter().rev().enumerate() {
+        match (seq, elements(seq)) {
+            (List(l), _) if i == 0 => res = l.clone(),
+            (_, Some(elts)) => {
+                allocate(elts.len())?;
+                for val in elts.into_iter().rev() {
+                    res = res.cons(val);
+                }
+            }
+            (_, None) => bail!("non-seq passed to concat"),
         }
     }
-    Ok(List(Rc::new(new_v.to_vec())))
+    Ok(List(res))
+}
+
+// (conj coll x y ...) adds each value in turn: in front of a list, at the
+// end of a vector, or to a set
+fn conj(args: Vec<MalVal>) -> MalRet {
+    let vals = args.iter().skip(1).cloned();
+    allocate(vals.len())?;
+    match args.first() {
+        Some(List(l)) => Ok(List(vals.fold(l.clone(), |res, val| res.cons(val)))),
+        Some(Nil) => Ok(List(vals.fold(Seq::default(), |res, val| res.cons(val)))),
+        Some(Vector(v)) => {
+            let mut res = v.clone();
+            vals.for_each(|val| res.push(val));
+            Ok(Vector(res))
+        }
+        Some(Set(s)) => {
+            let mut res = s.clone();
+            for val in vals {
+                res.insert(set_key(&val)?, val);
+            }
+            Ok(Set(res))
+        }
+        _ => bail!("conj expects a list, vector or set"),
+    }
 }
 
 fn count(args: Vec<MalVal>) -> MalRet {
-    let li = &args[0];
-    match li {
-        List(l) => {
-            Ok(Num(l.len() as i64))
+    if args.len() != 1 {
+        bail!("count expects one argument");
+    }
+    match &args[0] {
+        List(l) => Ok(Num(l.len() as i64)),
+        Vector(v) => Ok(Num(v.len() as i64)),
+        Map(m) | Set(m) => Ok(Num(m.len() as i64)),
+        Str(s) => Ok(Num(s.chars().count() as i64)),
+        Nil => Ok(Num(0)),
+        _ => Err(anyhow!("non-seq passed to count")),
+    }
+}
+
+fn list(args: Vec<MalVal>) -> MalRet {
+    new_list(args)
+}
+
+fn empty(args: Vec<MalVal>) -> MalRet {
+    match args.first() {
+        Some(List(l)) => Ok(Bool(l.is_empty())),
+        Some(Vector(v)) => Ok(Bool(v.is_empty())),
+        Some(Map(m) | Set(m)) => Ok(Bool(m.is_empty())),
+        Some(_) => Ok(Bool(false)),
+        None => bail!("expecting one arg"),
+    }
+}
+
+pub(crate) fn vector(args: Vec<MalVal>) -> MalRet {
+    allocate(args.len())?;
+    Ok(Vector(args.into()))
+}
+
+// a vector of the elements of a list, vector or set
+fn vec(args: Vec<MalVal>) -> MalRet {
+    match args.first() {
+        Some(Vector(v)) => Ok(Vector(v.with_meta(None))),
+        Some(coll) => match elements(coll) {
+            Some(elts) => vector(elts),
+            None => bail!("vec expects a list, vector or set"),
+        },
+        None => bail!("vec expects a list, vector or set"),
+    }
+}
+
+fn first(args: Vec<MalVal>) -> MalRet {
+    match args.first() {
+        Some(List(l)) => Ok(l.first().cloned().unwrap_or(Nil)),
+        Some(Vector(v)) => Ok(v.get(0).cloned().unwrap_or(Nil)),
+        Some(Nil) => Ok(Nil),
+        _ => Err(anyhow!("non-seq passed to first")),
+    }
+}
+
+// a list, also for the rest of a vector
+fn rest(args: Vec<MalVal>) -> MalRet {
+    match args.first() {
+        Some(List(l)) => Ok(List(l.rest())),
+        Some(Vector(v)) => new_list(v.iter().skip(1).cloned().collect()),
+        Some(Nil) => Ok(list![]),
+        _ => Err(anyhow!("non-seq passed to rest")),
+    }
+}
+
+fn nth(args: Vec<MalVal>) -> MalRet {
+    let (elt, n) = match (args.first(), args.get(1)) {
+        (Some(List(l)), Some(Num(n))) => (l.get(*n as usize), n),
+        (Some(Vector(v)), Some(Num(n))) => (v.get(*n as usize), n),
+        _ => bail!("nth expects a list or vector and an index"),
+    };
+    elt.cloned().context(format!("index {} out of range", n))
+}
+
+fn hash_map(args: Vec<MalVal>) -> MalRet {
+    if !args.len().is_multiple_of(2) {
+        bail!("hash-map expects an even number of args");
+    }
+    assoc_into(Hamt::new(), &args)
+}
+
+fn assoc_into(mut map: Hamt, kvs: &[MalVal]) -> MalRet {
+    allocate(kvs.len() / 2)?;
+    for pair in kvs.chunks(2) {
+        let val = pair.get(1).context("assoc expects key/value pairs")?;
+        map.insert(map_key(&pair[0])?, val.clone());
+    }
+    Ok(Map(map))
+}
+
+// (assoc v i x ...) replaces the element at each index, or adds one at
+// the end
+fn assoc_vector(mut v: vector::Vector, kvs: &[MalVal]) -> MalRet {
+    allocate(kvs.len() / 2)?;
+    for pair in kvs.chunks(2) {
+        let val = pair.get(1).context("assoc expects index/value pairs")?;
+        match pair[0] {
+            Num(i) if (0..v.len() as i64).contains(&i) => v.set(i as usize, val.clone()),
+            Num(i) if i == v.len() as i64 => v.push(val.clone()),
+            Num(i) => bail!("index {} out of range", i),
+            _ => bail!("assoc expects an index for a vector"),
         }
-        _ => Err(anyhow!("non-seq passed to count"))
     }
+    Ok(Vector(v))
+}
+
+fn assoc(args: Vec<MalVal>) -> MalRet {
+    match args.first() {
+        Some(Map(m)) => assoc_into(m.clone(), &args[1..]),
+        Some(Vector(v)) => assoc_vector(v.clone(), &args[1..]),
+        Some(Nil) => assoc_into(Hamt::new(), &args[1..]),
+        _ => Err(anyhow!("assoc expects a map or vector")),
+    }
+}
+
+fn dissoc(args: Vec<MalVal>) -> MalRet {
+    match args.first() {
+        Some(Map(m)) => {
+            let mut map = m.clone();
+            for key in args[1..].iter() {
+                map.remove(&map_key(key)?);
+            }
+            Ok(Map(map))
+        }
+        Some(Nil) => Ok(Nil),
+        _ => Err(anyhow!("dissoc expects a map")),
+    }
+}
+
+// a set's element is its own key, and a vector's keys are its indices
+fn get(args: Vec<MalVal>) -> MalRet {
+    let default = args.get(2).cloned().unwrap_or(Nil);
+    match (args.first(), args.get(1)) {
+        (Some(Map(m) | Set(m)), Some(key)) => Ok(m.get(&map_key(key)?).cloned().unwrap_or(default)),
+        (Some(Vector(v)), Some(Num(i))) => Ok(v.get(*i as usize).cloned().unwrap_or(default)),
+        (Some(Vector(_)) | Some(Nil), Some(_)) => Ok(default),
+        _ => Err(anyhow!("get expects a map and a key")),
+    }
+}
+
+fn contains(args: Vec<MalVal>) -> MalRet {
+    match (args.first(), args.get(1)) {
+        (Some(Map(m) | Set(m)), Some(key)) => Ok(Bool(m.contains_key(&map_key(key)?))),
+        (Some(Vector(v)), Some(Num(i))) => Ok(Bool((0..v.len() as i64).contains(i))),
+        (Some(Vector(_)) | Some(Nil), Some(_)) => Ok(Bool(false)),
+        _ => Err(anyhow!("contains? expects a map and a key")),
+    }
+}
+
+// sets: each element is stored under its `set_key`
+pub(crate) fn hash_set(args: Vec<MalVal>) -> MalRet {
+    allocate(args.len())?;
+    let mut set = Hamt::new();
+    for val in args {
+        set.insert(set_key(&val)?, val);
+    }
+    Ok(Set(set))
+}
+
+// a set of the elements of a list, vector or set
+fn set(args: Vec<MalVal>) -> MalRet {
+    match args.first().and_then(elements) {
+        Some(elts) => hash_set(elts),
+        None => bail!("set expects a list, vector or set"),
+    }
+}
+
+// (disj set x y ...) removes each value
+fn disj(args: Vec<MalVal>) -> MalRet {
+    match args.first() {
+        Some(Set(s)) => {
+            let mut set = s.clone();
+            for val in args[1..].iter() {
+                set.remove(&set_key(val)?);
+            }
+            Ok(Set(set))
+        }
+        Some(Nil) => Ok(Nil),
+        _ => Err(anyhow!("disj expects a set")),
+    }
+}
+
+fn keys(args: Vec<MalVal>) -> MalRet {
+    match args.first() {
+        Some(Map(m)) => new_list(map_entries(m).into_iter().map(|(k, _)| k).collect()),
+        _ => Err(anyhow!("keys expects a map")),
+    }
+}
+
+fn vals(args: Vec<MalVal>) -> MalRet {
+    match args.first() {
+        Some(Map(m)) => new_list(map_entries(m).into_iter().map(|(_, v)| v).collect()),
+        _ => Err(anyhow!("vals expects a map")),
+    }
+}
+
+fn keyword(args: Vec<MalVal>) -> MalRet {
+    match args.first() {
+        Some(Str(s)) => Ok(Kw(s.clone())),
+        Some(kw @ Kw(_)) => Ok(kw.clone()),
+        _ => Err(anyhow!("keyword expects a string")),
+    }
+}
+
+// Characters and strings. Strings are indexed and split by Unicode scalar
+// value (`char`), like the characters `seq` makes of them.
+
+fn int_to_char(args: Vec<MalVal>) -> MalRet {
+    match args.first() {
+        Some(Num(n)) => u32::try_from(*n)
+            .ok()
+            .and_then(char::from_u32)
+            .map(Char)
+            .context(format!("{} is not a Unicode scalar value", n)),
+        _ => Err(anyhow!("int->char expects a number")),
+    }
+}
+
+fn char_to_int(args: Vec<MalVal>) -> MalRet {
+    match args.first() {
+        Some(Char(c)) => Ok(Num(*c as i64)),
+        _ => Err(anyhow!("char->int expects a character")),
+    }
+}
+
+// the character for a number, a one-character string or a character
+fn char(args: Vec<MalVal>) -> MalRet {
+    match args.first() {
+        Some(Num(_)) => int_to_char(args),
+        Some(Char(c)) => Ok(Char(*c)),
+        Some(Str(s)) if s.chars().count() == 1 => Ok(Char(s.chars().next().unwrap())),
+        _ => Err(anyhow!("char expects a number or a one-character string")),
+    }
+}
+
+// the elements of a list, vector or set, the entries of a map as
+// (key value) lists, or the characters of a string; nil when there are none
+fn seq(args: Vec<MalVal>) -> MalRet {
+    let elts: Vec<MalVal> = match args.first() {
+        Some(List(l)) if !l.is_empty() => return Ok(List(l.clone())),
+        Some(Map(m)) => map_entries(m)
+            .into_iter()
+            .map(|(k, v)| list![k, v])
+            .collect(),
+        Some(Str(s)) => s.chars().map(Char).collect(),
+        Some(coll) => match elements(coll) {
+            Some(elts) => elts,
+            None => bail!("seq expects a collection or string"),
+        },
+        None => bail!("seq expects a collection or string"),
+    };
+    match elts.is_empty() {
+        true => Ok(Nil),
+        false => new_list(elts),
+    }
+}
+
+// a string argument, or a character as a string
+fn text(val: Option<&MalVal>) -> Option<String> {
+    match val {
+        Some(Str(s)) => Some(s.clone()),
+        Some(Char(c)) => Some(c.to_string()),
+        _ => None,
+    }
+}
+
+fn upper_case(args: Vec<MalVal>) -> MalRet {
+    match args.first() {
+        Some(Str(s)) => new_str(s.to_uppercase()),
+        _ => Err(anyhow!("upper-case expects a string")),
+    }
+}
+
+fn lower_case(args: Vec<MalVal>) -> MalRet {
+    match args.first() {
+        Some(Str(s)) => new_str(s.to_lowercase()),
+        _ => Err(anyhow!("lower-case expects a string")),
+    }
+}
+
+fn trim(args: Vec<MalVal>) -> MalRet {
+    match args.first() {
+        Some(Str(s)) => new_str(s.trim().to_owned()),
+        _ => Err(anyhow!("trim expects a string")),
+    }
+}
+
+fn starts_with(args: Vec<MalVal>) -> MalRet {
+    match (args.first(), text(args.get(1))) {
+        (Some(Str(s)), Some(prefix)) => Ok(Bool(s.starts_with(&prefix))),
+        _ => Err(anyhow!("starts-with? expects two strings")),
+    }
+}
+
+fn ends_with(args: Vec<MalVal>) -> MalRet {
+    match (args.first(), text(args.get(1))) {
+        (Some(Str(s)), Some(suffix)) => Ok(Bool(s.ends_with(&suffix))),
+        _ => Err(anyhow!("ends-with? expects two strings")),
+    }
+}
+
+// (index-of s x) or (index-of s x from): the index of the first `x`, a
+// string or character, in `s` at or after `from`; nil if there is none
+fn index_of(args: Vec<MalVal>) -> MalRet {
+    let (Some(Str(s)), Some(needle)) = (args.first(), text(args.get(1))) else {
+        bail!("index-of expects a string and a string or character");
+    };
+    let from = match args.get(2) {
+        Some(Num(n)) if *n >= 0 => *n as usize,
+        Some(_) => bail!("index-of expects a non-negative index"),
+        None => 0,
+    };
+    let Some(start) = s.char_indices().map(|(i, _)| i).chain([s.len()]).nth(from) else {
+        return Ok(Nil);
+    };
+    Ok(match s[start..].find(&needle) {
+        Some(i) => Num((from + s[start..start + i].chars().count()) as i64),
+        None => Nil,
+    })
+}
+
+// (replace s match replacement) replaces every `match`; both are strings or
+// characters
+fn replace(args: Vec<MalVal>) -> MalRet {
+    match (args.first(), text(args.get(1)), text(args.get(2))) {
+        (Some(Str(s)), Some(from), Some(to)) => new_str(s.replace(&from, &to)),
+        _ => Err(anyhow!("replace expects three strings")),
+    }
+}
+
+fn meta(args: Vec<MalVal>) -> MalRet {
+    match args.first() {
+        Some(val) => Ok(val.meta().map_or(Nil, |meta| (**meta).clone())),
+        None => bail!("meta expects a value"),
+    }
+}
+
+pub(crate) fn with_meta(args: Vec<MalVal>) -> MalRet {
+    match (args.first(), args.get(1)) {
+        (Some(val), Some(meta)) => val.with_meta(meta),
+        _ => Err(anyhow!("with-meta expects a value and a map")),
+    }
+}
+
+// (vary-meta x f args...) is x with the metadata (f (meta x) args...)
+fn vary_meta(args: Vec<MalVal>) -> MalRet {
+    match (args.first(), args.get(1)) {
+        (Some(val), Some(f)) => {
+            let mut f_args = vec![meta(vec![val.clone()])?];
+            f_args.extend_from_slice(&args[2..]);
+            val.with_meta(&apply(f, f_args)?)
+        }
+        _ => Err(anyhow!("vary-meta expects a value and a function")),
+    }
+}
+
+fn atom(args: Vec<MalVal>) -> MalRet {
+    match &args[..] {
+        [val] => {
+            allocate(1)?;
+            Ok(Atom(Rc::new(RefCell::new(val.clone()))))
+        }
+        _ => bail!("atom expects one argument"),
+    }
+}
+
+fn deref(args: Vec<MalVal>) -> MalRet {
+    match args.first() {
+        Some(Atom(a)) => Ok(a.borrow().clone()),
+        _ => bail!("deref expects an atom"),
+    }
+}
+
+fn reset(args: Vec<MalVal>) -> MalRet {
+    match (args.first(), args.get(1)) {
+        (Some(Atom(a)), Some(val)) => {
+            a.replace(val.clone());
+            Ok(val.clone())
+        }
+        _ => bail!("reset! expects an atom and a value"),
+    }
+}
+
+// (swap! a f args...) sets a to (f @a args...) and returns it
+fn swap(args: Vec<MalVal>) -> MalRet {
+    match (args.first(), args.get(1)) {
+        (Some(Atom(a)), Some(f)) => {
+            let mut f_args = vec![a.borrow().clone()];
+            f_args.extend_from_slice(&args[2..]);
+            // `f` may use the atom, so it isn't borrowed during the call
+            let val = apply(f, f_args)?;
+            a.replace(val.clone());
+            Ok(val)
+        }
+        _ => bail!("swap! expects an atom and a function"),
+    }
+}
+
+fn throw(args: Vec<MalVal>) -> MalRet {
+    Err(anyhow!(join(&args, false, " ")?))
+}
+
+macro_rules! predicate {
+    ($pat:pat $(if $guard:expr)?) => {
+        |a: Vec<MalVal>| match a.first() {
+            Some(x) => Ok(Bool(matches!(x, $pat $(if $guard)?))),
+            None => bail!("expecting one arg"),
+        }
+    };
 }
 
 //==================================================================
 
 //==================================================================
 
-pub fn ns() -> Vec<(&'static str, fn(Vec<MalVal>) -> MalRet)> {
+pub fn ns() -> Vec<(&'static str, MalFn)> {
     vec![
-        ("+", binary!(Num, Num, |x, y| x + y)),
-        ("-", binary!(Num, Num, |x, y| x - y)),
-        ("*", binary!(Num, Num, |x, y| x * y)),
-        ("/", binary!(Num, Num, |x, y| x / y)),
-        ("=", binary!(Num, Bool, |x, y| x == y)),
-        ("<", binary!(Num, Bool, |x, y| x < y)),
-        ("<=", binary!(Num, Bool, |x, y| x <= y)),
-        (">", binary!(Num, Bool, |x, y| x > y)),
-        (">=", binary!(Num, Bool, |x, y| x >= y)),
-        ("and", binary!(Bool, Bool, |x, y| x && y)),
-        ("or", binary!(Bool, Bool, |x, y| x || y)),
+        ("+", binary!(Num, Num, |x: i64, y| x.checked_add(y))),
+        ("-", binary!(Num, Num, |x: i64, y| x.checked_sub(y))),
+        ("*", binary!(Num, Num, |x: i64, y| x.checked_mul(y))),
+        ("/", binary!(Num, Num, |x: i64, y| x.checked_div(y))),
+        ("=", equal),
+        ("<", binary!(Num, Bool, |x, y| Some(x < y))),
+        ("<=", binary!(Num, Bool, |x, y| Some(x <= y))),
+        (">", binary!(Num, Bool, |x, y| Some(x > y))),
+        (">=", binary!(Num, Bool, |x, y| Some(x >= y))),
+        ("pr-str", pr_str),
+        ("str", str),
         ("prn", prn),
+        ("println", println),
+        ("pprint", pprint),
+        ("read-string", read_string),
+        ("slurp", slurp),
+        ("list", list),
+        ("list?", predicate!(List(_))),
+        ("empty?", empty),
+        ("vector", vector),
+        ("vec", vec),
+        ("vector?", predicate!(Vector(_))),
+        ("map?", predicate!(Map(_))),
+        ("hash-set", hash_set),
+        ("set", set),
+        ("set?", predicate!(Set(_))),
+        ("disj", disj),
+        ("keyword?", predicate!(Kw(_))),
+        ("nil?", predicate!(Nil)),
+        ("symbol?", predicate!(Sym(..))),
+        ("string?", predicate!(Str(_))),
+        ("number?", predicate!(Num(_))),
+        ("char?", predicate!(Char(_))),
+        ("first", first),
+        ("rest", rest),
+        ("nth", nth),
+        ("hash-map", hash_map),
+        ("assoc", assoc),
+        ("dissoc", dissoc),
+        ("get", get),
+        ("contains?", contains),
+        ("keys", keys),
+        ("vals", vals),
+        ("keyword", keyword),
+        ("char", char),
+        ("int->char", int_to_char),
+        ("char->int", char_to_int),
+        ("seq", seq),
+        ("upper-case", upper_case),
+        ("lower-case", lower_case),
+        ("trim", trim),
+        ("starts-with?", starts_with),
+        ("ends-with?", ends_with),
+        ("index-of", index_of),
+        ("replace", replace),
+        ("meta", meta),
+        ("with-meta", with_meta),
+        ("vary-meta", vary_meta),
+        ("atom", atom),
+        ("atom?", predicate!(Atom(_))),
+        ("deref", deref),
+        ("reset!", reset),
+        ("swap!", swap),
+        ("throw", throw),
         ("cons", cons),
         ("concat", concat),
+        ("conj", conj),
         ("count", count),
+        ("gensym", gensym_builtin),
+        ("gc", gc_builtin),
+        ("heap-stats", heap_stats),
+        ("time-ms", time_ms),
     ]
 }
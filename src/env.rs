@@ -1,29 +1,115 @@
 use std::{cell::RefCell, rc::Rc};
 
-use anyhow::{anyhow, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use fnv::FnvHashMap;
 
-use crate::types::{
-    MalRet,
-    MalVal::{self, List, Sym},
+use crate::{
+    analyze::{Node, Pattern},
+    exec, gc,
+    hamt::Hamt,
+    limits::allocate,
+    printer::print_readably,
+    seq::Seq,
+    symbol::{self, Symbol},
+    types::{
+        map_entries, map_key, MalRet,
+        MalVal::{self, Kw, List, Map, Nil, Str, Sym, Vector},
+    },
 };
 
+// the bindings of one env; envs made for calls and `let*`s hold a few
+// names and are scanned, the global env (or one that grows) is hashed
+#[derive(Debug, Clone)]
+enum Bindings {
+    Vec(Vec<(Symbol, MalVal)>),
+    Map(FnvHashMap<Symbol, MalVal>),
+}
+
+// a `Bindings::Vec` longer than this becomes a map
+const MAX_SCANNED: usize = 16;
+
+impl Bindings {
+    fn get(&self, key: Symbol) -> Option<&MalVal> {
+        match self {
+            Bindings::Vec(v) => v.iter().find(|(k, _)| *k == key).map(|(_, v)| v),
+            Bindings::Map(m) => m.get(&key),
+        }
+    }
+
+    // the value of `key`, found at once when it is in slot `idx`
+    fn get_at(&self, idx: usize, key: Symbol) -> Option<&MalVal> {
+        match self {
+            Bindings::Vec(v) => match v.get(idx) {
+                Some((k, val)) if *k == key => Some(val),
+                _ => self.get(key),
+            },
+            Bindings::Map(m) => m.get(&key),
+        }
+    }
+
+    fn insert(&mut self, key: Symbol, val: MalVal) {
+        match self {
+            Bindings::Vec(v) => match v.iter().position(|(k, _)| *k == key) {
+                Some(i) => v[i].1 = val,
+                None if v.len() < MAX_SCANNED => v.push((key, val)),
+                None => {
+                    let mut map: FnvHashMap<_, _> = std::mem::take(v).into_iter().collect();
+                    map.insert(key, val);
+                    *self = Bindings::Map(map);
+                }
+            },
+            Bindings::Map(m) => {
+                m.insert(key, val);
+            }
+        }
+    }
+
+    fn for_each(&self, mut f: impl FnMut(Symbol, &MalVal)) {
+        match self {
+            Bindings::Vec(v) => v.iter().for_each(|(k, v)| f(*k, v)),
+            Bindings::Map(m) => m.iter().for_each(|(k, v)| f(*k, v)),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct EnvInternal {
-    data: RefCell<FnvHashMap<String, MalVal>>,
+    data: RefCell<Bindings>,
     outer: Option<Env>,
 }
 pub type Env = Rc<EnvInternal>;
 pub fn new_env(outer: Option<Env>) -> Env {
-    Rc::new(EnvInternal {
-        data: RefCell::new(FnvHashMap::default()),
+    let data = match outer {
+        Some(_) => Bindings::Vec(vec![]),
+        None => Bindings::Map(FnvHashMap::default()),
+    };
+    let env = Rc::new(EnvInternal {
+        data: RefCell::new(data),
         outer,
-    })
+    });
+    gc::register(&env);
+    env
+}
+
+pub fn outer_env(env: &Env) -> Option<&Env> {
+    env.outer.as_ref()
+}
+
+// call `f` on each value bound in `env` itself, without cloning them
+pub fn for_each_value(env: &Env, mut f: impl FnMut(&MalVal)) {
+    env.data.borrow().for_each(|_, val| f(val));
+}
+
+// drop all the bindings of `env`
+pub fn clear_env(env: &Env) {
+    let data = std::mem::replace(&mut *env.data.borrow_mut(), Bindings::Vec(vec![]));
+    drop(data);
 }
 
 pub fn set_env(env: &Env, key: MalVal, val: MalVal) -> MalRet {
     match key {
-        Sym(sym) => {
+        Sym(sym, _) => {
+            allocate(1)?;
             env.data.borrow_mut().insert(sym, val.clone());
             Ok(val)
         }
@@ -31,58 +117,456 @@ pub fn set_env(env: &Env, key: MalVal, val: MalVal) -> MalRet {
     }
 }
 
-pub fn find_env(env: &Env, key: &String) -> Option<Env> {
-    if env.data.borrow().contains_key(key) {
-        Some(env.clone())
-    } else {
-        match env.outer.clone() {
-            Some(e) => find_env(&e, key),
-            None => None,
+pub fn find_env(env: &Env, key: Symbol) -> Option<Env> {
+    let mut env = env;
+    loop {
+        if env.data.borrow().get(key).is_some() {
+            return Some(env.clone());
         }
+        env = env.outer.as_ref()?;
+    }
+}
+
+// the outermost env of `env`
+pub fn root_env(env: &Env) -> Env {
+    match &env.outer {
+        Some(outer) => root_env(outer),
+        None => env.clone(),
     }
 }
 
+// bindings of `env` itself (not its outer envs), sorted by name
+pub fn env_bindings(env: &Env) -> Vec<(String, MalVal)> {
+    let mut binds = vec![];
+    env.data
+        .borrow()
+        .for_each(|k, v| binds.push((k.to_string(), v.clone())));
+    binds.sort_by(|a, b| a.0.cmp(&b.0));
+    binds
+}
+
 pub fn get_env(env: &Env, key: &MalVal) -> MalRet {
     match key {
-        Sym(s) => {
-            let found_env = find_env(&env, s);
-            if found_env.is_some() {
-                Ok(found_env
-                    .unwrap()
-                    .data
-                    .borrow()
-                    .get(s)
-                    .context(format!("`{}` not found", s))?
-                    .clone())
-            } else {
-                Err(anyhow!(format!("`{}` not found", s)))
+        Sym(s, _) => {
+            let mut env = env;
+            loop {
+                if let Some(val) = env.data.borrow().get(*s) {
+                    return Ok(val.clone());
+                }
+                match &env.outer {
+                    Some(outer) => env = outer,
+                    None => bail!("`{}` not found", s),
+                }
             }
         }
         _ => Err(anyhow!("invalid key type")),
     }
 }
 
-pub fn bind_env(env: &Env, mbinds: &MalVal, exprs: &Vec<MalVal>) -> Result<Env> {
+// the local `name`, which the analyzer put in slot `idx` of the env `depth`
+// levels up. Names are bound in the order the analyzer gives them slots, so
+// the slot is usually right; an error while unbound, as in the VM
+pub fn get_local(env: &Env, depth: usize, idx: usize, name: Symbol) -> MalRet {
+    let mut env = env;
+    for _ in 0..depth {
+        env = env
+            .outer
+            .as_ref()
+            .with_context(|| format!("`{}` used outside of its scope", name))?;
+    }
+    let data = env.data.borrow();
+    match data.get_at(idx, name) {
+        Some(val) => Ok(val.clone()),
+        None => bail!("`{}` used before it is bound", name),
+    }
+}
+
+pub fn bind_env(env: &Env, params: &Pattern, exprs: &[MalVal]) -> Result<Env> {
     let new_env = new_env(Some(env.clone()));
-    match mbinds {
-        List(binds) => {
-            for (i, bind) in binds.iter().enumerate() {
-                match bind {
-                    Sym(sym) if sym == "&" => {
-                        set_env(
-                            &new_env,
-                            binds[i + 1].clone(),
-                            List(Rc::new(exprs[i..].to_vec())),
-                        )?;
-                        break;
+    bind_pattern(&new_env, params, List(exprs.iter().cloned().collect()))?;
+    Ok(new_env)
+}
+
+// where the names of a pattern are bound: an env for the tree-walker, a
+// frame for the VM
+pub trait Target {
+    fn set(&mut self, name: Symbol, val: MalVal) -> Result<()>;
+    // the value of the pattern's default number `idx`, counted in the order
+    // `walk_pattern` meets them
+    fn default(&mut self, idx: usize) -> MalRet;
+}
+
+struct EnvTarget<'a> {
+    env: &'a Env,
+    defaults: &'a [Rc<Node>],
+}
+
+impl Target for EnvTarget<'_> {
+    fn set(&mut self, name: Symbol, val: MalVal) -> Result<()> {
+        set_env(self.env, Sym(name, None), val)?;
+        Ok(())
+    }
+
+    fn default(&mut self, idx: usize) -> MalRet {
+        exec(self.defaults[idx].clone(), self.env.clone())
+    }
+}
+
+// bind the symbols in `pattern` to the matching parts of `val`; shared by
+// `let*`, `fn*` and `defmacro!`
+//
+//   sym                  the whole value
+//   (p1 p2 &optional p3 (p4 default) & rest &key k (k2 default) :as v)
+//                        a list; `&key` takes `:k value` pairs after the
+//                        positional values
+//   {:keys (a b) x :x :or {a 1} :as m}
+//                        a map; missing keys bind their `:or` default or nil
+//
+// The defaults are analyzed with the pattern and run in the env being
+// bound, so each sees the names bound before it.
+pub fn bind_pattern(env: &Env, pattern: &Pattern, val: MalVal) -> Result<()> {
+    let mut target = EnvTarget {
+        env,
+        defaults: &pattern.defaults,
+    };
+    bind_into(&mut target, &pattern.form, val)
+}
+
+// bind `pattern` to `val` in `target`
+pub fn bind_into(target: &mut impl Target, pattern: &MalVal, val: MalVal) -> Result<()> {
+    Binder {
+        target,
+        next_default: 0,
+    }
+    .pattern(pattern, val)
+}
+
+// a sequential pattern, split into its sections
+#[derive(Default)]
+struct SeqPattern<'a> {
+    required: Vec<&'a MalVal>,
+    // (pattern, default)
+    optional: Vec<(&'a MalVal, Option<&'a MalVal>)>,
+    rest: Option<&'a MalVal>,
+    // (name, default)
+    keys: Vec<(&'a MalVal, Option<&'a MalVal>)>,
+    whole: Option<&'a MalVal>,
+}
+
+impl SeqPattern<'_> {
+    // the smallest and largest number of values the pattern accepts
+    fn arity(&self) -> (usize, Option<usize>) {
+        let min = self.required.len();
+        if self.rest.is_some() || !self.keys.is_empty() {
+            (min, None)
+        } else {
+            (min, Some(min + self.optional.len()))
+        }
+    }
+}
+
+fn parse_seq<'a>(pattern: &MalVal, pats: &'a [MalVal]) -> Result<SeqPattern<'a>> {
+    // `pattern` or `(pattern default)`
+    fn with_default(pat: &MalVal) -> Result<(&MalVal, Option<&MalVal>)> {
+        match pat {
+            List(l) if l.len() == 2 => Ok((&l[0], Some(&l[1]))),
+            List(_) => bail!("expected `(name default)`, got `{}`", print_readably(pat)),
+            _ => Ok((pat, None)),
+        }
+    }
+
+    let mut res = SeqPattern::default();
+    let mut section = "";
+    let mut pats = pats.iter();
+    while let Some(pat) = pats.next() {
+        match pat {
+            Sym(s, _) if *s == symbol::AMP_KEY => section = s.as_str(),
+            Kw(k) if k == "as" => {
+                res.whole = Some(pats.next().context("missing name after `:as`")?)
+            }
+            // only `&key` parameters and `:as` can follow the rest pattern
+            _ if res.rest.is_some() && section != "&key" => bail!(
+                "unexpected `{}` after the rest pattern in `{}`",
+                print_readably(pat),
+                print_readably(pattern)
+            ),
+            Sym(s, _) if *s == symbol::AMP_OPTIONAL => section = s.as_str(),
+            Sym(s, _) if *s == symbol::AMP => {
+                res.rest = Some(pats.next().context("missing pattern after `&`")?)
+            }
+            _ if section == "&optional" => res.optional.push(with_default(pat)?),
+            _ if section == "&key" => match with_default(pat)? {
+                key @ (Sym(..), _) => res.keys.push(key),
+                _ => bail!(
+                    "`&key` parameters in `{}` must be symbols",
+                    print_readably(pattern)
+                ),
+            },
+            _ => res.required.push(pat),
+        }
+    }
+    Ok(res)
+}
+
+// the smallest and largest number of args a parameter list accepts
+pub fn arity(params: &MalVal) -> Result<(usize, Option<usize>)> {
+    match params {
+        List(pats) => Ok(parse_seq(params, pats)?.arity()),
+        _ => Ok((0, None)),
+    }
+}
+
+// "2", "1-3" or "2+"
+pub fn describe_arity((min, max): (usize, Option<usize>)) -> String {
+    match max {
+        Some(max) if max == min => min.to_string(),
+        Some(max) => format!("{}-{}", min, max),
+        None => format!("{}+", min),
+    }
+}
+
+// bind `args` to the first arity clause of a function that accepts them;
+// returns the new env and the clause's body
+pub fn bind_fn<B: Clone>(env: &Env, arities: &[(Pattern, B)], args: &[MalVal]) -> Result<(Env, B)> {
+    let mut accepted = vec![];
+    for (params, body) in arities.iter() {
+        let (min, max) = arity(&params.form)?;
+        if args.len() >= min && max.is_none_or(|max| args.len() <= max) {
+            return Ok((bind_env(env, params, args)?, body.clone()));
+        }
+        accepted.push(describe_arity((min, max)));
+    }
+    bail!(
+        "wrong number of args ({}) passed to function accepting {}",
+        args.len(),
+        accepted.join(", ")
+    )
+}
+
+// a part of a pattern, as `walk_pattern` meets it
+pub enum Part<'a> {
+    Name(Symbol),
+    Default(&'a MalVal),
+}
+
+// call `f` on the names and defaults of `pattern` in the order they are
+// bound: each default just before the pattern it is for. A malformed
+// pattern is walked as far as binding it would get
+pub fn walk_pattern(pattern: &MalVal, f: &mut dyn FnMut(Part) -> Result<()>) -> Result<()> {
+    match pattern {
+        Sym(s, _) => f(Part::Name(*s))?,
+        List(pats) => {
+            let Ok(pat) = parse_seq(pattern, pats) else {
+                return Ok(());
+            };
+            for p in pat.required.iter() {
+                walk_pattern(p, f)?;
+            }
+            for (p, default) in pat.optional.iter().chain(pat.keys.iter()) {
+                if let Some(default) = default {
+                    f(Part::Default(default))?;
+                }
+                walk_pattern(p, f)?;
+            }
+            for p in pat.rest.iter().chain(pat.whole.iter()) {
+                walk_pattern(p, f)?;
+            }
+        }
+        Map(pats) => {
+            let Ok(defaults) = map_defaults(pattern, pats) else {
+                return Ok(());
+            };
+            let walk_key = |pat: &MalVal, f: &mut dyn FnMut(Part) -> Result<()>| {
+                if let Some(default) = key_default(defaults, pat) {
+                    f(Part::Default(default))?;
+                }
+                walk_pattern(pat, f)
+            };
+            for (key, pat) in map_entries(pats) {
+                match key {
+                    Kw(ref k) if k == "keys" || k == "strs" || k == "syms" => {
+                        let Ok(names) = key_names(pattern, k, &pat) else {
+                            return Ok(());
+                        };
+                        for name in names {
+                            walk_key(&Sym(name, None), f)?;
+                        }
                     }
-                    _ => {
-                        set_env(&new_env, bind.clone(), exprs[i].clone())?;
+                    Kw(ref k) if k == "as" => walk_pattern(&pat, f)?,
+                    Kw(ref k) if k == "or" => (),
+                    _ => walk_key(&key, f)?,
+                }
+            }
+        }
+        _ => (),
+    }
+    Ok(())
+}
+
+// the `:or` defaults of a map pattern
+fn map_defaults<'a>(pattern: &MalVal, pats: &'a Hamt) -> Result<Option<&'a Hamt>> {
+    match pats.get("kor") {
+        Some(Map(defaults)) => Ok(Some(defaults)),
+        Some(_) => bail!("`:or` in `{}` must be a map", print_readably(pattern)),
+        None => Ok(None),
+    }
+}
+
+// the default of `pat` in the `:or` of a map pattern
+fn key_default<'a>(defaults: Option<&'a Hamt>, pat: &MalVal) -> Option<&'a MalVal> {
+    defaults?.get(&map_key(pat).ok()?)
+}
+
+// the symbols listed by `:keys`, `:strs` or `:syms` in a map pattern
+fn key_names(pattern: &MalVal, k: &str, names: &MalVal) -> Result<Vec<Symbol>> {
+    let List(names) = names else {
+        bail!("`:{}` in `{}` must be a list", k, print_readably(pattern));
+    };
+    names
+        .iter()
+        .map(|name| match name {
+            Sym(s, _) => Ok(*s),
+            _ => bail!(
+                "`:{}` in `{}` must list symbols",
+                k,
+                print_readably(pattern)
+            ),
+        })
+        .collect()
+}
+
+struct Binder<'a, T> {
+    target: &'a mut T,
+    // the number of the next default met
+    next_default: usize,
+}
+
+impl<T: Target> Binder<'_, T> {
+    fn pattern(&mut self, pattern: &MalVal, val: MalVal) -> Result<()> {
+        match pattern {
+            Sym(s, _) => self.target.set(*s, val),
+            List(pats) => self.seq(pattern, pats, val),
+            Map(pats) => self.map(pattern, pats, val),
+            _ => bail!("invalid binding pattern `{}`", print_readably(pattern)),
+        }
+    }
+
+    // `val`, or else the value of `default`, or else nil
+    fn or_default(&mut self, val: Option<MalVal>, default: Option<&MalVal>) -> MalRet {
+        let idx = self.next_default;
+        if default.is_some() {
+            self.next_default += 1;
+        }
+        match (val, default) {
+            (Some(val), _) => Ok(val),
+            (None, Some(_)) => self.target.default(idx),
+            (None, None) => Ok(Nil),
+        }
+    }
+
+    fn seq(&mut self, pattern: &MalVal, pats: &[MalVal], val: MalVal) -> Result<()> {
+        let vals = match &val {
+            List(l) => l.clone(),
+            Vector(v) => v.iter().cloned().collect(),
+            Nil => Seq::default(),
+            _ => bail!(
+                "cannot bind `{}` to `{}`: expected a list or vector",
+                print_readably(&val),
+                print_readably(pattern)
+            ),
+        };
+
+        let pat = parse_seq(pattern, pats)?;
+        let (min, max) = pat.arity();
+        if vals.len() < min || max.is_some_and(|max| vals.len() > max) {
+            bail!(
+                "cannot bind `{}` to `{}`: expected {} values, got {}",
+                print_readably(&val),
+                print_readably(pattern),
+                describe_arity((min, max)),
+                vals.len()
+            );
+        }
+
+        let mut vals_iter = vals.iter().cloned();
+        for p in pat.required.iter() {
+            self.pattern(p, vals_iter.next().unwrap())?;
+        }
+        for (p, default) in pat.optional.iter() {
+            let val = self.or_default(vals_iter.next(), *default)?;
+            self.pattern(p, val)?;
+        }
+        let remaining: Vec<MalVal> = vals_iter.collect();
+        if !pat.keys.is_empty() {
+            if !remaining.len().is_multiple_of(2) {
+                bail!(
+                    "keyword arguments to `{}` must come in pairs",
+                    print_readably(pattern)
+                );
+            }
+            let mut given = FnvHashMap::default();
+            for kv in remaining.chunks(2) {
+                match &kv[0] {
+                    Kw(k) => given.insert(k.as_str(), kv[1].clone()),
+                    k => bail!("expected a keyword argument, got `{}`", print_readably(k)),
+                };
+            }
+            for (name, default) in pat.keys.iter() {
+                let Sym(s, _) = name else { unreachable!() };
+                let val = self.or_default(given.remove(s.as_str()), *default)?;
+                self.pattern(name, val)?;
+            }
+            if let (Some(k), None) = (given.keys().next(), pat.rest) {
+                bail!(
+                    "unknown keyword argument `:{}` for `{}`",
+                    k,
+                    print_readably(pattern)
+                );
+            }
+        }
+        if let Some(rest) = pat.rest {
+            self.pattern(rest, List(remaining.into()))?;
+        }
+        if let Some(whole) = pat.whole {
+            self.pattern(whole, val)?;
+        }
+        Ok(())
+    }
+
+    fn map(&mut self, pattern: &MalVal, pats: &Hamt, val: MalVal) -> Result<()> {
+        let map = match &val {
+            Map(m) => Some(m.clone()),
+            Nil => None,
+            _ => bail!(
+                "cannot bind `{}` to `{}`: expected a map",
+                print_readably(&val),
+                print_readably(pattern)
+            ),
+        };
+        let defaults = map_defaults(pattern, pats)?;
+        let bind_key = |binder: &mut Self, pat: &MalVal, key: &str| -> Result<()> {
+            let found = map.as_ref().and_then(|m| m.get(key).cloned());
+            let val = binder.or_default(found, key_default(defaults, pat))?;
+            binder.pattern(pat, val)
+        };
+
+        for (key, pat) in map_entries(pats) {
+            match key {
+                Kw(ref k) if k == "keys" || k == "strs" || k == "syms" => {
+                    for name in key_names(pattern, k, &pat)? {
+                        let key = match &k[..] {
+                            "keys" => Kw(name.to_string()),
+                            "strs" => Str(name.to_string()),
+                            _ => Sym(name, None),
+                        };
+                        bind_key(self, &Sym(name, None), &map_key(&key)?)?;
                     }
                 }
+                Kw(ref k) if k == "as" => self.pattern(&pat, val.clone())?,
+                Kw(ref k) if k == "or" => (),
+                _ => bind_key(self, &key, &map_key(&pat)?)?,
             }
-            Ok(new_env)
         }
-        _ => Err(anyhow!("failed to bind")),
+        Ok(())
     }
 }
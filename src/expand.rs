@@ -0,0 +1,293 @@
+// Macro expansion: one step at a time, at the head of a form, or throughout
+// a whole form.
+
+use anyhow::{anyhow, Result};
+use fnv::FnvHashSet;
+
+use crate::{
+    env::{bind_fn, find_env, get_env, Env},
+    exec,
+    hamt::Hamt,
+    is_multi_arity, profile,
+    symbol::{self, Symbol},
+    syntax_rules,
+    types::{
+        map_entries, MalRet,
+        MalVal::{self, Kw, List, MalFunc, Map, Sym, Syntax, Vector, VmFunc},
+        Meta,
+    },
+    vm,
+};
+
+fn is_macro_call(ast: &MalVal, env: &Env) -> Option<MalVal> {
+    match ast {
+        List(v) => match v.first() {
+            Some(Sym(s, _)) => match find_env(env, *s) {
+                Some(e) => match get_env(&e, &v[0]) {
+                    Ok(f @ MalFunc { is_macro: true, .. }) | Ok(f @ Syntax(_)) => Some(f),
+                    Ok(VmFunc(c)) if c.is_macro => Some(VmFunc(c)),
+                    _ => None,
+                },
+                _ => None,
+            },
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// expand `ast` once if it is a macro call
+pub fn macroexpand_1(ast: &MalVal, env: &Env) -> Result<Option<MalVal>> {
+    let expanded = match is_macro_call(ast, env) {
+        Some(
+            ref f @ MalFunc {
+                ref arities,
+                env: ref ienv,
+                ..
+            },
+        ) => {
+            let List(ref args) = ast else { unreachable!() };
+            // counted like a call, as the VM counts its macros
+            let _timer = profile::enter(f);
+            let (fn_env, body) = bind_fn(ienv, arities, &args[1..])?;
+            exec(body, fn_env)?
+        }
+        Some(Syntax(rules)) => syntax_rules::expand(&rules, ast, env)?,
+        Some(VmFunc(closure)) => {
+            let List(ref args) = ast else { unreachable!() };
+            vm::call(&closure, args[1..].to_vec())?
+        }
+        Some(_) => return Err(anyhow!("unreachable: macroexpand")),
+        None => return Ok(None),
+    };
+    Ok(Some(expanded))
+}
+
+// expand the head of `ast` until it is no longer a macro call
+pub fn macroexpand(mut ast: MalVal, env: &Env) -> Result<(bool, MalVal)> {
+    let mut was_expanded = false;
+    while let Some(expanded) = macroexpand_1(&ast, env)? {
+        ast = expanded;
+        was_expanded = true;
+    }
+    Ok((was_expanded, ast))
+}
+
+// expand macros in `ast` and in all of its subforms
+pub fn macroexpand_all(ast: MalVal, env: &Env) -> MalRet {
+    Walker {
+        env,
+        step: None,
+        shadowed: FnvHashSet::default(),
+    }
+    .walk(ast)
+}
+
+// expand the outermost, leftmost macro call in `ast` once; None when there
+// is nothing left to expand
+pub fn macroexpand_step(ast: MalVal, env: &Env) -> Result<Option<MalVal>> {
+    let mut walker = Walker {
+        env,
+        step: Some(false),
+        shadowed: FnvHashSet::default(),
+    };
+    let ast = walker.walk(ast)?;
+    Ok(if walker.step == Some(true) {
+        Some(ast)
+    } else {
+        None
+    })
+}
+
+struct Walker<'a> {
+    env: &'a Env,
+    // None: expand everything, Some(done): expand a single call
+    step: Option<bool>,
+    // names bound by enclosing `fn*`/`let*` forms, which hide macros
+    shadowed: FnvHashSet<Symbol>,
+}
+
+// the names a binding pattern binds (see `env::bind_pattern`)
+pub fn symbols_in(pattern: &MalVal, syms: &mut Vec<Symbol>) {
+    match pattern {
+        Sym(s, _) if ![symbol::AMP, symbol::AMP_OPTIONAL, symbol::AMP_KEY].contains(s) => {
+            syms.push(*s)
+        }
+        List(l) => {
+            // after `&optional` or `&key`, a list is `(pattern default)`
+            let mut defaults = false;
+            let mut pats = l.iter();
+            while let Some(p) = pats.next() {
+                match p {
+                    Sym(symbol::AMP_OPTIONAL | symbol::AMP_KEY, _) => defaults = true,
+                    Sym(symbol::AMP, _) => pats.by_ref().take(1).for_each(|p| symbols_in(p, syms)),
+                    List(l) if defaults && l.len() == 2 => symbols_in(&l[0], syms),
+                    _ => symbols_in(p, syms),
+                }
+            }
+        }
+        Map(m) => {
+            for (key, pat) in map_entries(m) {
+                match key {
+                    Kw(k) if k == "or" => (),
+                    Kw(k) if k == "keys" || k == "strs" || k == "syms" || k == "as" => {
+                        symbols_in(&pat, syms)
+                    }
+                    _ => symbols_in(&key, syms),
+                }
+            }
+        }
+        _ => (),
+    }
+}
+
+// `val`, rebuilt from a form that had `meta`, with it again
+fn keep_meta(val: MalVal, meta: Meta) -> MalRet {
+    match meta {
+        Some(meta) => val.with_meta(&meta),
+        None => Ok(val),
+    }
+}
+
+impl Walker<'_> {
+    fn walk(&mut self, mut ast: MalVal) -> MalRet {
+        if self.step == Some(true) {
+            return Ok(ast);
+        }
+        let shadowed = matches!(&ast, List(l) if matches!(l.first(), Some(Sym(s, _)) if self.shadowed.contains(s)));
+        if !shadowed {
+            match self.step {
+                None => ast = macroexpand(ast, self.env)?.1,
+                Some(_) => {
+                    if let Some(expanded) = macroexpand_1(&ast, self.env)? {
+                        self.step = Some(true);
+                        return Ok(expanded);
+                    }
+                }
+            }
+        }
+        let meta = ast.meta().cloned();
+        keep_meta(self.walk_forms(ast)?, meta)
+    }
+
+    // walk the forms in `ast`, which is expanded already
+    fn walk_forms(&mut self, ast: MalVal) -> MalRet {
+        let list = match ast {
+            List(ref list) if !list.is_empty() => list.clone(),
+            Map(ref map) => {
+                let mut res = Hamt::new();
+                for (k, v) in map.iter() {
+                    res.insert(k.clone(), self.walk(v.clone())?);
+                }
+                return Ok(Map(res));
+            }
+            Vector(ref v) => {
+                let elts = v.iter().map(|elt| self.walk(elt.clone()));
+                return Ok(Vector(elts.collect::<Result<_>>()?));
+            }
+            _ => return Ok(ast),
+        };
+        let head = match &list[0] {
+            Sym(s, _) if !self.shadowed.contains(s) => Some(*s),
+            _ => None,
+        };
+        match head {
+            Some(symbol::QUOTE | symbol::DEFINE_SYNTAX) => Ok(ast),
+            Some(symbol::QUASIQUOTE | symbol::QUASIQUOTEEXPAND) if list.len() == 2 => {
+                Ok(list![list[0].clone(), self.walk_quasi(list[1].clone())?])
+            }
+            Some(symbol::DEF | symbol::DEFMACRO) => self.walk_from(&list, 2),
+            Some(symbol::FN) if is_multi_arity(&list[1..]) => {
+                let mut res = vec![list[0].clone()];
+                for clause in list[1..].iter() {
+                    let List(clause) = clause else { unreachable!() };
+                    let mut binds = vec![];
+                    symbols_in(&clause[0], &mut binds);
+                    res.push(self.scoped(binds, |w| w.walk_from(clause, 1))?);
+                }
+                Ok(list!(res))
+            }
+            Some(symbol::FN) if list.len() >= 2 => {
+                let mut binds = vec![];
+                symbols_in(&list[1], &mut binds);
+                self.scoped(binds, |w| w.walk_from(&list, 2))
+            }
+            Some(symbol::CATCH) if list.len() >= 2 => {
+                let mut binds = vec![];
+                symbols_in(&list[1], &mut binds);
+                self.scoped(binds, |w| w.walk_from(&list, 2))
+            }
+            Some(symbol::LET | symbol::LOOP) if list.len() >= 2 => {
+                let List(ref binds) = list[1] else {
+                    return self.walk_from(&list, 2);
+                };
+                let mut res = list.to_vec();
+                let mut new_binds = vec![];
+                let mut names = vec![];
+                // each init form sees the names bound before it
+                let saved = self.shadowed.clone();
+                for bind in binds.iter() {
+                    match bind {
+                        List(b) if b.len() == 2 => {
+                            let init = self.walk(b[1].clone())?;
+                            new_binds.push(list![b[0].clone(), init]);
+                            symbols_in(&b[0], &mut names);
+                            self.shadowed.extend(names.drain(..));
+                        }
+                        _ => new_binds.push(bind.clone()),
+                    }
+                }
+                res[1] = list!(new_binds);
+                for form in res[2..].iter_mut() {
+                    *form = self.walk(form.clone())?;
+                }
+                self.shadowed = saved;
+                Ok(list!(res))
+            }
+            _ => self.walk_from(&list, 0),
+        }
+    }
+
+    // walk the elements of `list` from index `start` on
+    fn walk_from(&mut self, list: &[MalVal], start: usize) -> MalRet {
+        let mut res = list.to_vec();
+        for form in res[start.min(list.len())..].iter_mut() {
+            *form = self.walk(form.clone())?;
+        }
+        Ok(list!(res))
+    }
+
+    // inside a quasiquote template only unquoted forms are code
+    fn walk_quasi(&mut self, ast: MalVal) -> MalRet {
+        let meta = ast.meta().cloned();
+        let walked: MalRet = match ast {
+            List(ref list)
+                if list.len() == 2
+                    && matches!(&list[0], Sym(s, _) if *s == symbol::UNQUOTE || *s == symbol::SPLICE_UNQUOTE) =>
+            {
+                Ok(list![list[0].clone(), self.walk(list[1].clone())?])
+            }
+            List(list) => {
+                let mut res = vec![];
+                for form in list.iter() {
+                    res.push(self.walk_quasi(form.clone())?);
+                }
+                Ok(list!(res))
+            }
+            Vector(v) => {
+                let elts = v.iter().map(|elt| self.walk_quasi(elt.clone()));
+                Ok(Vector(elts.collect::<Result<_>>()?))
+            }
+            _ => Ok(ast),
+        };
+        keep_meta(walked?, meta)
+    }
+
+    fn scoped(&mut self, binds: Vec<Symbol>, f: impl FnOnce(&mut Self) -> MalRet) -> MalRet {
+        let saved = self.shadowed.clone();
+        self.shadowed.extend(binds);
+        let ret = f(self);
+        self.shadowed = saved;
+        ret
+    }
+}
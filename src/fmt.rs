@@ -0,0 +1,201 @@
+// `lisp_rs fmt`: rewrite source files in the canonical layout, keeping
+// comments and blank lines.
+
+use std::{
+    fs,
+    io::{self, Read},
+};
+
+use anyhow::{bail, Context, Result};
+
+use crate::{
+    pretty::{special_form, DEFAULT_WIDTH},
+    reader::{read_all, read_str, read_syntax, Syntax},
+    types::MalVal::Sym,
+};
+
+const USAGE: &str = "usage: lisp_rs fmt [--check] [--width <n>] [<file>...]";
+
+// run the `fmt` subcommand and return the process exit code
+pub fn run(args: &[String]) -> Result<i32> {
+    let mut check = false;
+    let mut width = DEFAULT_WIDTH;
+    let mut files = vec![];
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match &arg[..] {
+            "--check" => check = true,
+            "--width" => width = args.next().context(USAGE)?.parse()?,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return Ok(0);
+            }
+            _ if arg.starts_with("--") => bail!("unknown option `{}`\n{}", arg, USAGE),
+            _ => files.push(arg),
+        }
+    }
+
+    if files.is_empty() {
+        let mut src = String::new();
+        io::stdin().read_to_string(&mut src)?;
+        let formatted = format_source(&src, width)?;
+        if check {
+            return Ok(if formatted == src { 0 } else { 1 });
+        }
+        print!("{}", formatted);
+        return Ok(0);
+    }
+
+    let mut code = 0;
+    for file in files {
+        let src = fs::read_to_string(file).with_context(|| format!("{}: failed to read", file))?;
+        let formatted = format_source(&src, width).with_context(|| file.to_owned())?;
+        if formatted == src {
+            continue;
+        }
+        if check {
+            println!("would reformat {}", file);
+            code = 1;
+        } else {
+            fs::write(file, formatted).with_context(|| format!("{}: failed to write", file))?;
+        }
+    }
+    Ok(code)
+}
+
+// format `src`, failing rather than returning output that reads back to
+// different forms
+pub fn format_source(src: &str, width: usize) -> Result<String> {
+    let mut out = String::new();
+    for node in read_syntax(src)? {
+        match node {
+            Syntax::Blank => out.push('\n'),
+            Syntax::Comment {
+                text,
+                trailing: true,
+            } if !out.is_empty() => {
+                out.pop();
+                out.push(' ');
+                out.push_str(&text);
+                out.push('\n');
+            }
+            node => {
+                write_node(&node, width, &mut out);
+                out.push('\n');
+            }
+        }
+    }
+
+    if read_all(src)? != read_all(&out)? {
+        bail!("formatted output does not read back to the same forms");
+    }
+    Ok(out)
+}
+
+fn column(out: &str) -> usize {
+    match out.rfind('\n') {
+        Some(i) => out[i + 1..].chars().count(),
+        None => out.chars().count(),
+    }
+}
+
+fn newline(indent: usize, out: &mut String) {
+    out.push('\n');
+    out.extend(std::iter::repeat_n(' ', indent));
+}
+
+// the children of a collection, and the text around them
+fn collection(node: &Syntax) -> Option<(&[Syntax], (&str, &str))> {
+    match node {
+        Syntax::List(children) => Some((children, ("(", ")"))),
+        Syntax::Vector(children) => Some((children, ("[", "]"))),
+        Syntax::Map(children) => Some((children, ("{", "}"))),
+        Syntax::Set(children) => Some((children, ("#{", "}"))),
+        _ => None,
+    }
+}
+
+// the node on a single line, unless it contains comments or blank lines
+fn flat(node: &Syntax) -> Option<String> {
+    match node {
+        Syntax::Atom(text) => Some(text.clone()),
+        Syntax::Prefixed(prefix, inner) => Some(format!("{}{}", prefix, flat(inner)?)),
+        Syntax::Comment { .. } | Syntax::Blank => None,
+        _ => {
+            let (children, (open, close)) = collection(node)?;
+            let children = children.iter().map(flat).collect::<Option<Vec<_>>>()?;
+            Some(format!("{}{}{}", open, children.join(" "), close))
+        }
+    }
+}
+
+fn write_node(node: &Syntax, width: usize, out: &mut String) {
+    match node {
+        Syntax::Atom(text) | Syntax::Comment { text, .. } => out.push_str(text),
+        Syntax::Prefixed(prefix, inner) => {
+            out.push_str(prefix);
+            write_node(inner, width, out);
+        }
+        Syntax::Blank => (),
+        _ => {
+            if let Some((children, delimiters)) = collection(node) {
+                write_list(node, children, delimiters, width, out)
+            }
+        }
+    }
+}
+
+fn write_list(
+    node: &Syntax,
+    children: &[Syntax],
+    (open, close): (&str, &str),
+    width: usize,
+    out: &mut String,
+) {
+    let col = column(out);
+    if let Some(flat) = flat(node) {
+        if col + flat.chars().count() <= width {
+            out.push_str(&flat);
+            return;
+        }
+    }
+
+    // same layout rules as the pretty-printer; other collections than lists
+    // are data, with their elements lined up under the first
+    let (inline, indent) = match children.first() {
+        _ if open != "(" => (0, open.len()),
+        Some(Syntax::Atom(head)) if matches!(read_str(head), Ok(Sym(..))) => {
+            match special_form(head) {
+                Some(layout) => layout,
+                None => (1, head.chars().count() + 2),
+            }
+        }
+        _ => (0, 1),
+    };
+
+    out.push_str(open);
+    // once a comment or blank line shows up, everything after it goes on
+    // its own line
+    let mut broken = false;
+    let mut after_comment = false;
+    for (i, child) in children.iter().enumerate() {
+        match child {
+            Syntax::Blank => out.push('\n'),
+            Syntax::Comment { trailing: true, .. } if i > 0 => out.push(' '),
+            _ if i == 0 => (),
+            _ if i <= inline && !broken => out.push(' '),
+            // maps: one key/value pair per line
+            _ if open == "{" && i % 2 == 1 && !broken => out.push(' '),
+            _ => newline(col + indent, out),
+        }
+        if !matches!(child, Syntax::Blank) {
+            write_node(child, width, out);
+        }
+        after_comment = matches!(child, Syntax::Comment { .. });
+        broken |= after_comment || matches!(child, Syntax::Blank);
+    }
+    if after_comment {
+        newline(col + indent, out);
+    }
+    out.push_str(close);
+}
@@ -0,0 +1,326 @@
+// Cycle collection. Envs are reference counted, and a function stored in the
+// env it closes over (any recursive `defn`) is a cycle that `Rc` alone never
+// frees. Every env (and VM frame) is registered here, and `collect` finds the
+// envs that are only kept alive by such cycles and clears their bindings,
+// which breaks the cycles.
+//
+// Garbage is found by trial deletion: for every node (env, frame, list, map,
+// function, syntax-rules macro or atom) reachable from a registered env, the
+// references from other nodes are subtracted from its strong count. Nodes
+// with references left are held from outside the heap (the Rust stack, the
+// REPL, an embedder) and are live, as is everything they reach.
+
+use std::{
+    cell::RefCell,
+    rc::{Rc, Weak},
+};
+
+use fnv::FnvHashMap;
+
+use crate::{
+    analyze,
+    env::{clear_env, for_each_value, outer_env, Env, EnvInternal},
+    hamt, seq,
+    syntax_rules::SyntaxRules,
+    types::MalVal::{self, Atom, List, MalFunc, Map, Set, Syntax, Vector, VmFunc},
+    vector,
+    vm::{Closure, Frame},
+};
+
+// don't bother collecting until there are this many envs
+const MIN_THRESHOLD: usize = 10_000;
+
+// a registered env or VM frame
+enum Scope {
+    Env(Weak<EnvInternal>),
+    Frame(Weak<Frame>),
+}
+
+impl Scope {
+    fn is_alive(&self) -> bool {
+        match self {
+            Scope::Env(env) => env.strong_count() > 0,
+            Scope::Frame(frame) => frame.strong_count() > 0,
+        }
+    }
+
+    fn upgrade(&self) -> Option<Node> {
+        match self {
+            Scope::Env(env) => env.upgrade().map(Node::Env),
+            Scope::Frame(frame) => frame.upgrade().map(Node::Frame),
+        }
+    }
+}
+
+struct Heap {
+    envs: Vec<Scope>,
+    // prune or collect when `envs` grows this long
+    threshold: usize,
+    collections: u64,
+    freed: u64,
+}
+
+thread_local! {
+    static HEAP: RefCell<Heap> = const {
+        RefCell::new(Heap {
+            envs: vec![],
+            threshold: MIN_THRESHOLD,
+            collections: 0,
+            freed: 0,
+        })
+    };
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct HeapStats {
+    // envs and frames still allocated, including garbage not collected yet
+    pub envs: usize,
+    pub collections: u64,
+    // envs and frames freed by collections so far
+    pub freed: u64,
+}
+
+pub fn stats() -> HeapStats {
+    HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.envs.retain(Scope::is_alive);
+        HeapStats {
+            envs: heap.envs.len(),
+            collections: heap.collections,
+            freed: heap.freed,
+        }
+    })
+}
+
+pub fn register(env: &Env) {
+    push(Scope::Env(Rc::downgrade(env)));
+}
+
+pub fn register_frame(frame: &Rc<Frame>) {
+    push(Scope::Frame(Rc::downgrade(frame)));
+}
+
+fn push(scope: Scope) {
+    let due = HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.envs.push(scope);
+        if heap.envs.len() < heap.threshold {
+            return false;
+        }
+        // most envs die with the call that made them; only collect when
+        // pruning those doesn't help
+        heap.envs.retain(Scope::is_alive);
+        if 2 * heap.envs.len() >= heap.threshold {
+            return true;
+        }
+        heap.threshold = MIN_THRESHOLD.max(2 * heap.envs.len());
+        false
+    });
+    if due {
+        collect();
+    }
+}
+
+#[derive(Clone)]
+enum Node {
+    Env(Env),
+    Frame(Rc<Frame>),
+    Closure(Rc<Closure>),
+    List(Rc<seq::Buffer>),
+    Map(Rc<hamt::Node>),
+    Vector(Rc<vector::Node>),
+    Tail(Rc<Vec<MalVal>>),
+    Func(Rc<Vec<(analyze::Pattern, Rc<analyze::Node>)>>),
+    Syntax(Rc<SyntaxRules>),
+    Meta(Rc<MalVal>),
+    Atom(Rc<RefCell<MalVal>>),
+}
+
+impl Node {
+    fn key(&self) -> usize {
+        match self {
+            Node::Env(rc) => Rc::as_ptr(rc) as usize,
+            Node::Frame(rc) => Rc::as_ptr(rc) as usize,
+            Node::Closure(rc) => Rc::as_ptr(rc) as usize,
+            Node::List(rc) => Rc::as_ptr(rc) as usize,
+            Node::Map(rc) => Rc::as_ptr(rc) as usize,
+            Node::Vector(rc) => Rc::as_ptr(rc) as usize,
+            Node::Tail(rc) => Rc::as_ptr(rc) as usize,
+            Node::Func(rc) => Rc::as_ptr(rc) as usize,
+            Node::Syntax(rc) => Rc::as_ptr(rc) as usize,
+            Node::Meta(rc) => Rc::as_ptr(rc) as usize,
+            Node::Atom(rc) => Rc::as_ptr(rc) as usize,
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match self {
+            Node::Env(rc) => Rc::strong_count(rc),
+            Node::Frame(rc) => Rc::strong_count(rc),
+            Node::Closure(rc) => Rc::strong_count(rc),
+            Node::List(rc) => Rc::strong_count(rc),
+            Node::Map(rc) => Rc::strong_count(rc),
+            Node::Vector(rc) => Rc::strong_count(rc),
+            Node::Tail(rc) => Rc::strong_count(rc),
+            Node::Func(rc) => Rc::strong_count(rc),
+            Node::Syntax(rc) => Rc::strong_count(rc),
+            Node::Meta(rc) => Rc::strong_count(rc),
+            Node::Atom(rc) => Rc::strong_count(rc),
+        }
+    }
+
+    // the nodes this one references
+    fn children(&self, out: &mut Vec<Node>) {
+        match self {
+            Node::Env(env) => {
+                out.extend(outer_env(env).cloned().map(Node::Env));
+                for_each_value(env, |val| value_node(val, out));
+            }
+            Node::Frame(frame) => {
+                out.extend(frame.parent().cloned().map(Node::Frame));
+                frame.for_each_value(|val| value_node(val, out));
+            }
+            Node::Closure(closure) => {
+                out.extend(closure.frame().cloned().map(Node::Frame));
+                out.push(Node::Env(closure.env().clone()));
+                out.extend(closure.meta().clone().map(Node::Meta));
+            }
+            Node::List(buf) => {
+                buf.for_each_buffer(|next| out.push(Node::List(next.clone())));
+                buf.for_each_value(|val| value_node(val, out));
+            }
+            Node::Map(node) => {
+                let mut nodes = vec![];
+                node.for_each_child(
+                    |n| nodes.push(Node::Map(n.clone())),
+                    |val| value_node(val, out),
+                );
+                out.extend(nodes);
+            }
+            Node::Vector(node) => {
+                let mut nodes = vec![];
+                node.for_each_child(
+                    |n| nodes.push(Node::Vector(n.clone())),
+                    |val| value_node(val, out),
+                );
+                out.extend(nodes);
+            }
+            Node::Tail(vals) => vals.iter().for_each(|val| value_node(val, out)),
+            Node::Func(arities) => {
+                // analyzed bodies and defaults aren't traversed. Their
+                // constants are
+                // mostly plain data, but a `syntax-rules` template can
+                // insert a closure as `(quote <closure>)`: an env only
+                // reachable through one of those is never collected
+                for (params, _) in arities.iter() {
+                    value_node(&params.form, out);
+                }
+            }
+            Node::Syntax(rules) => out.push(Node::Env(rules.env().clone())),
+            Node::Meta(meta) => value_node(meta, out),
+            Node::Atom(atom) => value_node(&atom.borrow(), out),
+        }
+    }
+}
+
+// the nodes `val` holds a reference to; a VM closure's metadata is the
+// closure's own
+fn value_node(val: &MalVal, out: &mut Vec<Node>) {
+    if !matches!(val, VmFunc(_)) {
+        out.extend(val.meta().cloned().map(Node::Meta));
+    }
+    match val {
+        List(l) => out.push(Node::List(l.buffer().clone())),
+        Map(m) | Set(m) => out.push(Node::Map(m.root().clone())),
+        Vector(v) => {
+            out.push(Node::Vector(v.root().clone()));
+            out.push(Node::Tail(v.tail().clone()));
+        }
+        MalFunc { arities, env, .. } => {
+            out.push(Node::Func(arities.clone()));
+            out.push(Node::Env(env.clone()));
+        }
+        Syntax(rules) => out.push(Node::Syntax(rules.clone())),
+        VmFunc(closure) => out.push(Node::Closure(closure.clone())),
+        Atom(atom) => out.push(Node::Atom(atom.clone())),
+        _ => (),
+    }
+}
+
+// free the envs and frames only reachable from reference cycles; returns
+// how many
+pub fn collect() -> usize {
+    let roots: Vec<Node> = HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.envs.retain(Scope::is_alive);
+        heap.envs.iter().filter_map(Scope::upgrade).collect()
+    });
+
+    // every node reachable from an env, holding one reference to each
+    let mut nodes: Vec<Node> = vec![];
+    let mut index: FnvHashMap<usize, usize> = FnvHashMap::default();
+    let mut edges: Vec<Vec<usize>> = vec![];
+    let mut pending: Vec<Node> = roots;
+    while let Some(node) = pending.pop() {
+        if index.contains_key(&node.key()) {
+            continue;
+        }
+        index.insert(node.key(), nodes.len());
+        nodes.push(node);
+        edges.push(vec![]);
+        let mut children = vec![];
+        nodes.last().unwrap().children(&mut children);
+        // resolved into `edges` once every node is known
+        pending.extend(children.iter().cloned());
+        *edges.last_mut().unwrap() = children.iter().map(Node::key).collect();
+    }
+    let edges: Vec<Vec<usize>> = edges
+        .into_iter()
+        .map(|keys| keys.iter().map(|key| index[key]).collect())
+        .collect();
+
+    // references from outside the heap: all but ours and the other nodes'
+    let mut external: Vec<isize> = nodes
+        .iter()
+        .map(|n| n.strong_count() as isize - 1)
+        .collect();
+    for children in edges.iter() {
+        for &child in children.iter() {
+            external[child] -= 1;
+        }
+    }
+
+    let mut live = vec![false; nodes.len()];
+    let mut pending: Vec<usize> = (0..nodes.len()).filter(|&i| external[i] > 0).collect();
+    while let Some(i) = pending.pop() {
+        if !live[i] {
+            live[i] = true;
+            pending.extend(edges[i].iter().filter(|&&child| !live[child]));
+        }
+    }
+
+    let garbage: Vec<&Node> = nodes
+        .iter()
+        .zip(live.iter())
+        .filter(|(node, &live)| !live && matches!(node, Node::Env(_) | Node::Frame(_)))
+        .map(|(node, _)| node)
+        .collect();
+    let freed = garbage.len();
+    for node in garbage {
+        match node {
+            Node::Env(env) => clear_env(env),
+            Node::Frame(frame) => frame.clear(),
+            _ => (),
+        }
+    }
+    drop(nodes);
+
+    HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.envs.retain(Scope::is_alive);
+        heap.threshold = MIN_THRESHOLD.max(2 * heap.envs.len());
+        heap.collections += 1;
+        heap.freed += freed as u64;
+    });
+    freed
+}
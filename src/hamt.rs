@@ -0,0 +1,286 @@
+// Persistent hash maps, as hash array mapped tries. Each level of the trie
+// indexes 5 bits of the key's hash; a map is the root of a trie whose nodes
+// are shared with the maps it was built from, and an update copies only the
+// nodes on the path to the key, so `assoc` and `dissoc` are O(log n).
+// Updating a map nothing else shares is done in place.
+
+use std::{fmt, hash::Hasher, rc::Rc};
+
+use fnv::FnvHasher;
+
+use crate::types::{MalVal, Meta};
+
+const BITS: u32 = 5;
+const MASK: u64 = (1 << BITS) - 1;
+
+#[derive(Clone)]
+pub enum Node {
+    // the entries whose hash has each bit of `bitmap` next, in order
+    Branch { bitmap: u32, entries: Vec<Entry> },
+    // leaves whose keys have the same hash, once it has been used up
+    Collision(Vec<Entry>),
+}
+
+#[derive(Clone)]
+pub enum Entry {
+    Leaf(String, MalVal),
+    Node(Rc<Node>),
+}
+
+#[derive(Clone)]
+pub struct Hamt {
+    root: Rc<Node>,
+    len: usize,
+    // kept by `insert` and `remove`
+    meta: Meta,
+}
+
+fn hash(key: &str) -> u64 {
+    let mut hasher = FnvHasher::default();
+    hasher.write(key.as_bytes());
+    hasher.finish()
+}
+
+fn empty_node(shift: u32) -> Node {
+    if shift >= u64::BITS {
+        Node::Collision(vec![])
+    } else {
+        Node::Branch {
+            bitmap: 0,
+            entries: vec![],
+        }
+    }
+}
+
+impl Node {
+    // call `nodes` on each child node and `values` on each value held here
+    pub fn for_each_child(
+        &self,
+        mut nodes: impl FnMut(&Rc<Node>),
+        mut values: impl FnMut(&MalVal),
+    ) {
+        let (Node::Branch { entries, .. } | Node::Collision(entries)) = self;
+        for entry in entries.iter() {
+            match entry {
+                Entry::Leaf(_, val) => values(val),
+                Entry::Node(node) => nodes(node),
+            }
+        }
+    }
+
+    fn get(&self, hash: u64, shift: u32, key: &str) -> Option<&MalVal> {
+        let entry = match self {
+            Node::Branch { bitmap, entries } => {
+                let bit = 1 << ((hash >> shift) & MASK);
+                if bitmap & bit == 0 {
+                    return None;
+                }
+                &entries[(bitmap & (bit - 1)).count_ones() as usize]
+            }
+            Node::Collision(entries) => {
+                return entries.iter().find_map(|entry| match entry {
+                    Entry::Leaf(k, v) if k == key => Some(v),
+                    _ => None,
+                })
+            }
+        };
+        match entry {
+            Entry::Leaf(k, v) if k == key => Some(v),
+            Entry::Leaf(..) => None,
+            Entry::Node(node) => node.get(hash, shift + BITS, key),
+        }
+    }
+}
+
+// set `key` in the trie at `node`; returns whether it is a new key
+fn insert(node: &mut Rc<Node>, hash: u64, shift: u32, key: String, val: MalVal) -> bool {
+    match Rc::make_mut(node) {
+        Node::Branch { bitmap, entries } => {
+            let bit = 1 << ((hash >> shift) & MASK);
+            let idx = (*bitmap & (bit - 1)).count_ones() as usize;
+            if *bitmap & bit == 0 {
+                *bitmap |= bit;
+                entries.insert(idx, Entry::Leaf(key, val));
+                return true;
+            }
+            match &mut entries[idx] {
+                Entry::Leaf(k, v) if *k == key => {
+                    *v = val;
+                    false
+                }
+                Entry::Node(child) => insert(child, hash, shift + BITS, key, val),
+                leaf => {
+                    // two keys share this slot: push both a level down
+                    let placeholder = Entry::Leaf(String::new(), MalVal::Nil);
+                    let Entry::Leaf(k, v) = std::mem::replace(leaf, placeholder) else {
+                        unreachable!()
+                    };
+                    let mut child = Rc::new(empty_node(shift + BITS));
+                    insert(&mut child, self::hash(&k), shift + BITS, k, v);
+                    insert(&mut child, hash, shift + BITS, key, val);
+                    *leaf = Entry::Node(child);
+                    true
+                }
+            }
+        }
+        Node::Collision(entries) => {
+            for entry in entries.iter_mut() {
+                if let Entry::Leaf(k, v) = entry {
+                    if *k == key {
+                        *v = val;
+                        return false;
+                    }
+                }
+            }
+            entries.push(Entry::Leaf(key, val));
+            true
+        }
+    }
+}
+
+// remove `key`, which is in the trie at `node`
+fn remove(node: &mut Rc<Node>, hash: u64, shift: u32, key: &str) {
+    match Rc::make_mut(node) {
+        Node::Branch { bitmap, entries } => {
+            let bit = 1 << ((hash >> shift) & MASK);
+            let idx = (*bitmap & (bit - 1)).count_ones() as usize;
+            let Entry::Node(child) = &mut entries[idx] else {
+                *bitmap &= !bit;
+                entries.remove(idx);
+                return;
+            };
+            remove(child, hash, shift + BITS, key);
+            // a node left with a single leaf is replaced by the leaf
+            let (Node::Branch { entries: left, .. } | Node::Collision(left)) = &**child;
+            if let [leaf @ Entry::Leaf(..)] = &left[..] {
+                entries[idx] = leaf.clone();
+            }
+        }
+        Node::Collision(entries) => {
+            entries.retain(|entry| !matches!(entry, Entry::Leaf(k, _) if k == key));
+        }
+    }
+}
+
+impl Hamt {
+    pub fn new() -> Hamt {
+        Hamt {
+            root: Rc::new(empty_node(0)),
+            len: 0,
+            meta: None,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, key: &str) -> Option<&MalVal> {
+        self.root.get(hash(key), 0, key)
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+
+    pub fn insert(&mut self, key: String, val: MalVal) {
+        if insert(&mut self.root, hash(&key), 0, key, val) {
+            self.len += 1;
+        }
+    }
+
+    pub fn remove(&mut self, key: &str) {
+        if self.contains_key(key) {
+            remove(&mut self.root, hash(key), 0, key);
+            self.len -= 1;
+        }
+    }
+
+    pub fn iter(&self) -> Iter<'_> {
+        let (Node::Branch { entries, .. } | Node::Collision(entries)) = &*self.root;
+        Iter {
+            stack: vec![entries.iter()],
+        }
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &String> {
+        self.iter().map(|(k, _)| k)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &MalVal> {
+        self.iter().map(|(_, v)| v)
+    }
+
+    // the root of the trie, for the cycle collector
+    pub fn root(&self) -> &Rc<Node> {
+        &self.root
+    }
+
+    pub fn meta(&self) -> &Meta {
+        &self.meta
+    }
+
+    // the same map with `meta` instead of its metadata
+    pub fn with_meta(&self, meta: Meta) -> Hamt {
+        Hamt {
+            meta,
+            ..self.clone()
+        }
+    }
+}
+
+impl Default for Hamt {
+    fn default() -> Hamt {
+        Hamt::new()
+    }
+}
+
+pub struct Iter<'a> {
+    stack: Vec<std::slice::Iter<'a, Entry>>,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (&'a String, &'a MalVal);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.stack.last_mut()?.next() {
+                Some(Entry::Leaf(k, v)) => return Some((k, v)),
+                Some(Entry::Node(node)) => {
+                    let (Node::Branch { entries, .. } | Node::Collision(entries)) = &**node;
+                    self.stack.push(entries.iter());
+                }
+                None => {
+                    self.stack.pop();
+                }
+            }
+        }
+    }
+}
+
+impl FromIterator<(String, MalVal)> for Hamt {
+    fn from_iter<I: IntoIterator<Item = (String, MalVal)>>(iter: I) -> Hamt {
+        let mut map = Hamt::new();
+        for (k, v) in iter {
+            map.insert(k, v);
+        }
+        map
+    }
+}
+
+// metadata doesn't take part in equality
+impl PartialEq for Hamt {
+    fn eq(&self, other: &Hamt) -> bool {
+        self.len == other.len && self.iter().all(|(k, v)| other.get(k) == Some(v))
+    }
+}
+
+impl fmt::Debug for Hamt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
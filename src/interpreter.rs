@@ -0,0 +1,62 @@
+// The embedding API: an interpreter with its own global env, whose
+// evaluations can be bounded when running untrusted scripts.
+
+use anyhow::Result;
+
+use crate::{
+    env::Env,
+    eval_with, init_env,
+    limits::{with_limits, Limits},
+    reader::read_all,
+    types::{MalRet, MalVal::Nil},
+    Backend,
+};
+
+pub struct Interpreter {
+    env: Env,
+    backend: Backend,
+}
+
+impl Interpreter {
+    // an interpreter with the core builtins and the prelude
+    pub fn new() -> Result<Self> {
+        Self::with_prelude(true)
+    }
+
+    pub fn with_prelude(prelude: bool) -> Result<Self> {
+        Self::with_options(prelude, Backend::default())
+    }
+
+    // an interpreter with the prelude, evaluating with `backend`
+    pub fn with_backend(backend: Backend) -> Result<Self> {
+        Self::with_options(true, backend)
+    }
+
+    pub fn with_options(prelude: bool, backend: Backend) -> Result<Self> {
+        Ok(Interpreter {
+            env: init_env(prelude, backend)?,
+            backend,
+        })
+    }
+
+    pub fn env(&self) -> &Env {
+        &self.env
+    }
+
+    // evaluate every form in `src`, returning the value of the last one
+    pub fn eval_str(&self, src: &str) -> MalRet {
+        self.eval_with_limits(src, &Limits::default())
+    }
+
+    // like `eval_str`, failing once any of `limits` is exceeded; the limits
+    // cover all the forms in `src` together
+    pub fn eval_with_limits(&self, src: &str, limits: &Limits) -> MalRet {
+        with_limits(limits, || {
+            let mut ret = Nil;
+            for form in read_all(src)? {
+                ret = eval_with(self.backend, form, self.env.clone())?;
+            }
+            Ok(ret)
+        })
+    }
+}
@@ -0,0 +1,350 @@
+// The lexer: splits source text into tokens that borrow from it, each with
+// its kind and span. Comments are tokens too, for the formatter; the reader
+// skips them.
+//
+// Malformed input is reported rather than skipped: an unterminated string,
+// block comment or `|` in a name, an invalid escape in a string, or a control character
+// outside of one.
+// After an error the lexer carries on from the end of the bad text.
+
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    // `(`
+    Open,
+    // `)`
+    Close,
+    // `[`
+    OpenBracket,
+    // `]`
+    CloseBracket,
+    // `{`
+    OpenBrace,
+    // `#{`, which starts a set and is closed by `}`
+    OpenSet,
+    // `}`
+    CloseBrace,
+    // `'`
+    Quote,
+    // `` ` ``
+    Quasiquote,
+    // `~`
+    Unquote,
+    // `~@`
+    SpliceUnquote,
+    // `^`
+    Caret,
+    // `@`
+    At,
+    // a string literal, quotes included, with valid escapes
+    Str,
+    // a character literal: `\` and a character or the name of one
+    Char,
+    // `;` to the end of the line, the line break excluded; a block comment
+    // `#| ... |#`, which nests; or a `#!` line at the very start
+    Comment,
+    // `#_`, which comments out the next form
+    DatumComment,
+    // a number, keyword or symbol; `|...|` sections of a name may hold
+    // any character
+    Atom,
+}
+
+// byte offsets into the source
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    // 1-based line and column (in chars) of the start of the span in `src`
+    pub fn line_col(&self, src: &str) -> (usize, usize) {
+        let before = &src[..self.start];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        (
+            before.matches('\n').count() + 1,
+            before[line_start..].chars().count() + 1,
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Token<'a> {
+    pub kind: TokenKind,
+    pub text: &'a str,
+    pub span: Span,
+}
+
+// a syntax error and where it is
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub message: String,
+    pub span: Span,
+}
+
+impl Diagnostic {
+    pub fn new(span: Span, message: String) -> Self {
+        Diagnostic { message, span }
+    }
+
+    // `line:col: message`, for the source the span is in
+    pub fn describe(&self, src: &str) -> String {
+        let (line, col) = self.span.line_col(src);
+        format!("{}:{}: {}", line, col, self.message)
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Diagnostic {}
+
+pub struct Lexer<'a> {
+    src: &'a str,
+    pos: usize,
+}
+
+// ends an atom
+fn is_delimiter(b: u8) -> bool {
+    matches!(
+        b,
+        b'(' | b')' | b'[' | b']' | b'{' | b'}' | b'\'' | b'"' | b'`' | b',' | b';' | b' '
+    ) || b.is_ascii_control()
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(src: &'a str) -> Self {
+        Lexer { src, pos: 0 }
+    }
+
+    fn char_at(&self, pos: usize) -> Option<char> {
+        self.src[pos..].chars().next()
+    }
+
+    fn skip_whitespace(&mut self) {
+        let bytes = self.src.as_bytes();
+        while let Some(&b) = bytes.get(self.pos) {
+            if b == b',' || b.is_ascii_whitespace() {
+                self.pos += 1;
+            } else if b >= 0x80 && self.char_at(self.pos).is_some_and(char::is_whitespace) {
+                self.pos += self.char_at(self.pos).unwrap().len_utf8();
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn token(&mut self, kind: TokenKind, start: usize) -> Option<Result<Token<'a>, Diagnostic>> {
+        Some(Ok(Token {
+            kind,
+            text: &self.src[start..self.pos],
+            span: Span {
+                start,
+                end: self.pos,
+            },
+        }))
+    }
+
+    fn error(
+        &self,
+        message: String,
+        start: usize,
+        end: usize,
+    ) -> Option<Result<Token<'a>, Diagnostic>> {
+        Some(Err(Diagnostic::new(Span { start, end }, message)))
+    }
+
+    fn line_comment(&mut self, start: usize) -> Option<Result<Token<'a>, Diagnostic>> {
+        self.pos = self.src[start..]
+            .find('\n')
+            .map_or(self.src.len(), |i| start + i);
+        self.token(TokenKind::Comment, start)
+    }
+
+    fn block_comment(&mut self, start: usize) -> Option<Result<Token<'a>, Diagnostic>> {
+        let bytes = self.src.as_bytes();
+        let mut depth = 0;
+        while self.pos + 1 < bytes.len() {
+            match &bytes[self.pos..self.pos + 2] {
+                b"#|" => depth += 1,
+                b"|#" => depth -= 1,
+                _ => {
+                    self.pos += 1;
+                    continue;
+                }
+            }
+            self.pos += 2;
+            if depth == 0 {
+                return self.token(TokenKind::Comment, start);
+            }
+        }
+        self.pos = bytes.len();
+        self.error("unterminated block comment".to_owned(), start, self.pos)
+    }
+
+    // the rest of a character literal, from after its backslash: one
+    // character, or a name made of letters and digits
+    fn char(&mut self, start: usize) -> Option<Result<Token<'a>, Diagnostic>> {
+        match self.char_at(self.pos) {
+            Some(c) if !c.is_whitespace() && !c.is_control() => {
+                self.pos += c.len_utf8();
+                if c.is_alphanumeric() {
+                    while let Some(c) = self.char_at(self.pos).filter(|c| c.is_alphanumeric()) {
+                        self.pos += c.len_utf8();
+                    }
+                }
+                self.token(TokenKind::Char, start)
+            }
+            _ => self.error(
+                "expected a character after `\\`".to_owned(),
+                start,
+                self.pos,
+            ),
+        }
+    }
+
+    // a `|...|` section of an atom starting at `start`, in which `\` escapes
+    // the next character
+    fn bars(&mut self, start: usize) -> Result<(), Diagnostic> {
+        let bytes = self.src.as_bytes();
+        self.pos += 1;
+        loop {
+            match bytes.get(self.pos) {
+                None => {
+                    let span = Span {
+                        start,
+                        end: self.pos,
+                    };
+                    return Err(Diagnostic::new(span, "unterminated `|` in name".to_owned()));
+                }
+                Some(b'|') => {
+                    self.pos += 1;
+                    return Ok(());
+                }
+                Some(b'\\') if self.pos + 1 < bytes.len() => self.pos += 2,
+                Some(_) => self.pos += 1,
+            }
+        }
+    }
+
+    // the rest of a string literal, from after its opening quote
+    fn string(&mut self, start: usize) -> Option<Result<Token<'a>, Diagnostic>> {
+        let bytes = self.src.as_bytes();
+        let mut invalid = None;
+        loop {
+            match bytes.get(self.pos) {
+                None => {
+                    return self.error("unterminated string".to_owned(), start, self.pos);
+                }
+                Some(b'"') => {
+                    self.pos += 1;
+                    break;
+                }
+                Some(b'\\') => {
+                    let escape = self.pos;
+                    self.pos += 1;
+                    match self.char_at(self.pos) {
+                        Some('n' | '\\' | '"') => self.pos += 1,
+                        Some(c) => {
+                            self.pos += c.len_utf8();
+                            invalid.get_or_insert((escape, self.pos));
+                        }
+                        None => (),
+                    }
+                }
+                Some(_) => self.pos += 1,
+            }
+        }
+        match invalid {
+            Some((from, to)) => self.error(
+                format!("invalid escape `{}` in string", &self.src[from..to]),
+                from,
+                to,
+            ),
+            None => self.token(TokenKind::Str, start),
+        }
+    }
+}
+
+impl<'a> Iterator for Lexer<'a> {
+    type Item = Result<Token<'a>, Diagnostic>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        use TokenKind::*;
+
+        self.skip_whitespace();
+        let bytes = self.src.as_bytes();
+        let start = self.pos;
+        let kind = match *bytes.get(start)? {
+            b'(' => Open,
+            b')' => Close,
+            b'[' => OpenBracket,
+            b']' => CloseBracket,
+            b'{' => OpenBrace,
+            b'}' => CloseBrace,
+            b'\'' => Quote,
+            b'`' => Quasiquote,
+            b'~' if bytes.get(start + 1) == Some(&b'@') => {
+                self.pos += 2;
+                return self.token(SpliceUnquote, start);
+            }
+            b'~' => Unquote,
+            b'^' => Caret,
+            b'@' => At,
+            b'"' => {
+                self.pos += 1;
+                return self.string(start);
+            }
+            b'\\' => {
+                self.pos += 1;
+                return self.char(start);
+            }
+            b';' => return self.line_comment(start),
+            b'#' if start == 0 && bytes.get(1) == Some(&b'!') => return self.line_comment(start),
+            b'#' if bytes.get(start + 1) == Some(&b'|') => return self.block_comment(start),
+            b'#' if bytes.get(start + 1) == Some(&b'_') => {
+                self.pos += 2;
+                return self.token(DatumComment, start);
+            }
+            b'#' if bytes.get(start + 1) == Some(&b'{') => {
+                self.pos += 2;
+                return self.token(OpenSet, start);
+            }
+            _ => {
+                let c = self.char_at(start).unwrap();
+                if c.is_control() {
+                    self.pos += c.len_utf8();
+                    return self.error(format!("unexpected character {:?}", c), start, self.pos);
+                }
+                while let Some(&b) = bytes.get(self.pos) {
+                    if is_delimiter(b) {
+                        break;
+                    }
+                    match b {
+                        b'|' => {
+                            if let Err(err) = self.bars(start) {
+                                return Some(Err(err));
+                            }
+                        }
+                        0..0x80 => self.pos += 1,
+                        _ => {
+                            let c = self.char_at(self.pos).unwrap();
+                            if c.is_whitespace() || c.is_control() {
+                                break;
+                            }
+                            self.pos += c.len_utf8();
+                        }
+                    }
+                }
+                return self.token(Atom, start);
+            }
+        };
+        self.pos += 1;
+        self.token(kind, start)
+    }
+}
@@ -0,0 +1,374 @@
+use std::rc::Rc;
+
+use anyhow::{anyhow, bail, Context, Result};
+use env::{bind_fn, bind_pattern, get_env, get_local, new_env, set_env};
+use fnv::FnvHashMap;
+use types::{
+    MalRet,
+    MalVal::{
+        self, Bool, List, MalFunc, Map, Nil, RustFunc, Set, Str, Sym, Syntax, Vector, VmFunc,
+    },
+};
+
+use crate::analyze::{analyze, Loop, Node};
+use crate::core::gensym;
+use crate::env::Env;
+use crate::expand::{macroexpand, macroexpand_1, macroexpand_all};
+use crate::hamt::Hamt;
+use crate::limits::allocate;
+use crate::reader::read_all;
+use crate::stack::Depth;
+use crate::symbol::Symbol;
+#[macro_use]
+pub mod types;
+pub mod analyze;
+pub mod check;
+pub mod core;
+pub mod env;
+pub mod expand;
+pub mod fmt;
+pub mod gc;
+pub mod hamt;
+pub mod interpreter;
+pub mod lexer;
+pub mod limits;
+pub mod pretty;
+pub mod printer;
+pub mod profile;
+pub mod reader;
+pub mod repl;
+pub mod sandbox;
+pub mod seq;
+pub mod stack;
+pub mod symbol;
+pub mod syntax_rules;
+pub mod vector;
+pub mod vm;
+
+fn qq_iter(elts: &[MalVal], gensyms: &mut FnvHashMap<String, MalVal>) -> MalVal {
+    let mut acc = list![];
+    for elt in elts.iter().rev() {
+        if let List(v) = elt {
+            if v.len() == 2 {
+                if let Sym(s, _) = v[0] {
+                    if s == symbol::SPLICE_UNQUOTE {
+                        acc = list![Sym(symbol::CONCAT, None), v[1].clone(), acc];
+                        continue;
+                    }
+                }
+            }
+        }
+        acc = list![Sym(symbol::CONS, None), qq(elt, gensyms), acc];
+    }
+    acc
+}
+
+// `gensyms` maps each auto-gensym `x#` of one quasiquote form to its fresh symbol
+fn qq(ast: &MalVal, gensyms: &mut FnvHashMap<String, MalVal>) -> MalVal {
+    match ast {
+        List(v) => {
+            if v.len() == 2 {
+                if let Sym(s, _) = v[0] {
+                    if s == symbol::UNQUOTE {
+                        return v[1].clone();
+                    }
+                }
+            }
+            qq_iter(v, gensyms)
+        }
+        Vector(v) => {
+            let elts: Vec<MalVal> = v.iter().cloned().collect();
+            list![Sym(symbol::VEC, None), qq_iter(&elts, gensyms)]
+        }
+        Sym(s, _)
+            if s.gensym_number().is_none() && s.as_str().len() > 1 && s.as_str().ends_with('#') =>
+        {
+            let name = s.as_str();
+            let sym = gensyms.entry(name.to_owned()).or_insert_with(|| {
+                gensym(Symbol::new(&format!("{}__auto", &name[..name.len() - 1])))
+            });
+            list![Sym(symbol::QUOTE, None), sym.clone()]
+        }
+        Sym(..) | Map(_) | Set(_) => list![Sym(symbol::QUOTE, None), ast.clone()],
+        _ => ast.clone(),
+    }
+}
+
+pub(crate) fn quasiquote(ast: &MalVal) -> MalVal {
+    qq(ast, &mut FnvHashMap::default())
+}
+
+// whether the arguments of a `fn*` form are `(params body...)` clauses
+pub fn is_multi_arity(form: &[MalVal]) -> bool {
+    !form.is_empty()
+        && form
+            .iter()
+            .all(|f| matches!(f, List(l) if matches!(l.first(), Some(List(_)))))
+}
+
+// several forms in a body are wrapped in an implicit `do`
+pub(crate) fn implicit_do(body: &[MalVal]) -> MalVal {
+    match body {
+        [] => Nil,
+        [body] => body.clone(),
+        _ => {
+            let mut forms = vec![Sym(symbol::DO, None)];
+            forms.extend_from_slice(body);
+            list!(forms)
+        }
+    }
+}
+
+// the (params, body) clauses of `(fn* params body...)` or
+// `(fn* (params body...) (params body...) ...)`
+pub(crate) fn fn_arities(form: &[MalVal]) -> Result<Vec<(MalVal, MalVal)>> {
+    let clause = |params: &MalVal, body: &[MalVal]| (params.clone(), implicit_do(body));
+
+    match form {
+        [] => bail!("fn* expects a parameter list"),
+        [params, body @ ..] if !is_multi_arity(form) => Ok(vec![clause(params, body)]),
+        clauses => Ok(clauses
+            .iter()
+            .map(|c| {
+                let List(c) = c else { unreachable!() };
+                clause(&c[0], &c[1..])
+            })
+            .collect()),
+    }
+}
+
+// where `recur` jumps to: the innermost `loop` whose body is in tail position
+struct Recur {
+    target: Rc<Loop>,
+    // env the `loop` form was evaluated in
+    env: Env,
+}
+
+// evaluate `ast`; a top-level `do` is analyzed one form at a time, so the
+// macros it defines are seen by the forms after them
+pub fn eval(ast: MalVal, env: Env) -> MalRet {
+    if let List(list) = &ast {
+        if let [Sym(symbol::DO, _), forms @ ..] = &list[..] {
+            let mut ret = Nil;
+            for form in forms {
+                ret = eval(form.clone(), env.clone())?;
+            }
+            return Ok(ret);
+        }
+    }
+    exec(analyze(&ast, &env)?, env)
+}
+
+// run an analyzed form
+// TCOのためにmutで受け取る
+pub fn exec(mut node: Rc<Node>, mut env: Env) -> MalRet {
+    let _depth = Depth::enter()?;
+    let mut recur: Option<Recur> = None;
+    // the function whose body is running, for the profiler
+    let mut timer = None;
+
+    loop {
+        limits::tick()?;
+        node = match &*node {
+            Node::Const(val) => return Ok(val.clone()),
+            Node::Var(name) => return get_env(&env, &Sym(*name, None)),
+            Node::Local(name, depth, idx) => return get_local(&env, *depth, *idx, *name),
+            Node::Map(entries) => {
+                allocate(entries.len())?;
+                let mut res = Hamt::new();
+                for (k, v) in entries.iter() {
+                    res.insert(k.clone(), exec(v.clone(), env.clone())?);
+                }
+                return Ok(Map(res));
+            }
+            Node::Def(name, val) => {
+                let val = exec(val.clone(), env.clone())?;
+                profile::name(&val, *name);
+                return set_env(&env, Sym(*name, None), val);
+            }
+            Node::DefMacro(name, val) => {
+                let val = exec(val.clone(), env.clone())?;
+                profile::name(&val, *name);
+                // bound where `def!` would bind it, not in the function's env
+                return match val {
+                    MalFunc {
+                        arities,
+                        env: ienv,
+                        meta,
+                        ..
+                    } => set_env(
+                        &env,
+                        Sym(*name, None),
+                        MalFunc {
+                            arities,
+                            is_macro: true,
+                            env: ienv,
+                            meta,
+                        },
+                    ),
+                    VmFunc(c) => set_env(&env, Sym(*name, None), VmFunc(Rc::new(c.as_macro()))),
+                    _ => Err(anyhow!("set macro on non-func")),
+                };
+            }
+            Node::Let(_, binds, body) => {
+                env = new_env(Some(env.clone()));
+                for (pattern, val) in binds.iter() {
+                    let val = exec(val.clone(), env.clone())?;
+                    bind_pattern(&env, pattern, val)?;
+                }
+                body.clone()
+            }
+            Node::Loop(target) => {
+                let loop_env = new_env(Some(env.clone()));
+                for (pattern, val) in target.binds.iter() {
+                    let val = exec(val.clone(), loop_env.clone())?;
+                    bind_pattern(&loop_env, pattern, val)?;
+                }
+                recur = Some(Recur {
+                    target: target.clone(),
+                    env,
+                });
+                env = loop_env;
+                target.body.clone()
+            }
+            Node::Recur(args) => {
+                // the analyzer only allows `recur` in tail position of a `loop`
+                let Some(Recur { target, env: outer }) = &recur else {
+                    unreachable!("`recur` outside of a `loop`")
+                };
+                let mut vals = vec![];
+                for arg in args.iter() {
+                    vals.push(exec(arg.clone(), env.clone())?);
+                }
+                env = new_env(Some(outer.clone()));
+                for ((pattern, _), val) in target.binds.iter().zip(vals) {
+                    bind_pattern(&env, pattern, val)?;
+                }
+                target.body.clone()
+            }
+            // (try* expr (catch* e handler)): `e` is bound to the error message
+            Node::Try(body, pattern, _, handler) => match exec(body.clone(), env.clone()) {
+                Err(err) => {
+                    env = new_env(Some(env.clone()));
+                    bind_pattern(&env, pattern, Str(format!("{:#}", err)))?;
+                    handler.clone()
+                }
+                ret => return ret,
+            },
+            Node::Do(forms) => {
+                let (last, forms) = forms.split_last().unwrap();
+                for form in forms {
+                    exec(form.clone(), env.clone())?;
+                }
+                last.clone()
+            }
+            Node::If(test, then, other) => match exec(test.clone(), env.clone())? {
+                Bool(false) | Nil => other.clone(),
+                _ => then.clone(),
+            },
+            Node::Fn(arities) => {
+                return Ok(MalFunc {
+                    arities: arities.clone(),
+                    is_macro: false,
+                    env,
+                    meta: None,
+                })
+            }
+            Node::DefineSyntax(name, rules) => {
+                let rules = syntax_rules::parse(rules, &env)?;
+                return set_env(&env, Sym(*name, None), Syntax(Rc::new(rules)));
+            }
+            Node::Expand(head, form) => {
+                return match *head {
+                    symbol::MACROEXPAND => Ok(macroexpand(form.clone(), &env)?.1),
+                    symbol::MACROEXPAND_1 => {
+                        Ok(macroexpand_1(form, &env)?.unwrap_or_else(|| form.clone()))
+                    }
+                    _ => macroexpand_all(form.clone(), &env),
+                };
+            }
+            Node::Call(func, args) => {
+                allocate(args.len() + 1)?;
+                let func = exec(func.clone(), env.clone())?;
+                let mut vals = vec![];
+                for arg in args.iter() {
+                    vals.push(exec(arg.clone(), env.clone())?);
+                }
+                if !matches!(func, VmFunc(_)) {
+                    drop(timer.take());
+                    timer = profile::enter(&func);
+                }
+                match func {
+                    RustFunc(f, _) => return f(vals),
+                    MalFunc {
+                        arities, env: ienv, ..
+                    } => {
+                        let body;
+                        (env, body) = bind_fn(&ienv, &arities, &vals)?;
+                        recur = None;
+                        body
+                    }
+                    VmFunc(closure) => return vm::call(&closure, vals),
+                    _ => bail!("apttempt to call non-function"),
+                }
+            }
+        };
+    }
+}
+
+// call the function `f` with `args`
+pub fn apply(f: &MalVal, args: Vec<MalVal>) -> MalRet {
+    match f {
+        RustFunc(f, _) => f(args),
+        MalFunc { arities, env, .. } => {
+            let _timer = profile::enter(f);
+            let (env, body) = bind_fn(env, arities, &args)?;
+            exec(body, env)
+        }
+        VmFunc(closure) => vm::call(closure, args),
+        _ => bail!("apttempt to call non-function"),
+    }
+}
+
+// how forms are evaluated: by walking the tree with `eval`, or compiled to
+// bytecode and run by the VM
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Backend {
+    #[default]
+    TreeWalker,
+    Vm,
+}
+
+pub fn eval_with(backend: Backend, ast: MalVal, env: Env) -> MalRet {
+    match backend {
+        Backend::TreeWalker => eval(ast, env),
+        Backend::Vm => vm::eval_vm(ast, env),
+    }
+}
+
+const PRELUDE: &str = include_str!("prelude.mal");
+
+// the global env, with the prelude evaluated by `backend`
+pub fn init_env(prelude: bool, backend: Backend) -> Result<Env> {
+    let global_env = new_env(None);
+    let core_funcs = core::ns();
+    for (sym, func) in core_funcs {
+        profile::name(&RustFunc(func, None), Symbol::new(sym));
+        set_env(
+            &global_env,
+            Sym(Symbol::new(sym), None),
+            RustFunc(func, None),
+        )?;
+    }
+    if prelude {
+        load_prelude(&global_env, backend)?;
+    }
+    Ok(global_env)
+}
+
+pub fn load_prelude(env: &Env, backend: Backend) -> Result<()> {
+    for form in read_all(PRELUDE)? {
+        eval_with(backend, form, env.clone()).context("failed to load the prelude")?;
+    }
+    Ok(())
+}
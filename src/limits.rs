@@ -0,0 +1,152 @@
+// Budgets for a running evaluation: fuel (steps of the evaluator),
+// allocations, a wall-clock deadline, and interruption from another thread
+// or Ctrl-C.
+// `eval` calls `tick` on every step, so even a non-terminating program
+// returns an error once a budget runs out.
+
+use std::{
+    cell::Cell,
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Result};
+
+use crate::stack::{max_depth, set_max_depth};
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Limits {
+    // steps of the evaluator
+    pub fuel: Option<u64>,
+    // cells allocated: see `allocate`
+    pub allocations: Option<u64>,
+    pub timeout: Option<Duration>,
+    // see `stack`; the current limit when unset
+    pub max_depth: Option<usize>,
+}
+
+#[derive(Clone, Copy, Default)]
+struct Budget {
+    fuel: Option<u64>,
+    allocations: Option<u64>,
+    deadline: Option<Instant>,
+    steps: u64,
+}
+
+// reading the clock on every step is too slow
+const CLOCK_INTERVAL: u64 = 1024;
+
+thread_local! {
+    static BUDGET: Cell<Budget> = Cell::new(Budget::default());
+    // whether `with_limits` is running
+    static ACTIVE: Cell<bool> = const { Cell::new(false) };
+}
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+// make the current evaluation fail with "interrupted"; safe to call from a
+// signal handler or another thread
+pub fn interrupt() {
+    INTERRUPTED.store(true, Ordering::Relaxed);
+}
+
+// account for one step of the evaluator
+pub fn tick() -> Result<()> {
+    if INTERRUPTED.load(Ordering::Relaxed) {
+        bail!("interrupted");
+    }
+    let mut budget = BUDGET.with(|b| b.get());
+    budget.steps += 1;
+    match budget.fuel {
+        Some(0) => bail!("out of fuel after {} steps", budget.steps - 1),
+        Some(fuel) => budget.fuel = Some(fuel - 1),
+        None => (),
+    }
+    if budget.steps.is_multiple_of(CLOCK_INTERVAL)
+        && budget.deadline.is_some_and(|d| Instant::now() >= d)
+    {
+        bail!("evaluation timed out");
+    }
+    BUDGET.with(|b| b.set(budget));
+    Ok(())
+}
+
+// account for `cells` newly allocated cells: one per element of a list or
+// map, per env binding and per 8 bytes of a string
+pub fn allocate(cells: usize) -> Result<()> {
+    let mut budget = BUDGET.with(|b| b.get());
+    if let Some(left) = budget.allocations {
+        match left.checked_sub(cells as u64) {
+            Some(left) => budget.allocations = Some(left),
+            None => bail!("allocation limit exceeded"),
+        }
+        BUDGET.with(|b| b.set(budget));
+    }
+    Ok(())
+}
+
+// the stricter of two limits, either of which may be unset
+fn min_limit<T: Ord>(a: Option<T>, b: Option<T>) -> Option<T> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+// run `f` under `limits`. Inside another call, the outer limits still
+// apply, and what `f` uses is charged to them.
+pub fn with_limits<T>(limits: &Limits, f: impl FnOnce() -> Result<T>) -> Result<T> {
+    let nested = ACTIVE.with(|a| a.replace(true));
+    if !nested {
+        INTERRUPTED.store(false, Ordering::Relaxed);
+    }
+    let saved = BUDGET.with(|b| b.get());
+    let saved_depth = max_depth();
+    let start = Budget {
+        fuel: min_limit(limits.fuel, saved.fuel.filter(|_| nested)),
+        allocations: min_limit(limits.allocations, saved.allocations.filter(|_| nested)),
+        deadline: min_limit(
+            limits.timeout.map(|t| Instant::now() + t),
+            saved.deadline.filter(|_| nested),
+        ),
+        steps: 0,
+    };
+    BUDGET.with(|b| b.set(start));
+    match limits.max_depth {
+        Some(depth) if nested => set_max_depth(depth.min(saved_depth)),
+        Some(depth) => set_max_depth(depth),
+        None => (),
+    }
+    let ret = f();
+    let end = BUDGET.with(|b| b.get());
+    let used = |start: Option<u64>, end: Option<u64>| start.zip(end).map_or(0, |(s, e)| s - e);
+    let mut outer = saved;
+    if nested {
+        outer.fuel = saved.fuel.map(|fuel| fuel - used(start.fuel, end.fuel));
+        outer.allocations = saved
+            .allocations
+            .map(|left| left - used(start.allocations, end.allocations));
+        outer.steps += end.steps;
+    }
+    BUDGET.with(|b| b.set(outer));
+    ACTIVE.with(|a| a.set(nested));
+    set_max_depth(saved_depth);
+    ret
+}
+
+// Ctrl-C cancels the running evaluation instead of killing the process
+#[cfg(unix)]
+pub fn install_sigint_handler() {
+    extern "C" fn on_sigint(_: libc::c_int) {
+        interrupt();
+    }
+    unsafe {
+        libc::signal(
+            libc::SIGINT,
+            on_sigint as extern "C" fn(libc::c_int) as libc::sighandler_t,
+        );
+    }
+}
+
+#[cfg(not(unix))]
+pub fn install_sigint_handler() {}
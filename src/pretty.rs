@@ -0,0 +1,247 @@
+// Wadler-style pretty printer: values are turned into a `Doc` whose groups
+// are laid out flat when they fit in the remaining width and broken across
+// lines otherwise.
+
+use anyhow::Result;
+
+use crate::{
+    hamt::Hamt,
+    printer::{print_with, PrintConfig},
+    stack::Depth,
+    types::{
+        map_entries, set_elements,
+        MalVal::{self, List, Map, Set, Sym, Vector},
+    },
+};
+
+pub const DEFAULT_WIDTH: usize = 80;
+
+enum Doc {
+    Text(String),
+    // a space when flat, a newline plus indentation when broken
+    Line,
+    Nest(usize, Box<Doc>),
+    // indentation of the inner doc is relative to the current column
+    Align(Box<Doc>),
+    Concat(Vec<Doc>),
+    Group(Box<Doc>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Flat,
+    Break,
+}
+
+// fails like `print_with` on lists nested deeper than the stack allows
+pub fn pprint(mal: &MalVal, config: &PrintConfig, width: usize) -> Result<String> {
+    Ok(render(&to_doc(mal, config, 0)?, width))
+}
+
+// forms whose first `n` arguments stay on the head line, with the rest
+// indented as a body
+pub fn special_form(name: &str) -> Option<(usize, usize)> {
+    match name {
+        "def!" | "let*" | "fn*" | "loop" | "defmacro!" | "define-syntax" => Some((1, 2)),
+        "when" | "unless" | "if-let" | "when-let" | "case" | "doto" => Some((1, 2)),
+        "let" | "dotimes" | "doseq" | "while" => Some((1, 2)),
+        "defn" | "defmacro" => Some((2, 2)),
+        "do" | "quasiquote" => Some((0, 2)),
+        "if" => Some((1, 4)),
+        _ => None,
+    }
+}
+
+fn to_doc(mal: &MalVal, config: &PrintConfig, depth: usize) -> Result<Doc> {
+    let list = match mal {
+        List(list) if !list.is_empty() => list,
+        Map(map) if !map.is_empty() => return map_doc(map, config, depth),
+        Vector(v) if !v.is_empty() => return fill_doc(v, ("[", "]"), config, depth),
+        Set(s) if !s.is_empty() => return fill_doc(&set_elements(s), ("#{", "}"), config, depth),
+        _ => return Ok(Doc::Text(print_with(mal, config)?)),
+    };
+    let _depth = Depth::enter()?;
+    if config.too_deep(depth) {
+        return Ok(Doc::Text("...".to_owned()));
+    }
+
+    let mut elts = vec![];
+    for (i, x) in list.iter().enumerate() {
+        if config.max_length.is_some_and(|max| i >= max) {
+            elts.push(Doc::Text("...".to_owned()));
+            break;
+        }
+        elts.push(to_doc(x, config, depth + 1)?);
+    }
+
+    // number of arguments kept on the head line, and the indentation of the rest
+    let is_data = !matches!(list[0], Sym(..));
+    let (inline, indent) = match &list[0] {
+        Sym(s, _) => match special_form(&s.name()) {
+            Some(layout) => layout,
+            // align the remaining arguments with the first one
+            None => (1, s.name().chars().count() + 2),
+        },
+        _ => (0, 1),
+    };
+
+    let mut elts = elts.into_iter();
+    let mut head = vec![Doc::Text("(".to_owned())];
+    head.extend(elts.next());
+    let mut body = vec![];
+    for (i, elt) in elts.enumerate() {
+        if i < inline {
+            head.push(Doc::Text(" ".to_owned()));
+            head.push(elt);
+        } else if is_data {
+            // fill data lists: break only before elements that don't fit
+            body.push(Doc::Group(Box::new(Doc::Concat(vec![Doc::Line, elt]))));
+        } else {
+            body.push(Doc::Line);
+            body.push(elt);
+        }
+    }
+    head.push(Doc::Nest(indent, Box::new(Doc::Concat(body))));
+    head.push(Doc::Text(")".to_owned()));
+    Ok(Doc::Group(Box::new(Doc::Align(Box::new(Doc::Concat(
+        head,
+    ))))))
+}
+
+// vectors and sets are filled like data lists, their elements lined up
+// under the first
+fn fill_doc<'a>(
+    elts: impl IntoIterator<Item = &'a MalVal>,
+    (open, close): (&str, &str),
+    config: &PrintConfig,
+    depth: usize,
+) -> Result<Doc> {
+    let _depth = Depth::enter()?;
+    if config.too_deep(depth) {
+        return Ok(Doc::Text("...".to_owned()));
+    }
+    let mut body = vec![];
+    for (i, x) in elts.into_iter().enumerate() {
+        let elided = config.max_length.is_some_and(|max| i >= max);
+        let elt = match elided {
+            true => Doc::Text("...".to_owned()),
+            false => to_doc(x, config, depth + 1)?,
+        };
+        match i {
+            0 => body.push(elt),
+            _ => body.push(Doc::Group(Box::new(Doc::Concat(vec![Doc::Line, elt])))),
+        }
+        if elided {
+            break;
+        }
+    }
+    Ok(Doc::Group(Box::new(Doc::Align(Box::new(Doc::Concat(
+        vec![
+            Doc::Text(open.to_owned()),
+            Doc::Nest(open.len(), Box::new(Doc::Concat(body))),
+            Doc::Text(close.to_owned()),
+        ],
+    ))))))
+}
+
+// one key/value pair per line when the map doesn't fit
+fn map_doc(map: &Hamt, config: &PrintConfig, depth: usize) -> Result<Doc> {
+    let _depth = Depth::enter()?;
+    if config.too_deep(depth) {
+        return Ok(Doc::Text("...".to_owned()));
+    }
+    let mut body = vec![];
+    for (i, (k, v)) in map_entries(map).iter().enumerate() {
+        if i > 0 {
+            body.push(Doc::Line);
+        }
+        if config.max_length.is_some_and(|max| i >= max) {
+            body.push(Doc::Text("...".to_owned()));
+            break;
+        }
+        body.push(to_doc(k, config, depth + 1)?);
+        body.push(Doc::Text(" ".to_owned()));
+        body.push(to_doc(v, config, depth + 1)?);
+    }
+    Ok(Doc::Group(Box::new(Doc::Align(Box::new(Doc::Concat(
+        vec![
+            Doc::Text("{".to_owned()),
+            Doc::Nest(1, Box::new(Doc::Concat(body))),
+            Doc::Text("}".to_owned()),
+        ],
+    ))))))
+}
+
+fn render(doc: &Doc, width: usize) -> String {
+    let mut out = String::new();
+    let mut col = 0;
+    let mut stack = vec![(0, Mode::Break, doc)];
+    while let Some((indent, mode, doc)) = stack.pop() {
+        match doc {
+            Doc::Text(s) => {
+                out.push_str(s);
+                col += s.chars().count();
+            }
+            Doc::Line => {
+                if mode == Mode::Flat {
+                    out.push(' ');
+                    col += 1;
+                } else {
+                    out.push('\n');
+                    out.extend(std::iter::repeat_n(' ', indent));
+                    col = indent;
+                }
+            }
+            Doc::Nest(i, d) => stack.push((indent + i, mode, d)),
+            Doc::Align(d) => stack.push((col, mode, d)),
+            Doc::Concat(docs) => {
+                for d in docs.iter().rev() {
+                    stack.push((indent, mode, d));
+                }
+            }
+            Doc::Group(d) => {
+                let rem = width as isize - col as isize;
+                let mode = if mode == Mode::Flat || fits(rem, (indent, Mode::Flat, d), &stack) {
+                    Mode::Flat
+                } else {
+                    Mode::Break
+                };
+                stack.push((indent, mode, d));
+            }
+        }
+    }
+    out
+}
+
+// does `first`, followed by the rest of the document up to its next line
+// break, fit into `rem` columns?
+fn fits(mut rem: isize, first: (usize, Mode, &Doc), rest: &[(usize, Mode, &Doc)]) -> bool {
+    let mut stack = vec![first];
+    let mut rest = rest.iter().rev();
+    while rem >= 0 {
+        let (indent, mode, doc) = match stack.pop() {
+            Some(item) => item,
+            None => match rest.next() {
+                Some(&item) => item,
+                None => return true,
+            },
+        };
+        match doc {
+            Doc::Text(s) => rem -= s.chars().count() as isize,
+            Doc::Line => {
+                if mode == Mode::Break {
+                    return true;
+                }
+                rem -= 1;
+            }
+            Doc::Nest(i, d) => stack.push((indent + i, mode, d)),
+            Doc::Concat(docs) => {
+                for d in docs.iter().rev() {
+                    stack.push((indent, mode, d));
+                }
+            }
+            Doc::Align(d) | Doc::Group(d) => stack.push((indent, mode, d)),
+        }
+    }
+    false
+}
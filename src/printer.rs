@@ -1,27 +1,262 @@
-use crate::types::MalVal;
+use anyhow::Result;
 
-pub fn print(mal: &MalVal) -> String {
+use crate::{
+    stack::Depth,
+    types::{map_entries, set_elements, MalVal},
+};
+
+// how `nil`, `true` and `false` are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LiteralStyle {
+    // `nil`, `true`, `false`: what the reader understands
+    Canonical,
+    // `NIL`, `TRUE`, `FALSE`: the historical output, not readable
+    Legacy,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PrintConfig {
+    pub style: LiteralStyle,
+    // quote and escape strings so the output can be read back
+    pub readably: bool,
+    // lists nested deeper than this are printed as `...`
+    pub max_depth: Option<usize>,
+    // only this many elements of a list, or entries of a map, are printed,
+    // followed by `...`
+    pub max_length: Option<usize>,
+}
+
+impl Default for PrintConfig {
+    fn default() -> Self {
+        PrintConfig {
+            style: LiteralStyle::Canonical,
+            readably: true,
+            max_depth: None,
+            max_length: None,
+        }
+    }
+}
+
+impl PrintConfig {
+    // whether lists at `depth` are elided
+    pub fn too_deep(&self, depth: usize) -> bool {
+        self.max_depth.is_some_and(|max| depth >= max)
+    }
+}
+
+// print `mal` so that the output can be read back by `read_str`. This and
+// `print_display` are for messages and can't fail: lists nested deeper than
+// the stack allows are printed as `...`
+pub fn print_readably(mal: &MalVal) -> String {
+    print_eliding(mal, &PrintConfig::default())
+}
+
+// print `mal` for humans: strings are written raw, without quotes
+pub fn print_display(mal: &MalVal) -> String {
+    print_eliding(
+        mal,
+        &PrintConfig {
+            readably: false,
+            ..PrintConfig::default()
+        },
+    )
+}
+
+fn print_eliding(mal: &MalVal, config: &PrintConfig) -> String {
+    let mut res = String::new();
+    // can't fail when eliding
+    let _ = pr_str(mal, config, true, 0, &mut res);
+    res
+}
+
+// print `mal` with `config`; lists nested deeper than the stack allows
+// fail with the same "stack depth exceeded" error as `eval`
+pub fn print_with(mal: &MalVal, config: &PrintConfig) -> Result<String> {
+    let mut res = String::new();
+    pr_str(mal, config, false, 0, &mut res)?;
+    Ok(res)
+}
+
+fn pr_str(
+    mal: &MalVal,
+    config: &PrintConfig,
+    elide: bool,
+    depth: usize,
+    res: &mut String,
+) -> Result<()> {
+    let legacy = config.style == LiteralStyle::Legacy;
     match mal {
-        MalVal::Nil => "NIL".to_owned(),
-        MalVal::Bool(b) => {
-            if *b {
-                "TRUE".to_owned()
+        MalVal::Nil => res.push_str(if legacy { "NIL" } else { "nil" }),
+        MalVal::Bool(b) => res.push_str(match (*b, legacy) {
+            (true, false) => "true",
+            (false, false) => "false",
+            (true, true) => "TRUE",
+            (false, true) => "FALSE",
+        }),
+        MalVal::Num(n) => res.push_str(&n.to_string()),
+        MalVal::Str(s) => {
+            if config.readably {
+                escape(s, res)
             } else {
-                "FALSE".to_owned()
+                res.push_str(s)
+            }
+        }
+        MalVal::Char(c) => {
+            if config.readably {
+                char_literal(*c, res)
+            } else {
+                res.push(*c)
+            }
+        }
+        MalVal::Kw(s) => {
+            res.push(':');
+            match config.readably && !is_plain_name(s, true) {
+                true => barred(s, res),
+                false => res.push_str(s),
+            }
+        }
+        // a gensym can't be read back as itself, so it isn't written as
+        // something that reads
+        MalVal::Sym(s, _) if config.readably && s.gensym_number().is_some() => {
+            res.push_str(&format!("#<gensym {}>", s.name()))
+        }
+        MalVal::Sym(s, _) => {
+            let name = s.name();
+            match config.readably && !is_plain_name(&name, false) {
+                true => barred(&name, res),
+                false => res.push_str(&name),
             }
         }
-        MalVal::Num(n) => n.to_string(),
-        MalVal::Sym(s) => s.clone(),
-        MalVal::List(l) => {
-            format!(
-                "({})",
-                l.iter()
-                    .map(|x| print(x))
-                    .collect::<Vec<String>>()
-                    .join(" ")
-            )
-        }
-        MalVal::RustFunc(_) => "<builtin func>".to_owned(),
-        MalVal::MalFunc { .. } => "<func>".to_owned(),
+        MalVal::List(l) => return pr_seq(l, 1, ("(", ")"), config, elide, depth, res),
+        MalVal::Vector(v) => return pr_seq(v, 1, ("[", "]"), config, elide, depth, res),
+        MalVal::Map(m) => {
+            let elts: Vec<_> = map_entries(m)
+                .into_iter()
+                .flat_map(|(k, v)| [k, v])
+                .collect();
+            return pr_seq(&elts, 2, ("{", "}"), config, elide, depth, res);
+        }
+        MalVal::Set(s) => {
+            return pr_seq(&set_elements(s), 1, ("#{", "}"), config, elide, depth, res)
+        }
+        MalVal::RustFunc(..) => res.push_str("<builtin func>"),
+        MalVal::MalFunc { is_macro: true, .. } | MalVal::Syntax(_) => res.push_str("<macro>"),
+        MalVal::VmFunc(c) if c.is_macro => res.push_str("<macro>"),
+        MalVal::MalFunc { .. } | MalVal::VmFunc(_) => res.push_str("<func>"),
+        // unreadable, as no atom reads back as the same one
+        MalVal::Atom(a) => {
+            let level = match Depth::enter() {
+                Ok(level) => Some(level),
+                Err(_) if elide => None,
+                Err(err) => return Err(err),
+            };
+            res.push_str("#<atom ");
+            match level {
+                Some(_) if !config.too_deep(depth) => {
+                    pr_str(&a.borrow(), config, elide, depth + 1, res)?
+                }
+                _ => res.push_str("..."),
+            }
+            res.push('>');
+        }
+    }
+    Ok(())
+}
+
+// `elts` in `open` and `close`, `per_entry` of them to an entry
+fn pr_seq<'a>(
+    elts: impl IntoIterator<Item = &'a MalVal>,
+    per_entry: usize,
+    (open, close): (&str, &str),
+    config: &PrintConfig,
+    elide: bool,
+    depth: usize,
+    res: &mut String,
+) -> Result<()> {
+    let level = match Depth::enter() {
+        Ok(level) => Some(level),
+        Err(_) if elide => None,
+        Err(err) => return Err(err),
+    };
+    if level.is_none() || config.too_deep(depth) {
+        res.push_str("...");
+        return Ok(());
+    }
+    res.push_str(open);
+    for (i, x) in elts.into_iter().enumerate() {
+        if i > 0 {
+            res.push(' ');
+        }
+        if i % per_entry == 0 && config.max_length.is_some_and(|max| i / per_entry >= max) {
+            res.push_str("...");
+            break;
+        }
+        pr_str(x, config, elide, depth + 1, res)?;
+    }
+    res.push_str(close);
+    Ok(())
+}
+
+fn escape(s: &str, res: &mut String) {
+    res.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => res.push_str("\\\""),
+            '\\' => res.push_str("\\\\"),
+            '\n' => res.push_str("\\n"),
+            _ => res.push(c),
+        }
+    }
+    res.push('"');
+}
+
+// whether the name of a symbol, or of a keyword after its colon, reads
+// back as itself when written as it is
+fn is_plain_name(name: &str, keyword: bool) -> bool {
+    let delimiter = |c: char| c.is_whitespace() || c.is_control() || "()[]{}'\"`,;|".contains(c);
+    if name.is_empty() || name.contains(delimiter) {
+        return false;
+    }
+    if keyword {
+        return true;
+    }
+    let reads_as_other = matches!(name, "nil" | "true" | "false")
+        || (name.len() > 1 && name.starts_with(':'))
+        || ["~", "^", "@", "\\", "#_", "#|", "#!", "#<"]
+            .iter()
+            .any(|prefix| name.starts_with(prefix));
+    let digits = name.strip_prefix('-').unwrap_or(name);
+    let number = !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit());
+    !reads_as_other && !number
+}
+
+// `|name|`, which the reader takes as it is but for `\` escapes
+fn barred(name: &str, res: &mut String) {
+    res.push('|');
+    for c in name.chars() {
+        if c == '|' || c == '\\' {
+            res.push('\\');
+        }
+        res.push(c);
+    }
+    res.push('|');
+}
+
+// the names of the characters written `\name`
+pub const CHAR_NAMES: &[(&str, char)] = &[
+    ("newline", '\n'),
+    ("space", ' '),
+    ("tab", '\t'),
+    ("return", '\r'),
+    ("formfeed", '\x0c'),
+    ("backspace", '\x08'),
+];
+
+fn char_literal(c: char, res: &mut String) {
+    res.push('\\');
+    match CHAR_NAMES.iter().find(|(_, named)| *named == c) {
+        Some((name, _)) => res.push_str(name),
+        None if c.is_control() || c.is_whitespace() => res.push_str(&format!("u{:04X}", c as u32)),
+        None => res.push(c),
     }
 }
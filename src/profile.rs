@@ -0,0 +1,145 @@
+// A counting profiler, enabled with `--profile`: every call to a builtin or
+// to a function bound with `def!`, and every expansion of a `defmacro!`
+// macro, is counted and timed, and `report` summarises them when the REPL
+// exits.
+//
+// Times include the callees. A function that recurses is only timed in its
+// outermost activation, so its time isn't counted once per level; the
+// callers of a function still include its time in theirs.
+
+use std::{
+    cell::{Cell, RefCell},
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use fnv::FnvHashMap;
+
+use crate::{
+    symbol::Symbol,
+    types::MalVal::{self, MalFunc, RustFunc, VmFunc},
+};
+
+#[derive(Default)]
+struct Profile {
+    // the named functions, kept alive so that their address isn't reused
+    names: FnvHashMap<usize, (Symbol, MalVal)>,
+    entries: FnvHashMap<usize, Entry>,
+}
+
+#[derive(Default)]
+struct Entry {
+    calls: u64,
+    // activations not yet returned from
+    active: u64,
+    total: Duration,
+}
+
+thread_local! {
+    static ENABLED: Cell<bool> = const { Cell::new(false) };
+    static PROFILE: RefCell<Profile> = RefCell::new(Profile::default());
+}
+
+pub fn enable() {
+    ENABLED.with(|e| e.set(true));
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.with(|e| e.get())
+}
+
+// what identifies a function across its closures: the builtin itself, or
+// the body shared by every closure of one `fn*`
+fn key(func: &MalVal) -> Option<usize> {
+    match func {
+        RustFunc(f, _) => Some(*f as usize),
+        MalFunc { arities, .. } => Some(Rc::as_ptr(arities) as usize),
+        VmFunc(closure) => Some(closure.id()),
+        _ => None,
+    }
+}
+
+// report calls to `func` under `name`
+pub fn name(func: &MalVal, name: Symbol) {
+    if !is_enabled() {
+        return;
+    }
+    if let Some(key) = key(func) {
+        PROFILE.with(|p| p.borrow_mut().names.insert(key, (name, func.clone())));
+    }
+}
+
+// the time spent in one call, recorded when dropped
+pub struct Timer {
+    key: usize,
+    start: Option<Instant>,
+}
+
+// start timing a call to `func`; `None` when profiling is off or `func`
+// has no name
+pub fn enter(func: &MalVal) -> Option<Timer> {
+    match is_enabled() {
+        true => enter_id(key(func)?),
+        false => None,
+    }
+}
+
+// `enter` for the function identified by `key`
+pub(crate) fn enter_id(key: usize) -> Option<Timer> {
+    if !is_enabled() {
+        return None;
+    }
+    PROFILE.with(|p| {
+        let mut p = p.borrow_mut();
+        if !p.names.contains_key(&key) {
+            return None;
+        }
+        let entry = p.entries.entry(key).or_default();
+        entry.calls += 1;
+        entry.active += 1;
+        Some(Timer {
+            key,
+            start: (entry.active == 1).then(Instant::now),
+        })
+    })
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        PROFILE.with(|p| {
+            let mut p = p.borrow_mut();
+            let entry = p.entries.get_mut(&self.key).unwrap();
+            entry.active -= 1;
+            if let Some(start) = self.start {
+                entry.total += start.elapsed();
+            }
+        })
+    }
+}
+
+// a table of the functions called, most time first
+pub fn report() -> String {
+    PROFILE.with(|p| {
+        let p = p.borrow();
+        let mut rows: Vec<_> = p
+            .entries
+            .iter()
+            .map(|(key, entry)| (p.names[key].0, entry))
+            .collect();
+        rows.sort_by(|a, b| {
+            b.1.total
+                .cmp(&a.1.total)
+                .then(a.0.as_str().cmp(b.0.as_str()))
+        });
+        let mut out = format!("{:>10} {:>12}  function\n", "calls", "total ms");
+        for (name, entry) in rows {
+            out += &format!(
+                "{:>10} {:>12.3}  {}\n",
+                entry.calls,
+                entry.total.as_secs_f64() * 1000.0,
+                name
+            );
+        }
+        out
+    })
+}
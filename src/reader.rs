@@ -1,121 +1,572 @@
-use std::rc::Rc;
+use anyhow::{anyhow, bail, Error, Result};
 
-use anyhow::{anyhow, bail, Context, Result};
-use once_cell::sync::Lazy;
-use regex::Regex;
+use crate::{
+    hamt::Hamt,
+    lexer::{Diagnostic, Lexer, Token, TokenKind},
+    printer::{print_readably, CHAR_NAMES},
+    seq::Seq,
+    stack::Depth,
+    symbol::{self, Symbol},
+    types::{
+        map_key, set_key,
+        MalVal::{self, Bool, Char, Kw, List, Map, Nil, Num, Set, Str, Sym, Vector},
+    },
+};
 
-use crate::types::MalVal::{self, Bool, List, Nil, Num, Sym};
-
-#[derive(Debug, Clone)]
-struct Reader {
-    tokens: Vec<String>,
-    pos: usize,
+struct Reader<'a> {
+    src: &'a str,
+    lexer: Lexer<'a>,
+    peeked: Option<Token<'a>>,
+    // `None` when the first syntax error fails the read; otherwise errors
+    // are collected here and reading carries on
+    diagnostics: Option<Vec<Diagnostic>>,
 }
 
-impl Reader {
-    fn next(&mut self) -> Result<String> {
-        self.pos += 1;
-        Ok(self
-            .tokens
-            .get(self.pos - 1)
-            .context("underflow")?
-            .to_string())
+impl<'a> Reader<'a> {
+    fn new(src: &'a str, recover: bool) -> Self {
+        Reader {
+            src,
+            lexer: Lexer::new(src),
+            peeked: None,
+            diagnostics: recover.then(Vec::new),
+        }
     }
 
-    fn peek(&self) -> Result<String> {
-        Ok(self.tokens.get(self.pos).context("underflow")?.to_string())
+    // the next token other than a comment, `None` at the end of input
+    fn peek(&mut self) -> Result<Option<Token<'a>>> {
+        while self.peeked.is_none() {
+            match self.lexer.next() {
+                Some(Ok(token)) if token.kind == TokenKind::Comment => (),
+                Some(Ok(token)) => self.peeked = Some(token),
+                Some(Err(diag)) => self.report(diag)?,
+                None => return Ok(None),
+            }
+        }
+        Ok(self.peeked)
     }
-}
 
-fn tokenize(string: &str) -> Vec<String> {
-    static RE: Lazy<Regex> = Lazy::new(|| {
-        Regex::new(r###"[\s,]*(~@|[\[\]{}()'`~^@]|"(?:\\.|[^\\"])*"?|;.*|[^\s\[\]{}('"`,;)]+)"###)
-            .unwrap()
-    });
+    fn next(&mut self) -> Result<Option<Token<'a>>> {
+        let token = self.peek()?;
+        self.peeked = None;
+        Ok(token)
+    }
 
-    let mut res = vec![];
-    for cap in RE.captures_iter(string) {
-        if cap[1].starts_with(";") {
-            continue;
+    // fail with a syntax error, or record it when recovering
+    fn report(&mut self, diag: Diagnostic) -> Result<()> {
+        match &mut self.diagnostics {
+            Some(diags) => {
+                diags.push(diag);
+                Ok(())
+            }
+            None => Err(error(self.src, &diag)),
         }
-        res.push(String::from(&cap[1]));
     }
-    res
+
+    fn starts_line(&self, token: &Token) -> bool {
+        token.span.start == 0 || self.src.as_bytes()[token.span.start - 1] == b'\n'
+    }
+
+    // the next token after any `#_` and the forms they discard
+    fn skip_discarded(&mut self) -> Result<Option<Token<'a>>> {
+        while let Some(token) = self.peek()? {
+            if token.kind != TokenKind::DatumComment {
+                return Ok(Some(token));
+            }
+            self.next()?;
+            match self.skip_discarded()? {
+                Some(next) if starts_form(next.kind) => {
+                    read_form(self)?;
+                }
+                _ => self.report(expected_form(&token))?,
+            }
+        }
+        Ok(None)
+    }
+
+    // skip to the next token that can start a form, reporting the ones in
+    // the way; false at the end of input or at the `close` token ending the
+    // collection being read. When recovering, a collection is also taken
+    // to end before a `(` that starts a line: most likely the next
+    // top-level form, after one that is missing its `)`.
+    fn at_form(&mut self, close: Option<TokenKind>) -> Result<bool> {
+        while let Some(token) = self.skip_discarded()? {
+            if Some(token.kind) == close {
+                return Ok(false);
+            }
+            if starts_form(token.kind) {
+                let resync = close.is_some()
+                    && self.diagnostics.is_some()
+                    && token.kind == TokenKind::Open
+                    && self.starts_line(&token);
+                return Ok(!resync);
+            }
+            self.report(unexpected(&token))?;
+            self.next()?;
+        }
+        Ok(false)
+    }
+}
+
+fn error(src: &str, diag: &Diagnostic) -> Error {
+    anyhow!(diag.describe(src))
+}
+
+fn unexpected(token: &Token) -> Diagnostic {
+    Diagnostic::new(token.span, format!("unexpected `{}`", token.text))
+}
+
+// nothing follows the reader macro or `#_` in `token`
+fn expected_form(token: &Token) -> Diagnostic {
+    Diagnostic::new(
+        token.span,
+        format!("expected a form after `{}`", token.text),
+    )
+}
+
+// the token that closes the collection `open` starts
+fn closing(open: &Token) -> TokenKind {
+    match open.kind {
+        TokenKind::Open => TokenKind::Close,
+        TokenKind::OpenBracket => TokenKind::CloseBracket,
+        _ => TokenKind::CloseBrace,
+    }
+}
+
+// the collection `open` starts has no end before `got`
+fn unclosed(open: &Token, got: &str) -> Diagnostic {
+    let close = match closing(open) {
+        TokenKind::Close => ")",
+        TokenKind::CloseBracket => "]",
+        _ => "}",
+    };
+    let message = format!(
+        "expected `{}` to close this `{}`, got {}",
+        close, open.text, got
+    );
+    Diagnostic::new(open.span, message)
 }
 
 pub fn read_str(string: &str) -> Result<MalVal> {
-    let tokens = tokenize(string);
-    if tokens.len() == 0 {
-        bail!("no input");
+    let mut reader = Reader::new(string, false);
+    match reader.at_form(None)? {
+        true => read_form(&mut reader),
+        false => bail!("no input"),
+    }
+}
+
+fn read_forms(reader: &mut Reader) -> Result<Vec<MalVal>> {
+    let mut forms = vec![];
+    while reader.at_form(None)? {
+        forms.push(read_form(reader)?);
+    }
+    Ok(forms)
+}
+
+// read every top-level form in `string`, e.g. the contents of a file; if it
+// has syntax errors, they are all reported, one per line
+pub fn read_all(string: &str) -> Result<Vec<MalVal>> {
+    read_forms(&mut Reader::new(string, false)).map_err(|err| match recover(string) {
+        Ok((_, diags)) if !diags.is_empty() => anyhow!(diags
+            .iter()
+            .map(|diag| diag.describe(string))
+            .collect::<Vec<_>>()
+            .join("\n")),
+        _ => err,
+    })
+}
+
+// read every top-level form in `string`, carrying on after syntax errors:
+// returns the forms, each read as far as it goes, and the errors found.
+// Fails only when the forms nest too deep.
+pub fn read_all_recovering(string: &str) -> Result<(Vec<MalVal>, Vec<Diagnostic>)> {
+    // recovery guesses where forms end, so it is only used once a read has
+    // failed, not to second-guess a file that reads
+    match read_forms(&mut Reader::new(string, false)) {
+        Ok(forms) => Ok((forms, vec![])),
+        Err(_) => recover(string),
+    }
+}
+
+fn recover(string: &str) -> Result<(Vec<MalVal>, Vec<Diagnostic>)> {
+    let mut reader = Reader::new(string, true);
+    let forms = read_forms(&mut reader)?;
+    let mut diags = reader.diagnostics.unwrap_or_default();
+    diags.sort_by_key(|diag| diag.span.start);
+    Ok((forms, diags))
+}
+
+// the symbol a reader macro token stands for
+fn reader_macro(kind: TokenKind) -> Option<Symbol> {
+    match kind {
+        TokenKind::Quote => Some(symbol::QUOTE),
+        TokenKind::Quasiquote => Some(symbol::QUASIQUOTE),
+        TokenKind::Unquote => Some(symbol::UNQUOTE),
+        TokenKind::SpliceUnquote => Some(symbol::SPLICE_UNQUOTE),
+        TokenKind::At => Some(symbol::DEREF),
+        _ => None,
     }
+}
 
-    read_form(&mut Reader { tokens, pos: 0 })
+fn starts_form(kind: TokenKind) -> bool {
+    use TokenKind::*;
+    matches!(
+        kind,
+        Open | OpenBracket | OpenBrace | OpenSet | Str | Char | Atom | Caret
+    ) || reader_macro(kind).is_some()
 }
 
+// read the form the next token starts; forms with errors are read as `nil`
+// when recovering
 fn read_form(reader: &mut Reader) -> Result<MalVal> {
-    let token = reader.peek()?;
-    match &token[..] {
-        "'" => {
-            let _ = reader.next();
-            Ok(List(Rc::new(vec![
-                Sym("quote".to_owned()),
-                read_form(reader)?,
-            ])))
-        }
-        "`" => {
-            let _ = reader.next();
-            Ok(List(Rc::new(vec![
-                Sym("quasiquote".to_owned()),
-                read_form(reader)?,
-            ])))
-        }
-        "~" => {
-            let _ = reader.next();
-            Ok(List(Rc::new(vec![
-                Sym("unquote".to_owned()),
-                read_form(reader)?,
-            ])))
-        }
-        "~@" => {
-            let _ = reader.next();
-            Ok(List(Rc::new(vec![
-                Sym("splice-unquote".to_owned()),
-                read_form(reader)?,
-            ])))
-        }
-        "(" => read_list(reader),
-        ")" => Err(anyhow!("unexpected ')'")),
-        _ => read_atom(reader),
-    }
-}
-
-fn read_list(reader: &mut Reader) -> Result<MalVal> {
-    let mut list = Vec::<MalVal>::new();
-    reader.next()?;
+    let _depth = Depth::enter()?;
+    let token = reader.next()?.expect("no form to read");
+    if let Some(sym) = reader_macro(token.kind) {
+        return Ok(match read_operand(reader, &token)? {
+            Some(form) => List(Seq::from(vec![Sym(sym, None), form])),
+            None => Nil,
+        });
+    }
+    if token.kind == TokenKind::Caret {
+        return read_with_meta(reader, &token);
+    }
+    match token.kind {
+        TokenKind::Open => Ok(List(read_seq(reader, &token)?.into())),
+        TokenKind::OpenBracket => Ok(Vector(read_seq(reader, &token)?.into())),
+        TokenKind::OpenBrace => read_map(reader, &token),
+        TokenKind::OpenSet => read_set(reader, &token),
+        TokenKind::Str => Ok(Str(unescape(token.text))),
+        TokenKind::Char => read_char(reader, &token),
+        _ => read_atom(reader, &token),
+    }
+}
+
+// the form after the prefix `token`, or `None` (reported) if there is none
+fn read_operand(reader: &mut Reader, token: &Token) -> Result<Option<MalVal>> {
+    match reader.skip_discarded()? {
+        Some(next) if starts_form(next.kind) => Ok(Some(read_form(reader)?)),
+        _ => {
+            reader.report(expected_form(token))?;
+            Ok(None)
+        }
+    }
+}
+
+// `^meta form`: `form` with `meta` as its metadata. `meta` is a map, or
+// `:flag` for `{:flag true}`, or a symbol or string `tag` for `{:tag tag}`;
+// with several, as in `^:a ^:b form`, the maps are merged. Only collections
+// and symbols can be given metadata this way.
+fn read_with_meta(reader: &mut Reader, caret: &Token) -> Result<MalVal> {
+    let mut meta = Hamt::new();
+    let mut token = *caret;
     loop {
-        let token = reader.peek().context("expected `)`, got EOF")?;
-        if token == ")" {
-            break;
+        let Some(form) = read_operand(reader, &token)? else {
+            return Ok(Nil);
+        };
+        let entries = match form {
+            Map(map) => map,
+            Kw(_) => [(map_key(&form)?, Bool(true))].into_iter().collect(),
+            Sym(..) | Str(_) => [(map_key(&Kw("tag".to_owned()))?, form)]
+                .into_iter()
+                .collect(),
+            _ => {
+                let message = "metadata must be a map, keyword, symbol or string".to_owned();
+                reader.report(Diagnostic::new(token.span, message))?;
+                Hamt::new()
+            }
+        };
+        for (key, val) in entries.iter() {
+            if !meta.contains_key(key) {
+                meta.insert(key.clone(), val.clone());
+            }
+        }
+        match reader.skip_discarded()? {
+            Some(next) if next.kind == TokenKind::Caret => token = reader.next()?.unwrap(),
+            _ => break,
+        }
+    }
+    let Some(form) = read_operand(reader, &token)? else {
+        return Ok(Nil);
+    };
+    match form {
+        List(_) | Vector(_) | Map(_) | Set(_) | Sym(..) => form.with_meta(&Map(meta)),
+        _ => {
+            let message = format!("cannot attach metadata to `{}`", print_readably(&form));
+            reader.report(Diagnostic::new(caret.span, message))?;
+            Ok(form)
         }
+    }
+}
+
+// read the forms up to the token closing `open`
+fn read_seq(reader: &mut Reader, open: &Token) -> Result<Vec<MalVal>> {
+    let close = closing(open);
+    let mut list = Vec::<MalVal>::new();
+    while reader.at_form(Some(close))? {
         list.push(read_form(reader)?);
     }
-    let _ = reader.next();
-    Ok(List(Rc::new(list)))
+    match reader.peek()? {
+        Some(token) if token.kind == close => {
+            reader.next()?;
+        }
+        next => {
+            let got = if next.is_some() {
+                "the next top-level form"
+            } else {
+                "EOF"
+            };
+            reader.report(unclosed(open, got))?;
+        }
+    }
+    Ok(list)
+}
+
+fn read_map(reader: &mut Reader, open: &Token) -> Result<MalVal> {
+    let mut elts = read_seq(reader, open)?;
+    if !elts.len().is_multiple_of(2) {
+        let message = "map literal must contain an even number of forms".to_owned();
+        reader.report(Diagnostic::new(open.span, message))?;
+        elts.pop();
+    }
+    let mut map = Hamt::new();
+    for pair in elts.chunks(2) {
+        match map_key(&pair[0]) {
+            Ok(key) => map.insert(key, pair[1].clone()),
+            Err(err) => reader.report(Diagnostic::new(open.span, err.to_string()))?,
+        }
+    }
+    Ok(Map(map))
+}
+
+fn read_set(reader: &mut Reader, open: &Token) -> Result<MalVal> {
+    let mut set = Hamt::new();
+    for elt in read_seq(reader, open)? {
+        match set_key(&elt) {
+            Ok(key) => set.insert(key, elt),
+            Err(err) => reader.report(Diagnostic::new(open.span, err.to_string()))?,
+        }
+    }
+    Ok(Set(set))
+}
+
+fn is_number(text: &str) -> bool {
+    let digits = text.strip_prefix('-').unwrap_or(text);
+    !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit())
 }
 
-fn read_atom(reader: &mut Reader) -> Result<MalVal> {
-    static NUM_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"^-?[0-9]+$").unwrap());
-    let token = reader.next()?;
-    match &token[..] {
+fn read_atom(reader: &mut Reader, token: &Token) -> Result<MalVal> {
+    let text = token.text;
+    match text {
+        _ if text.contains('|') => Ok(match text.strip_prefix(':') {
+            Some(name) => Kw(unbar(name)),
+            None => Sym(Symbol::intern(&unbar(text))?, None),
+        }),
+        _ if text.starts_with("#<") => {
+            let message = format!("`{}` starts a value that can't be read back", text);
+            reader.report(Diagnostic::new(token.span, message))?;
+            Ok(Nil)
+        }
         "nil" => Ok(Nil),
         "true" => Ok(Bool(true)),
         "false" => Ok(Bool(false)),
-        _ => {
-            if NUM_RE.is_match(&token) {
-                Ok(Num(token.parse().unwrap()))
-            } else {
-                Ok(Sym(token))
+        _ if text.len() > 1 && text.starts_with(':') => Ok(Kw(text[1..].to_owned())),
+        _ if is_number(text) => match text.parse() {
+            Ok(n) => Ok(Num(n)),
+            Err(_) => {
+                reader.report(Diagnostic::new(
+                    token.span,
+                    "number out of range".to_owned(),
+                ))?;
+                Ok(Nil)
             }
+        },
+        _ => Ok(Sym(Symbol::intern(text)?, None)),
+    }
+}
+
+// a symbol or keyword name with `|...|` sections, which are taken as they
+// are but for `\` escapes
+fn unbar(text: &str) -> String {
+    let mut res = String::with_capacity(text.len());
+    let mut quoted = false;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '|' => quoted = !quoted,
+            '\\' if quoted => res.extend(chars.next()),
+            _ => res.push(c),
+        }
+    }
+    res
+}
+
+// `\c`, `\name` for one of `CHAR_NAMES`, or `\uXXXX`
+fn read_char(reader: &mut Reader, token: &Token) -> Result<MalVal> {
+    let name = &token.text[1..];
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return Ok(Char(c));
+    }
+    if let Some(&(_, c)) = CHAR_NAMES.iter().find(|(n, _)| *n == name) {
+        return Ok(Char(c));
+    }
+    let code = name.strip_prefix('u').filter(|hex| hex.len() == 4);
+    match code
+        .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+        .and_then(char::from_u32)
+    {
+        Some(c) => Ok(Char(c)),
+        None => {
+            let message = format!("unknown character `{}`", token.text);
+            reader.report(Diagnostic::new(token.span, message))?;
+            Ok(Nil)
+        }
+    }
+}
+
+// the contents of a string literal; the lexer has checked its escapes
+fn unescape(token: &str) -> String {
+    let body = &token[1..token.len() - 1];
+    if !body.contains('\\') {
+        return body.to_owned();
+    }
+    let mut res = String::with_capacity(body.len());
+    let mut chars = body.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            res.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => res.push('\n'),
+            Some(c) => res.push(c),
+            None => unreachable!("string ends with a backslash"),
+        }
+    }
+    res
+}
+
+// a form as written in the source, keeping comments, blank lines and the raw
+// text of atoms; this is what the formatter works on
+#[derive(Debug, Clone)]
+pub enum Syntax {
+    Atom(String),
+    List(Vec<Syntax>),
+    Vector(Vec<Syntax>),
+    Map(Vec<Syntax>),
+    Set(Vec<Syntax>),
+    // a reader macro (`'`, `` ` ``, `~`, `~@` or `@`) or `#_`, and its form; or
+    // `^` and the metadata for the form after it
+    Prefixed(String, Box<Syntax>),
+    // a `trailing` comment follows other code on the same line
+    Comment { text: String, trailing: bool },
+    // one or more empty lines between two forms
+    Blank,
+}
+
+struct SyntaxToken<'a> {
+    token: Token<'a>,
+    // number of line breaks between this token and the previous one
+    newlines: usize,
+}
+
+// read every top-level form in `string` without dropping comments
+pub fn read_syntax(string: &str) -> Result<Vec<Syntax>> {
+    let mut tokens = vec![];
+    let mut prev_end = 0;
+    for token in Lexer::new(string) {
+        let token = token.map_err(|diag| error(string, &diag))?;
+        tokens.push(SyntaxToken {
+            token,
+            newlines: string[prev_end..token.span.start].matches('\n').count(),
+        });
+        prev_end = token.span.end;
+    }
+    read_syntax_seq(string, &tokens, &mut 0, None)
+}
+
+// read up to the token closing `open`, or to the end of input at the top
+// level
+fn read_syntax_seq(
+    src: &str,
+    tokens: &[SyntaxToken],
+    pos: &mut usize,
+    open: Option<&Token>,
+) -> Result<Vec<Syntax>> {
+    let close = open.map(closing);
+    let mut res = vec![];
+    loop {
+        let token = match (tokens.get(*pos), open) {
+            (Some(token), _) => token,
+            (None, Some(open)) => return Err(error(src, &unclosed(open, "EOF"))),
+            (None, None) => return Ok(res),
+        };
+        if Some(token.token.kind) == close {
+            *pos += 1;
+            return Ok(res);
+        }
+        if token.newlines >= 2 && !res.is_empty() {
+            res.push(Syntax::Blank);
+        }
+        let mut hoisted = vec![];
+        let form = read_syntax_form(src, tokens, pos, &mut hoisted)?;
+        res.extend(hoisted);
+        res.push(form);
+    }
+}
+
+// comments between a prefix and its form are moved to `hoisted`, to go on
+// their own lines before the prefixed form
+fn read_syntax_form(
+    src: &str,
+    tokens: &[SyntaxToken],
+    pos: &mut usize,
+    hoisted: &mut Vec<Syntax>,
+) -> Result<Syntax> {
+    let _depth = Depth::enter()?;
+    let Some(SyntaxToken { token, newlines }) = tokens.get(*pos) else {
+        bail!("unexpected EOF");
+    };
+    *pos += 1;
+    if reader_macro(token.kind).is_some()
+        || matches!(token.kind, TokenKind::DatumComment | TokenKind::Caret)
+    {
+        while let Some(comment) = tokens
+            .get(*pos)
+            .filter(|t| t.token.kind == TokenKind::Comment)
+        {
+            hoisted.push(Syntax::Comment {
+                text: comment.token.text.trim_end().to_owned(),
+                trailing: false,
+            });
+            *pos += 1;
+        }
+        return Ok(Syntax::Prefixed(
+            token.text.to_owned(),
+            Box::new(read_syntax_form(src, tokens, pos, hoisted)?),
+        ));
+    }
+    match token.kind {
+        TokenKind::Open => Ok(Syntax::List(read_syntax_seq(
+            src,
+            tokens,
+            pos,
+            Some(token),
+        )?)),
+        TokenKind::OpenBracket => Ok(Syntax::Vector(read_syntax_seq(
+            src,
+            tokens,
+            pos,
+            Some(token),
+        )?)),
+        TokenKind::OpenBrace => Ok(Syntax::Map(read_syntax_seq(src, tokens, pos, Some(token))?)),
+        TokenKind::OpenSet => Ok(Syntax::Set(read_syntax_seq(src, tokens, pos, Some(token))?)),
+        TokenKind::Comment => Ok(Syntax::Comment {
+            text: token.text.trim_end().to_owned(),
+            trailing: *newlines == 0 && *pos > 1,
+        }),
+        TokenKind::Str | TokenKind::Char | TokenKind::Atom => {
+            Ok(Syntax::Atom(token.text.to_owned()))
         }
+        _ => Err(error(src, &unexpected(token))),
     }
 }
@@ -0,0 +1,214 @@
+use std::{fs, path::Path, time::Instant};
+
+use anyhow::{anyhow, Result};
+
+use crate::{
+    env::{env_bindings, get_env, set_env, Env},
+    eval_with,
+    expand::{macroexpand_all, macroexpand_step},
+    init_env,
+    limits::{with_limits, Limits},
+    pretty::{pprint, DEFAULT_WIDTH},
+    printer::{print_with, PrintConfig},
+    reader::{read_all, read_str},
+    symbol::Symbol,
+    types::{
+        MalRet,
+        MalVal::{self, List, Nil, Str, Sym},
+    },
+    Backend,
+};
+
+const HELP: &str = "\
+:help          show this message
+:env           list global bindings
+:time <expr>   evaluate <expr> and report the elapsed time
+:expand <expr> show <expr> with every macro expanded
+:step <expr>   show each step of the macro expansion of <expr>
+:pp [<expr>]   pretty-print <expr>, or toggle pretty-printing of results
+:load <file>   evaluate every form in <file>
+:reset         start over with a fresh global env
+:quit          leave the REPL
+
+*1, *2 and *3 hold the last three results, *e the last error.";
+
+pub struct Repl {
+    env: Env,
+    print_config: PrintConfig,
+    pretty: bool,
+    prelude: bool,
+    // applied to each evaluation
+    limits: Limits,
+    backend: Backend,
+}
+
+impl Repl {
+    pub fn new(
+        print_config: PrintConfig,
+        prelude: bool,
+        limits: Limits,
+        backend: Backend,
+    ) -> Result<Self> {
+        let repl = Repl {
+            env: init_env(prelude, backend)?,
+            print_config,
+            pretty: false,
+            prelude,
+            limits,
+            backend,
+        };
+        repl.reset_history()?;
+        Ok(repl)
+    }
+
+    // handle one line of input; returns false when the REPL should exit
+    pub fn handle_line(&mut self, line: &str) -> Result<bool> {
+        let line = line.trim();
+        if line.is_empty() {
+            return Ok(true);
+        }
+        if let Some(cmd) = line.strip_prefix(':') {
+            let (cmd, arg) = match cmd.split_once(char::is_whitespace) {
+                Some((cmd, arg)) => (cmd, arg.trim()),
+                None => (cmd, ""),
+            };
+            return self.meta_command(cmd, arg);
+        }
+
+        match read_str(line) {
+            Ok(ast) => {
+                let ret = self.eval(ast);
+                self.report(ret)?;
+            }
+            Err(err) => println!("Parse Error: {:#}", err),
+        }
+        Ok(true)
+    }
+
+    fn meta_command(&mut self, cmd: &str, arg: &str) -> Result<bool> {
+        match cmd {
+            "help" => println!("{}", HELP),
+            "env" => {
+                for (name, val) in env_bindings(&self.env) {
+                    match self.print(&val) {
+                        Ok(printed) => println!("{} = {}", name, printed),
+                        Err(err) => println!("{}: Error: {:#}", name, err),
+                    }
+                }
+            }
+            "time" => {
+                let start = Instant::now();
+                let ret = read_str(arg).and_then(|ast| self.eval(ast));
+                let elapsed = start.elapsed();
+                self.report(ret)?;
+                println!("Elapsed: {:?}", elapsed);
+            }
+            "expand" => match read_str(arg)
+                .and_then(|ast| with_limits(&self.limits, || macroexpand_all(ast, &self.env)))
+                .and_then(|expanded| self.print(&expanded))
+            {
+                Ok(printed) => println!("{}", printed),
+                Err(err) => println!("Error: {:#}", err),
+            },
+            "pp" if arg.is_empty() => {
+                self.pretty = !self.pretty;
+                println!("Pretty-printing {}", if self.pretty { "on" } else { "off" });
+            }
+            "pp" => {
+                let pretty = std::mem::replace(&mut self.pretty, true);
+                let ret = read_str(arg).and_then(|ast| self.eval(ast));
+                self.report(ret)?;
+                self.pretty = pretty;
+            }
+            "step" => {
+                if let Err(err) = with_limits(&self.limits, || self.step(arg)) {
+                    println!("Error: {:#}", err);
+                }
+            }
+            "load" => {
+                let ret = with_limits(&self.limits, || self.load(arg));
+                self.report(ret)?;
+            }
+            "reset" => {
+                self.env = init_env(self.prelude, self.backend)?;
+                self.reset_history()?;
+            }
+            "quit" => return Ok(false),
+            _ => println!("Unknown command `:{}`. Try :help", cmd),
+        }
+        Ok(true)
+    }
+
+    fn eval(&self, ast: MalVal) -> MalRet {
+        with_limits(&self.limits, || {
+            eval_with(self.backend, ast, self.env.clone())
+        })
+    }
+
+    fn step(&self, src: &str) -> Result<()> {
+        let mut ast = read_str(src)?;
+        println!("{}", self.print(&ast)?);
+        while let Some(expanded) = macroexpand_step(ast, &self.env)? {
+            ast = expanded;
+            println!("=> {}", self.print(&ast)?);
+        }
+        Ok(())
+    }
+
+    // evaluate the file at `path` with `*ARGV*` bound to `argv`
+    pub fn run_script(&self, path: &Path, argv: &[String]) -> MalRet {
+        let argv = argv.iter().map(|arg| Str(arg.clone())).collect::<Vec<_>>();
+        set_env(&self.env, sym("*ARGV*"), List(argv.into()))?;
+        with_limits(&self.limits, || self.load(&path.to_string_lossy()))
+    }
+
+    fn load(&self, path: &str) -> MalRet {
+        if path.is_empty() {
+            return Err(anyhow!("usage: :load <file>"));
+        }
+        let src = fs::read_to_string(path).map_err(|e| anyhow!("{}: {}", path, e))?;
+        let mut ret = Nil;
+        for form in read_all(&src)? {
+            ret = eval_with(self.backend, form, self.env.clone())?;
+        }
+        Ok(ret)
+    }
+
+    // print the result of an evaluation and record it in *1..*3 or *e
+    fn report(&self, ret: MalRet) -> Result<()> {
+        match ret.and_then(|val| Ok((self.print(&val)?, val))) {
+            Ok((printed, val)) => {
+                println!("{}", printed);
+                let last1 = get_env(&self.env, &sym("*1"))?;
+                let last2 = get_env(&self.env, &sym("*2"))?;
+                set_env(&self.env, sym("*3"), last2)?;
+                set_env(&self.env, sym("*2"), last1)?;
+                set_env(&self.env, sym("*1"), val)?;
+            }
+            Err(err) => {
+                println!("Error: {:#}", err);
+                set_env(&self.env, sym("*e"), Str(format!("{:#}", err)))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn print(&self, val: &MalVal) -> Result<String> {
+        if self.pretty {
+            pprint(val, &self.print_config, DEFAULT_WIDTH)
+        } else {
+            print_with(val, &self.print_config)
+        }
+    }
+
+    fn reset_history(&self) -> Result<()> {
+        for name in ["*1", "*2", "*3", "*e"] {
+            set_env(&self.env, sym(name), Nil)?;
+        }
+        Ok(())
+    }
+}
+
+fn sym(name: &str) -> MalVal {
+    Sym(Symbol::new(name), None)
+}
@@ -0,0 +1,185 @@
+// Sandboxed evaluation of untrusted code. A sandbox's root env holds only the
+// builtins of the capability sets it was created with and has no outer env,
+// so closures and macros made inside it can't see anything else; values
+// handed in from outside are checked for the same property.
+
+use std::rc::Rc;
+
+use anyhow::{bail, Result};
+
+use crate::{
+    core,
+    env::{env_bindings, new_env, root_env, set_env, Env},
+    eval,
+    limits::{with_limits, Limits},
+    load_prelude,
+    printer::print_readably,
+    reader::read_all,
+    symbol::Symbol,
+    types::{
+        MalRet,
+        MalVal::{self, Atom, List, MalFunc, Map, Nil, RustFunc, Set, Sym, Syntax, Vector, VmFunc},
+    },
+    Backend,
+};
+
+// named sets of builtins; special forms are always available
+pub const CAPABILITIES: &[(&str, &[&str])] = &[
+    ("arith", &["+", "-", "*", "/", "=", "<", "<=", ">", ">="]),
+    (
+        "data",
+        &[
+            "list",
+            "list?",
+            "empty?",
+            "vector",
+            "vec",
+            "vector?",
+            "map?",
+            "hash-set",
+            "set",
+            "set?",
+            "disj",
+            "keyword?",
+            "nil?",
+            "symbol?",
+            "string?",
+            "number?",
+            "char?",
+            "first",
+            "rest",
+            "nth",
+            "hash-map",
+            "assoc",
+            "dissoc",
+            "get",
+            "contains?",
+            "keys",
+            "vals",
+            "keyword",
+            "meta",
+            "with-meta",
+            "vary-meta",
+            "atom",
+            "atom?",
+            "deref",
+            "reset!",
+            "swap!",
+            "throw",
+            "cons",
+            "concat",
+            "conj",
+            "seq",
+            "count",
+            "gensym",
+        ],
+    ),
+    (
+        "strings",
+        &[
+            "pr-str",
+            "str",
+            "read-string",
+            "char",
+            "int->char",
+            "char->int",
+            "upper-case",
+            "lower-case",
+            "trim",
+            "starts-with?",
+            "ends-with?",
+            "index-of",
+            "replace",
+        ],
+    ),
+    ("console", &["prn", "println", "pprint"]),
+    ("fs", &["slurp"]),
+    ("gc", &["gc", "heap-stats"]),
+];
+
+// builtins in no capability set: the clock would let untrusted code time
+// what runs around it
+pub const DENIED: &[&str] = &["time-ms"];
+
+// no I/O; enough to load the prelude, whose macros expand into `cons` and
+// `concat`
+pub const DEFAULT_CAPABILITIES: &[&str] = &["arith", "data", "strings"];
+
+pub struct Sandbox {
+    env: Env,
+    limits: Limits,
+}
+
+impl Sandbox {
+    // a sandbox with the builtins of `capabilities` and the prelude; `limits`
+    // apply to each call to `eval_str`
+    pub fn new(capabilities: &[&str], limits: Limits) -> Result<Self> {
+        let sandbox = Self::without_prelude(capabilities, limits)?;
+        load_prelude(&sandbox.env, Backend::TreeWalker)?;
+        Ok(sandbox)
+    }
+
+    pub fn without_prelude(capabilities: &[&str], limits: Limits) -> Result<Self> {
+        let mut allowed = vec![];
+        for cap in capabilities {
+            match CAPABILITIES.iter().find(|(name, _)| name == cap) {
+                Some((_, builtins)) => allowed.extend_from_slice(builtins),
+                None => bail!("unknown capability `{}`", cap),
+            }
+        }
+        let env = new_env(None);
+        for (name, func) in core::ns() {
+            if allowed.contains(&name) {
+                set_env(&env, Sym(Symbol::new(name), None), RustFunc(func, None))?;
+            }
+        }
+        Ok(Sandbox { env, limits })
+    }
+
+    // bind `name` in the sandbox to `val`, which must not give access to
+    // anything outside the sandbox
+    pub fn define(&self, name: &str, val: MalVal) -> Result<()> {
+        self.check_contained(&val)?;
+        set_env(&self.env, Sym(Symbol::new(name), None), val)?;
+        Ok(())
+    }
+
+    pub fn eval_str(&self, src: &str) -> MalRet {
+        with_limits(&self.limits, || {
+            let mut ret = Nil;
+            for form in read_all(src)? {
+                ret = eval(form, self.env.clone())?;
+            }
+            Ok(ret)
+        })
+    }
+
+    fn check_contained(&self, val: &MalVal) -> Result<()> {
+        if let Some(meta) = val.meta() {
+            self.check_contained(meta)?;
+        }
+        let env = match val {
+            MalFunc { env, .. } => env,
+            Syntax(rules) => rules.env(),
+            VmFunc(closure) => closure.env(),
+            RustFunc(f, _) => {
+                let allowed = env_bindings(&self.env)
+                    .iter()
+                    .any(|(_, b)| matches!(b, RustFunc(g, _) if std::ptr::fn_addr_eq(*f, *g)));
+                if !allowed {
+                    bail!("builtin is not available in the sandbox");
+                }
+                return Ok(());
+            }
+            List(l) => return l.iter().try_for_each(|v| self.check_contained(v)),
+            Vector(v) => return v.iter().try_for_each(|v| self.check_contained(v)),
+            Map(m) | Set(m) => return m.values().try_for_each(|v| self.check_contained(v)),
+            Atom(a) => return self.check_contained(&a.borrow()),
+            _ => return Ok(()),
+        };
+        if !Rc::ptr_eq(&root_env(env), &self.env) {
+            bail!("`{}` was defined outside the sandbox", print_readably(val));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,326 @@
+// Persistent lists. A list is a run of slots at the end of a buffer,
+// followed by the list the buffer continues with, if any. `rest` moves the
+// start one slot on; `cons` writes the new head into the free slot just
+// before the start if no other list has claimed it, and otherwise starts a
+// new buffer continuing with the list. `cons`, `first`, `rest` and `count`
+// are O(1).
+//
+// `get` and `iter` walk the chain of buffers. Lists are still read as
+// slices by much of the interpreter: a list made of several buffers is
+// copied into one the first time it is read that way, and the copy is kept
+// for the lists sharing the buffer.
+
+use std::{
+    cell::{Cell, OnceCell, UnsafeCell},
+    fmt,
+    mem::MaybeUninit,
+    ops::Deref,
+    rc::Rc,
+};
+
+use crate::types::{MalVal, Meta};
+
+// size of a buffer started by `cons` onto a list it can't extend
+const MIN_CAPACITY: usize = 8;
+
+pub struct Buffer {
+    // slots `front..` hold values; the ones before are free
+    slots: Box<[UnsafeCell<MaybeUninit<MalVal>>]>,
+    front: Cell<usize>,
+    // the list after the last slot, and its length
+    next: Option<Seq>,
+    next_len: usize,
+    // the values from `front` on, with `next`'s, in one buffer; once made,
+    // no more values are added in front
+    flat: OnceCell<Rc<Buffer>>,
+}
+
+#[derive(Clone)]
+pub struct Seq {
+    buf: Rc<Buffer>,
+    start: usize,
+    // not kept by `cons` and `rest`
+    meta: Meta,
+}
+
+impl Buffer {
+    // a buffer of `capacity` slots holding `vals` at its end
+    fn new(
+        capacity: usize,
+        vals: impl ExactSizeIterator<Item = MalVal>,
+        next: Option<Seq>,
+    ) -> Rc<Buffer> {
+        let front = capacity - vals.len();
+        let slots = (0..front)
+            .map(|_| UnsafeCell::new(MaybeUninit::uninit()))
+            .chain(vals.map(|val| UnsafeCell::new(MaybeUninit::new(val))))
+            .collect();
+        Rc::new(Buffer {
+            slots,
+            front: Cell::new(front),
+            next_len: next.as_ref().map_or(0, |next| next.len()),
+            next,
+            flat: OnceCell::new(),
+        })
+    }
+
+    // the values of the slots from `from` on, which must be at or after
+    // `front`
+    #[inline]
+    fn values(&self, from: usize) -> &[MalVal] {
+        debug_assert!(from >= self.front.get());
+        let slots = &self.slots[from..];
+        // SAFETY: the slots from `front` on are initialized and never
+        // written again, and `UnsafeCell<MaybeUninit<T>>` has the layout of
+        // `T`
+        unsafe { std::slice::from_raw_parts(slots.as_ptr() as *const MalVal, slots.len()) }
+    }
+
+    fn flatten(&self) -> Rc<Buffer> {
+        let mut vals = self.values(self.front.get()).to_vec();
+        let mut next = self.next.as_ref();
+        while let Some(seq) = next {
+            vals.extend_from_slice(seq.buf.values(seq.start));
+            next = seq.buf.next.as_ref();
+        }
+        Seq::from(vals).buf
+    }
+
+    // call `f` on each value held, including those only some lists see
+    pub fn for_each_value(&self, f: impl FnMut(&MalVal)) {
+        self.values(self.front.get()).iter().for_each(f);
+    }
+
+    // the buffers this one refers to, for the cycle collector
+    pub fn for_each_buffer(&self, mut f: impl FnMut(&Rc<Buffer>)) {
+        if let Some(next) = &self.next {
+            f(&next.buf);
+        }
+        if let Some(flat) = self.flat.get() {
+            f(flat);
+        }
+    }
+
+    // drop the values held, but move the buffers of the lists among them,
+    // of `next` and of `flat` to `pending`
+    fn take_buffers(&mut self, pending: &mut Vec<Rc<Buffer>>) {
+        let front = std::mem::replace(self.front.get_mut(), self.slots.len());
+        for slot in self.slots[front..].iter_mut() {
+            // SAFETY: the slot is initialized, and `front` now marks it free
+            if let MalVal::List(seq) = unsafe { slot.get_mut().assume_init_read() } {
+                pending.push(seq.buf);
+            }
+        }
+        pending.extend(self.next.take().map(|seq| seq.buf));
+        pending.extend(self.flat.take());
+    }
+}
+
+impl Drop for Buffer {
+    // free nested lists and long chains of buffers without recursing down
+    // them
+    fn drop(&mut self) {
+        let mut pending = Vec::new();
+        self.take_buffers(&mut pending);
+        while let Some(buf) = pending.pop() {
+            if let Ok(mut buf) = Rc::try_unwrap(buf) {
+                buf.take_buffers(&mut pending);
+            }
+        }
+    }
+}
+
+impl Seq {
+    // the list with `val` in front of this one
+    pub fn cons(&self, val: MalVal) -> Seq {
+        let buf = &self.buf;
+        let front = buf.front.get();
+        if self.start == front && front > 0 && buf.flat.get().is_none() {
+            // SAFETY: the slot before `front` is free, and no list sees it
+            // until `front` is moved onto it
+            unsafe { (*buf.slots[front - 1].get()).write(val) };
+            buf.front.set(front - 1);
+            return Seq {
+                buf: buf.clone(),
+                start: front - 1,
+                meta: None,
+            };
+        }
+        // a list grown at the front gets buffers twice as big each time
+        let capacity = match self.start {
+            0 => MIN_CAPACITY.max(2 * buf.slots.len()),
+            _ => MIN_CAPACITY,
+        };
+        let next = (!self.is_empty()).then(|| self.clone());
+        Seq {
+            buf: Buffer::new(capacity, std::iter::once(val), next),
+            start: capacity - 1,
+            meta: None,
+        }
+    }
+
+    // the list without its first element, sharing the rest
+    pub fn rest(&self) -> Seq {
+        if self.start + 1 < self.buf.slots.len() {
+            return Seq {
+                buf: self.buf.clone(),
+                start: self.start + 1,
+                meta: None,
+            };
+        }
+        self.buf.next.clone().unwrap_or_default()
+    }
+
+    pub fn first(&self) -> Option<&MalVal> {
+        self.buf.values(self.start).first()
+    }
+
+    // the element at `idx`, found without copying the list into one buffer
+    pub fn get(&self, mut idx: usize) -> Option<&MalVal> {
+        let mut seq = self;
+        loop {
+            let vals = seq.buf.values(seq.start);
+            if idx < vals.len() {
+                return Some(&vals[idx]);
+            }
+            idx -= vals.len();
+            seq = seq.buf.next.as_ref()?;
+        }
+    }
+
+    // the elements, read from each buffer in turn
+    pub fn iter(&self) -> Iter<'_> {
+        Iter {
+            vals: self.buf.values(self.start).iter(),
+            next: self.buf.next.as_ref(),
+            len: self.len(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.buf.slots.len() - self.start + self.buf.next_len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // the buffer holding the elements, for the cycle collector
+    pub fn buffer(&self) -> &Rc<Buffer> {
+        &self.buf
+    }
+
+    pub fn meta(&self) -> &Meta {
+        &self.meta
+    }
+
+    // the same list with `meta` instead of its metadata
+    pub fn with_meta(&self, meta: Meta) -> Seq {
+        Seq {
+            meta,
+            ..self.clone()
+        }
+    }
+}
+
+impl Default for Seq {
+    fn default() -> Seq {
+        Seq {
+            buf: Buffer::new(0, std::iter::empty(), None),
+            start: 0,
+            meta: None,
+        }
+    }
+}
+
+impl Deref for Seq {
+    type Target = [MalVal];
+
+    #[inline]
+    fn deref(&self) -> &[MalVal] {
+        let buf = &self.buf;
+        if buf.next.is_none() {
+            return buf.values(self.start);
+        }
+        let flat = buf.flat.get_or_init(|| buf.flatten());
+        &flat.values(0)[self.start - buf.front.get()..]
+    }
+}
+
+impl From<Vec<MalVal>> for Seq {
+    fn from(vals: Vec<MalVal>) -> Seq {
+        let vals = Box::into_raw(vals.into_boxed_slice());
+        // SAFETY: `UnsafeCell<MaybeUninit<T>>` has the layout of `T`, so the
+        // values become the buffer's slots in place
+        let slots = unsafe { Box::from_raw(vals as *mut [UnsafeCell<MaybeUninit<MalVal>>]) };
+        Seq {
+            buf: Rc::new(Buffer {
+                slots,
+                front: Cell::new(0),
+                next: None,
+                next_len: 0,
+                flat: OnceCell::new(),
+            }),
+            start: 0,
+            meta: None,
+        }
+    }
+}
+
+impl FromIterator<MalVal> for Seq {
+    fn from_iter<I: IntoIterator<Item = MalVal>>(iter: I) -> Seq {
+        Seq::from(iter.into_iter().collect::<Vec<_>>())
+    }
+}
+
+pub struct Iter<'a> {
+    vals: std::slice::Iter<'a, MalVal>,
+    // the list after the current buffer
+    next: Option<&'a Seq>,
+    // elements left
+    len: usize,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = &'a MalVal;
+
+    fn next(&mut self) -> Option<&'a MalVal> {
+        loop {
+            if let Some(val) = self.vals.next() {
+                self.len -= 1;
+                return Some(val);
+            }
+            let next = self.next?;
+            self.vals = next.buf.values(next.start).iter();
+            self.next = next.buf.next.as_ref();
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+
+impl ExactSizeIterator for Iter<'_> {}
+
+impl<'a> IntoIterator for &'a Seq {
+    type Item = &'a MalVal;
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Iter<'a> {
+        self.iter()
+    }
+}
+
+// metadata doesn't take part in equality
+impl PartialEq for Seq {
+    fn eq(&self, other: &Seq) -> bool {
+        self.len() == other.len() && self.iter().eq(other.iter())
+    }
+}
+
+impl fmt::Debug for Seq {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
@@ -0,0 +1,101 @@
+// A limit on the nesting depth of the evaluator, reader and printer, which
+// all recurse on the Rust stack. Going deeper raises a "stack depth
+// exceeded" error instead of overflowing the stack and aborting.
+//
+// The depth limit alone is only safe for a thread with a big enough stack,
+// like the one `main` runs the interpreter on. So each level also checks
+// how much of its thread's stack is left, where the platform says where the
+// stack is, and fails the same way when it runs low; an embedder can call
+// the interpreter on any thread.
+
+use std::cell::Cell;
+
+use anyhow::{bail, Result};
+
+// the nesting allowed when the stack is big enough; `main` makes it so
+pub const DEFAULT_MAX_DEPTH: usize = 10_000;
+
+// stack size of the interpreter thread
+pub const STACK_SIZE: usize = 256 * 1024 * 1024;
+
+// stack kept free below the deepest level, for the frames between two
+// checks (builtins, macro expansion...) and for dropping values
+const RED_ZONE: usize = 256 * 1024;
+
+thread_local! {
+    static DEPTH: Cell<usize> = const { Cell::new(0) };
+    static MAX_DEPTH: Cell<usize> = const { Cell::new(DEFAULT_MAX_DEPTH) };
+    // the lowest address the stack may grow to, if known
+    static STACK_LIMIT: Cell<Option<usize>> = Cell::new(stack_start().map(|start| start + RED_ZONE));
+}
+
+pub fn max_depth() -> usize {
+    MAX_DEPTH.with(|max| max.get())
+}
+
+pub fn set_max_depth(max: usize) {
+    MAX_DEPTH.with(|cell| cell.set(max));
+}
+
+// the lowest address of the current thread's stack
+#[cfg(target_os = "linux")]
+fn stack_start() -> Option<usize> {
+    // SAFETY: `attr` is initialized by `pthread_getattr_np` before it is
+    // read, and destroyed once
+    unsafe {
+        let mut attr: libc::pthread_attr_t = std::mem::zeroed();
+        if libc::pthread_getattr_np(libc::pthread_self(), &mut attr) != 0 {
+            return None;
+        }
+        let mut addr = std::ptr::null_mut();
+        let mut size = 0;
+        let ret = libc::pthread_attr_getstack(&attr, &mut addr, &mut size);
+        libc::pthread_attr_destroy(&mut attr);
+        (ret == 0).then_some(addr as usize)
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn stack_start() -> Option<usize> {
+    // SAFETY: both only read the current thread's attributes
+    unsafe {
+        let thread = libc::pthread_self();
+        let top = libc::pthread_get_stackaddr_np(thread) as usize;
+        Some(top - libc::pthread_get_stacksize_np(thread))
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn stack_start() -> Option<usize> {
+    None
+}
+
+// whether the stack has grown into the red zone
+fn out_of_stack() -> bool {
+    let here = 0u8;
+    let here = std::ptr::addr_of!(here) as usize;
+    STACK_LIMIT.with(|limit| limit.get().is_some_and(|limit| here < limit))
+}
+
+// one level of recursion, released when dropped
+pub struct Depth(());
+
+impl Depth {
+    pub fn enter() -> Result<Depth> {
+        let depth = DEPTH.with(|depth| depth.get());
+        if depth >= max_depth() {
+            bail!("stack depth exceeded (limit {})", max_depth());
+        }
+        if out_of_stack() {
+            bail!("stack depth exceeded (out of stack at depth {})", depth);
+        }
+        DEPTH.with(|cell| cell.set(depth + 1));
+        Ok(Depth(()))
+    }
+}
+
+impl Drop for Depth {
+    fn drop(&mut self) {
+        DEPTH.with(|depth| depth.set(depth.get() - 1));
+    }
+}
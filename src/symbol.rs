@@ -0,0 +1,190 @@
+// Interned symbols. Each distinct name is stored once, and a `Symbol` is its
+// index in the interner, so symbols are compared and hashed as integers.
+//
+// The interner is per thread, like the values holding symbols (`MalVal` is
+// built on `Rc`). The names the evaluator dispatches on are interned first,
+// in the same order on every thread, and have constants below.
+//
+// Interned names are never freed, so the names a program makes up (those it
+// reads) are counted against the allocation limit. Gensyms aren't interned:
+// a gensym is its prefix's index and a number, and is written
+// `prefix__number`, but no other symbol is equal to it.
+
+use std::{
+    borrow::Cow,
+    cell::{Cell, RefCell},
+    fmt,
+};
+
+use anyhow::Result;
+use fnv::FnvHashMap;
+
+use crate::limits::allocate;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Symbol {
+    // index of the name in the interner; for a gensym, of its prefix
+    id: u32,
+    // the number of a gensym, 0 for an interned symbol
+    gensym: u64,
+}
+
+struct Interner {
+    names: Vec<&'static str>,
+    ids: FnvHashMap<&'static str, u32>,
+}
+
+macro_rules! predefined {
+    ($($name:ident = $s:literal,)*) => {
+        const PREDEFINED: &[&str] = &[$($s),*];
+
+        #[allow(non_camel_case_types, clippy::upper_case_acronyms)]
+        enum Index { $($name),* }
+
+        $(pub const $name: Symbol = Symbol { id: Index::$name as u32, gensym: 0 };)*
+    };
+}
+
+predefined! {
+    DEF = "def!",
+    LET = "let*",
+    LOOP = "loop",
+    RECUR = "recur",
+    TRY = "try*",
+    CATCH = "catch*",
+    QUOTE = "quote",
+    QUASIQUOTE = "quasiquote",
+    QUASIQUOTEEXPAND = "quasiquoteexpand",
+    UNQUOTE = "unquote",
+    SPLICE_UNQUOTE = "splice-unquote",
+    DEREF = "deref",
+    DO = "do",
+    IF = "if",
+    FN = "fn*",
+    DEFMACRO = "defmacro!",
+    DEFINE_SYNTAX = "define-syntax",
+    MACROEXPAND = "macroexpand",
+    MACROEXPAND_1 = "macroexpand-1",
+    MACROEXPAND_ALL = "macroexpand-all",
+    CONS = "cons",
+    CONCAT = "concat",
+    VEC = "vec",
+    AMP = "&",
+    AMP_OPTIONAL = "&optional",
+    AMP_KEY = "&key",
+}
+
+thread_local! {
+    static INTERNER: RefCell<Interner> = RefCell::new({
+        let mut interner = Interner {
+            names: vec![],
+            ids: FnvHashMap::default(),
+        };
+        for name in PREDEFINED {
+            interner.intern(name);
+        }
+        interner
+    });
+    static GENSYMS: Cell<u64> = const { Cell::new(0) };
+}
+
+impl Interner {
+    fn intern(&mut self, name: &str) -> u32 {
+        if let Some(&id) = self.ids.get(name) {
+            return id;
+        }
+        // names live as long as the program; there are only so many
+        let name: &'static str = Box::leak(name.to_owned().into_boxed_str());
+        let id = self.names.len() as u32;
+        self.names.push(name);
+        self.ids.insert(name, id);
+        id
+    }
+}
+
+impl Symbol {
+    // the symbol named `name`, for the interpreter's own names
+    pub fn new(name: &str) -> Symbol {
+        Symbol {
+            id: INTERNER.with(|interner| interner.borrow_mut().intern(name)),
+            gensym: 0,
+        }
+    }
+
+    // the symbol named `name`, for a name a program makes up: a new name
+    // is counted like a string
+    pub fn intern(name: &str) -> Result<Symbol> {
+        INTERNER.with(|interner| {
+            let mut interner = interner.borrow_mut();
+            if !interner.ids.contains_key(name) {
+                allocate(1 + name.len() / 8)?;
+            }
+            Ok(Symbol {
+                id: interner.intern(name),
+                gensym: 0,
+            })
+        })
+    }
+
+    // a fresh symbol named after `prefix`
+    pub fn gensym(prefix: Symbol) -> Symbol {
+        Symbol {
+            id: prefix.id,
+            gensym: GENSYMS.with(|n| {
+                n.set(n.get() + 1);
+                n.get()
+            }),
+        }
+    }
+
+    // the gensym `number` of `prefix`, as written by `map_key`
+    pub fn from_gensym(prefix: Symbol, number: u64) -> Symbol {
+        Symbol {
+            id: prefix.id,
+            gensym: number,
+        }
+    }
+
+    // the number of a gensym
+    pub fn gensym_number(self) -> Option<u64> {
+        (self.gensym != 0).then_some(self.gensym)
+    }
+
+    // the interned name; for a gensym, that of its prefix
+    pub fn as_str(self) -> &'static str {
+        INTERNER.with(|interner| interner.borrow().names[self.id as usize])
+    }
+
+    // the name the symbol is written as
+    pub fn name(self) -> Cow<'static, str> {
+        match self.gensym_number() {
+            None => Cow::Borrowed(self.as_str()),
+            Some(n) => Cow::Owned(format!("{}__{}", self.as_str(), n)),
+        }
+    }
+}
+
+// a gensym is equal to no name
+impl PartialEq<str> for Symbol {
+    fn eq(&self, other: &str) -> bool {
+        self.gensym == 0 && self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for Symbol {
+    fn eq(&self, other: &&str) -> bool {
+        *self == **other
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.name())
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.name())
+    }
+}
@@ -0,0 +1,373 @@
+// `define-syntax`/`syntax-rules`: pattern-matching macros.
+//
+// Expansion is hygienic in both directions:
+// - symbols the template binds itself (temporaries in `let*`, `fn*`,
+//   `loop`...) get a fresh name on every expansion, wherever they appear in
+//   the template, so they can't capture the caller's variables;
+// - symbols bound where the macro was defined keep referring to that
+//   binding, even when the caller shadows them.
+// Other symbols are left as they are, so a template can refer to a function
+// defined after the macro.
+
+use std::rc::Rc;
+
+use anyhow::{bail, Result};
+use fnv::{FnvHashMap, FnvHashSet};
+
+use crate::{
+    core::gensym,
+    env::{find_env, get_env, Env},
+    expand::symbols_in,
+    is_multi_arity,
+    printer::print_readably,
+    symbol::{self, Symbol},
+    types::{
+        MalRet,
+        MalVal::{self, List, MalFunc, Sym, Syntax},
+    },
+};
+
+const ELLIPSIS: &str = "...";
+
+// names `eval` treats specially; they are never renamed
+pub const SPECIAL_FORMS: &[&str] = &[
+    "def!",
+    "let*",
+    "quote",
+    "quasiquote",
+    "quasiquoteexpand",
+    "unquote",
+    "splice-unquote",
+    "do",
+    "if",
+    "fn*",
+    "loop",
+    "recur",
+    "try*",
+    "catch*",
+    "defmacro!",
+    "define-syntax",
+    "macroexpand",
+    "macroexpand-1",
+    "macroexpand-all",
+    "&",
+    "&optional",
+    "&key",
+];
+
+#[derive(Debug)]
+pub struct SyntaxRules {
+    literals: Vec<Symbol>,
+    // (pattern, template), tried in order
+    rules: Vec<(MalVal, MalVal)>,
+    // env the macro was defined in
+    env: Env,
+}
+
+impl SyntaxRules {
+    // env the macro was defined in
+    pub fn env(&self) -> &Env {
+        &self.env
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Binding {
+    One(MalVal),
+    // one binding per repetition of an ellipsis
+    Many(Vec<Binding>),
+}
+
+type Bindings = FnvHashMap<Symbol, Binding>;
+
+fn is_ellipsis(val: &MalVal) -> bool {
+    matches!(val, Sym(s, _) if s == ELLIPSIS)
+}
+
+// parse `(syntax-rules (literal ...) (pattern template) ...)`
+pub fn parse(form: &MalVal, env: &Env) -> Result<SyntaxRules> {
+    let list = match form {
+        List(list) if matches!(list.first(), Some(Sym(s, _)) if s == "syntax-rules") => list,
+        _ => bail!("define-syntax expects a syntax-rules form"),
+    };
+    let literals = match list.get(1) {
+        Some(List(lits)) => lits
+            .iter()
+            .map(|lit| match lit {
+                Sym(s, _) => Ok(*s),
+                _ => bail!("syntax-rules literals must be symbols"),
+            })
+            .collect::<Result<Vec<_>>>()?,
+        _ => bail!("syntax-rules expects a list of literals"),
+    };
+    let mut rules = vec![];
+    for rule in list[2..].iter() {
+        match rule {
+            List(rule) if rule.len() == 2 && matches!(rule[0], List(_)) => {
+                rules.push((rule[0].clone(), rule[1].clone()))
+            }
+            _ => bail!("invalid syntax-rules clause `{}`", print_readably(rule)),
+        }
+    }
+    Ok(SyntaxRules {
+        literals,
+        rules,
+        env: env.clone(),
+    })
+}
+
+// expand a use of the macro, `form`, appearing in `env`
+pub fn expand(rules: &SyntaxRules, form: &MalVal, env: &Env) -> MalRet {
+    let args = match form {
+        List(list) => &list[1..],
+        _ => bail!("invalid macro call"),
+    };
+    for (pattern, template) in rules.rules.iter() {
+        let mut binds = Bindings::default();
+        // the first element of the pattern stands for the macro name
+        let matched = match pattern {
+            List(pattern) => {
+                !pattern.is_empty() && match_list(rules, &pattern[1..], args, &mut binds)
+            }
+            _ => false,
+        };
+        if matched {
+            let mut binders = FnvHashSet::default();
+            template_binders(template, &binds, &mut binders);
+            let mut renamer = Renamer {
+                def_env: &rules.env,
+                use_env: env,
+                binders,
+                renames: FnvHashMap::default(),
+            };
+            return instantiate(template, &binds, &mut renamer);
+        }
+    }
+    bail!("no syntax-rules pattern matches `{}`", print_readably(form))
+}
+
+fn match_pattern(rules: &SyntaxRules, pat: &MalVal, form: &MalVal, binds: &mut Bindings) -> bool {
+    match pat {
+        Sym(s, _) if s == "_" => true,
+        Sym(s, _) if rules.literals.contains(s) => pat == form,
+        Sym(s, _) => {
+            binds.insert(*s, Binding::One(form.clone()));
+            true
+        }
+        List(pats) => match form {
+            List(forms) => match_list(rules, pats, forms, binds),
+            _ => false,
+        },
+        _ => pat == form,
+    }
+}
+
+fn match_list(
+    rules: &SyntaxRules,
+    pats: &[MalVal],
+    forms: &[MalVal],
+    binds: &mut Bindings,
+) -> bool {
+    let ellipsis = match pats.iter().position(is_ellipsis) {
+        Some(i) if i > 0 => i,
+        _ => {
+            return pats.len() == forms.len()
+                && pats
+                    .iter()
+                    .zip(forms)
+                    .all(|(p, f)| match_pattern(rules, p, f, binds))
+        }
+    };
+
+    // `before... sub ... after...`
+    let (before, sub, after) = (
+        &pats[..ellipsis - 1],
+        &pats[ellipsis - 1],
+        &pats[ellipsis + 1..],
+    );
+    if forms.len() < before.len() + after.len() {
+        return false;
+    }
+    let reps = forms.len() - before.len() - after.len();
+    if !match_list(rules, before, &forms[..before.len()], binds)
+        || !match_list(rules, after, &forms[before.len() + reps..], binds)
+    {
+        return false;
+    }
+
+    let mut matches = vec![];
+    for form in forms[before.len()..before.len() + reps].iter() {
+        let mut rep = Bindings::default();
+        if !match_pattern(rules, sub, form, &mut rep) {
+            return false;
+        }
+        matches.push(rep);
+    }
+    for var in pattern_vars(rules, sub) {
+        let reps = matches
+            .iter_mut()
+            .map(|rep| rep.remove(&var).unwrap_or(Binding::Many(vec![])))
+            .collect();
+        binds.insert(var, Binding::Many(reps));
+    }
+    true
+}
+
+fn pattern_vars(rules: &SyntaxRules, pat: &MalVal) -> Vec<Symbol> {
+    match pat {
+        Sym(s, _) if s != "_" && s != ELLIPSIS && !rules.literals.contains(s) => vec![*s],
+        List(pats) => pats.iter().flat_map(|p| pattern_vars(rules, p)).collect(),
+        _ => vec![],
+    }
+}
+
+// pattern variables in `template` bound to repetitions
+fn ellipsis_vars(template: &MalVal, binds: &Bindings) -> Vec<Symbol> {
+    match template {
+        Sym(s, _) if matches!(binds.get(s), Some(Binding::Many(_))) => vec![*s],
+        List(ts) => ts.iter().flat_map(|t| ellipsis_vars(t, binds)).collect(),
+        _ => vec![],
+    }
+}
+
+// the symbols `template` binds in the binding positions of `let*`, `loop`,
+// `fn*` and `catch*`, and of the prelude's binding macros; pattern
+// variables are the caller's and are left out
+fn template_binders(template: &MalVal, binds: &Bindings, res: &mut FnvHashSet<Symbol>) {
+    let List(ts) = template else {
+        return;
+    };
+    let mut patterns = vec![];
+    // `((pattern value) ...)`
+    let bindings = |bindings: Option<&MalVal>, patterns: &mut Vec<MalVal>| {
+        if let Some(List(bindings)) = bindings {
+            for binding in bindings.iter() {
+                if let List(b) = binding {
+                    patterns.extend(b.first().cloned());
+                }
+            }
+        }
+    };
+    match ts.first() {
+        Some(Sym(s, _)) if *s == symbol::LET || *s == symbol::LOOP || *s == "let" => {
+            match ts.get(1) {
+                // a named `let`
+                Some(name @ Sym(..)) => {
+                    patterns.push(name.clone());
+                    bindings(ts.get(2), &mut patterns);
+                }
+                binds => bindings(binds, &mut patterns),
+            }
+        }
+        Some(Sym(s, _)) if *s == symbol::FN && is_multi_arity(&ts[1..]) => {
+            for clause in ts[1..].iter() {
+                let List(clause) = clause else { unreachable!() };
+                patterns.push(clause[0].clone());
+            }
+        }
+        Some(Sym(s, _)) if *s == symbol::FN || *s == symbol::CATCH => {
+            patterns.extend(ts.get(1).cloned());
+        }
+        // `(dotimes (i n) ...)`, `(if-let (pattern value) ...)`...
+        Some(Sym(s, _)) if ["dotimes", "doseq", "if-let", "when-let"].contains(&s.as_str()) => {
+            if let Some(List(b)) = ts.get(1) {
+                patterns.extend(b.first().cloned());
+            }
+        }
+        _ => (),
+    }
+    let mut names = vec![];
+    for pattern in patterns.iter() {
+        symbols_in(pattern, &mut names);
+    }
+    res.extend(
+        names
+            .into_iter()
+            .filter(|s| s != ELLIPSIS && !binds.contains_key(s)),
+    );
+    for t in ts.iter() {
+        template_binders(t, binds, res);
+    }
+}
+
+fn instantiate(template: &MalVal, binds: &Bindings, renamer: &mut Renamer) -> MalRet {
+    match template {
+        Sym(s, _) => match binds.get(s) {
+            Some(Binding::One(val)) => Ok(val.clone()),
+            Some(Binding::Many(_)) => bail!("pattern variable `{}` used without `...`", s),
+            None => Ok(renamer.rename(*s)),
+        },
+        // `(... ...)` stands for a literal `...`
+        List(ts) if ts.len() == 2 && is_ellipsis(&ts[0]) => Ok(ts[1].clone()),
+        List(ts) => {
+            let mut res = vec![];
+            let mut i = 0;
+            while i < ts.len() {
+                if !ts.get(i + 1).is_some_and(is_ellipsis) {
+                    res.push(instantiate(&ts[i], binds, renamer)?);
+                    i += 1;
+                    continue;
+                }
+
+                let vars = ellipsis_vars(&ts[i], binds);
+                let mut reps = None;
+                for var in vars.iter() {
+                    if let Some(Binding::Many(b)) = binds.get(var) {
+                        match reps {
+                            Some(n) if n != b.len() => {
+                                bail!("pattern variables under `...` have different lengths")
+                            }
+                            _ => reps = Some(b.len()),
+                        }
+                    }
+                }
+                let Some(reps) = reps else {
+                    bail!("no pattern variable before `...` in template");
+                };
+                for k in 0..reps {
+                    let mut rep = binds.clone();
+                    for var in vars.iter() {
+                        if let Some(Binding::Many(b)) = binds.get(var) {
+                            rep.insert(*var, b[k].clone());
+                        }
+                    }
+                    res.push(instantiate(&ts[i], &rep, renamer)?);
+                }
+                i += 2;
+            }
+            Ok(list!(res))
+        }
+        _ => Ok(template.clone()),
+    }
+}
+
+struct Renamer<'a> {
+    def_env: &'a Env,
+    use_env: &'a Env,
+    // the symbols the template binds
+    binders: FnvHashSet<Symbol>,
+    // fresh names for them in this expansion
+    renames: FnvHashMap<Symbol, MalVal>,
+}
+
+impl Renamer<'_> {
+    fn rename(&mut self, s: Symbol) -> MalVal {
+        if SPECIAL_FORMS.contains(&s.as_str()) {
+            return Sym(s, None);
+        }
+        if self.binders.contains(&s) {
+            return self.renames.entry(s).or_insert_with(|| gensym(s)).clone();
+        }
+        let Some(def) = find_env(self.def_env, s) else {
+            return Sym(s, None);
+        };
+        match find_env(self.use_env, s) {
+            Some(used) if Rc::ptr_eq(&def, &used) => Sym(s, None),
+            // shadowed at the use site: refer to the definition-time value
+            _ => match get_env(&def, &Sym(s, None)) {
+                Ok(MalFunc { is_macro: true, .. }) | Ok(Syntax(_)) | Err(_) => Sym(s, None),
+                Ok(val) => list![Sym(symbol::QUOTE, None), val],
+            },
+        }
+    }
+}
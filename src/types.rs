@@ -1,33 +1,206 @@
-use std::rc::Rc;
+use std::{cell::RefCell, rc::Rc};
 
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
 
-use crate::env::Env;
+use crate::{
+    analyze::{Node, Pattern},
+    env::Env,
+    hamt::Hamt,
+    printer::print_readably,
+    seq::Seq,
+    symbol::Symbol,
+    syntax_rules::SyntaxRules,
+    vector::Vector,
+    vm::Closure,
+};
 
 #[derive(Debug, Clone)]
 pub enum MalVal {
     Nil,
     Bool(bool),
     Num(i64),
-    Sym(String),
-    RustFunc(fn(Vec<MalVal>) -> MalRet),
+    Str(String),
+    // a Unicode scalar value
+    Char(char),
+    // `:name`, stored without the colon
+    Kw(String),
+    // the metadata, such as a `^Long` hint, doesn't change which symbol
+    // it is
+    Sym(Symbol, Meta),
+    RustFunc(MalFn, Meta),
     MalFunc {
-        body: Rc<MalVal>,
-        params: Rc<MalVal>,
+        // (params, body) for each arity, tried in order
+        arities: Rc<Vec<(Pattern, Rc<Node>)>>,
         is_macro: bool,
         env: Env,
+        meta: Meta,
     },
-    List(Rc<Vec<MalVal>>),
+    List(Seq),
+    Vector(Vector),
+    // keys are encoded with `map_key`
+    Map(Hamt),
+    // each element under its `set_key`
+    Set(Hamt),
+    // a `syntax-rules` macro
+    Syntax(Rc<SyntaxRules>),
+    // a function compiled by the VM
+    VmFunc(Rc<Closure>),
+    // a mutable cell, made by `atom` and changed by `reset!` and `swap!`
+    Atom(Rc<RefCell<MalVal>>),
 }
 
 pub type MalRet = Result<MalVal>;
+// a map attached to a collection, symbol or function with `with-meta` or `^`
+pub type Meta = Option<Rc<MalVal>>;
+pub type MalFn = fn(Vec<MalVal>) -> MalRet;
 
 macro_rules! list {
   ($seq:expr) => {{
-    List(Rc::new($seq))
+    List($crate::seq::Seq::from($seq))
   }};
   [$($args:expr),*] => {{
     let v: Vec<MalVal> = vec![$($args),*];
-    List(Rc::new(v))
+    List($crate::seq::Seq::from(v))
   }}
 }
+
+// structural equality, ignoring metadata; a list and a vector are equal
+// when their elements are, and functions and atoms are equal only to
+// themselves
+impl PartialEq for MalVal {
+    fn eq(&self, other: &MalVal) -> bool {
+        match (self, other) {
+            (MalVal::Nil, MalVal::Nil) => true,
+            (MalVal::Bool(a), MalVal::Bool(b)) => a == b,
+            (MalVal::Num(a), MalVal::Num(b)) => a == b,
+            (MalVal::Str(a), MalVal::Str(b)) => a == b,
+            (MalVal::Char(a), MalVal::Char(b)) => a == b,
+            (MalVal::Kw(a), MalVal::Kw(b)) => a == b,
+            (MalVal::Sym(a, _), MalVal::Sym(b, _)) => a == b,
+            (MalVal::List(a), MalVal::List(b)) => a == b,
+            (MalVal::Vector(a), MalVal::Vector(b)) => a == b,
+            (MalVal::List(a), MalVal::Vector(b)) | (MalVal::Vector(b), MalVal::List(a)) => {
+                a.len() == b.len() && a.iter().eq(b.iter())
+            }
+            (MalVal::Map(a), MalVal::Map(b)) | (MalVal::Set(a), MalVal::Set(b)) => a == b,
+            (MalVal::RustFunc(a, _), MalVal::RustFunc(b, _)) => std::ptr::fn_addr_eq(*a, *b),
+            (
+                MalVal::MalFunc {
+                    arities: a,
+                    env: ea,
+                    ..
+                },
+                MalVal::MalFunc {
+                    arities: b,
+                    env: eb,
+                    ..
+                },
+            ) => Rc::ptr_eq(a, b) && Rc::ptr_eq(ea, eb),
+            (MalVal::Syntax(a), MalVal::Syntax(b)) => Rc::ptr_eq(a, b),
+            (MalVal::VmFunc(a), MalVal::VmFunc(b)) => a == b,
+            (MalVal::Atom(a), MalVal::Atom(b)) => Rc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+impl MalVal {
+    // the metadata of a collection, symbol or function
+    pub fn meta(&self) -> Option<&Rc<MalVal>> {
+        match self {
+            MalVal::Sym(_, meta) | MalVal::RustFunc(_, meta) => meta.as_ref(),
+            MalVal::List(l) => l.meta().as_ref(),
+            MalVal::Vector(v) => v.meta().as_ref(),
+            MalVal::Map(m) | MalVal::Set(m) => m.meta().as_ref(),
+            MalVal::MalFunc { meta, .. } => meta.as_ref(),
+            MalVal::VmFunc(closure) => closure.meta().as_ref(),
+            _ => None,
+        }
+    }
+
+    // the value with `meta`, a map or nil, as its metadata
+    pub fn with_meta(&self, meta: &MalVal) -> Result<MalVal> {
+        let meta = match meta {
+            MalVal::Map(_) => Some(Rc::new(meta.clone())),
+            MalVal::Nil => None,
+            _ => bail!("metadata must be a map, got `{}`", print_readably(meta)),
+        };
+        Ok(match self {
+            MalVal::Sym(s, _) => MalVal::Sym(*s, meta),
+            MalVal::RustFunc(f, _) => MalVal::RustFunc(*f, meta),
+            MalVal::List(l) => MalVal::List(l.with_meta(meta)),
+            MalVal::Vector(v) => MalVal::Vector(v.with_meta(meta)),
+            MalVal::Map(m) => MalVal::Map(m.with_meta(meta)),
+            MalVal::Set(s) => MalVal::Set(s.with_meta(meta)),
+            MalVal::MalFunc {
+                arities,
+                is_macro,
+                env,
+                ..
+            } => MalVal::MalFunc {
+                arities: arities.clone(),
+                is_macro: *is_macro,
+                env: env.clone(),
+                meta,
+            },
+            MalVal::VmFunc(closure) => MalVal::VmFunc(Rc::new(closure.with_meta(meta))),
+            _ => bail!("cannot attach metadata to `{}`", print_readably(self)),
+        })
+    }
+}
+
+// map keys are strings tagged with the type of the original key
+pub fn map_key(key: &MalVal) -> Result<String> {
+    Ok(match key {
+        MalVal::Str(s) => format!("s{}", s),
+        MalVal::Kw(s) => format!("k{}", s),
+        MalVal::Sym(s, _) => match s.gensym_number() {
+            None => format!("y{}", s),
+            Some(n) => format!("g{}:{}", n, s.as_str()),
+        },
+        MalVal::Num(n) => format!("n{}", n),
+        MalVal::Char(c) => format!("c{}", c),
+        _ => bail!("invalid map key `{}`", print_readably(key)),
+    })
+}
+
+// the key a set stores `elt` under
+pub fn set_key(elt: &MalVal) -> Result<String> {
+    map_key(elt).map_err(|_| anyhow!("invalid set element `{}`", print_readably(elt)))
+}
+
+// the value `map_key` encoded as `key`
+pub fn key_val(key: &str) -> MalVal {
+    let (tag, name) = key.split_at(1);
+    match tag {
+        "s" => MalVal::Str(name.to_owned()),
+        "k" => MalVal::Kw(name.to_owned()),
+        "y" => MalVal::Sym(Symbol::new(name), None),
+        "g" => {
+            let (n, prefix) = name.split_once(':').unwrap_or_default();
+            MalVal::Sym(
+                Symbol::from_gensym(Symbol::new(prefix), n.parse().unwrap_or_default()),
+                None,
+            )
+        }
+        "c" => MalVal::Char(name.chars().next().unwrap_or_default()),
+        _ => MalVal::Num(name.parse().unwrap_or_default()),
+    }
+}
+
+// the entries of `map`, sorted by key so that output is stable
+pub fn map_entries(map: &Hamt) -> Vec<(MalVal, MalVal)> {
+    let mut entries: Vec<_> = map.iter().collect();
+    entries.sort_by_key(|(k, _)| *k);
+    entries
+        .into_iter()
+        .map(|(k, v)| (key_val(k), v.clone()))
+        .collect()
+}
+
+// the elements of `set`, sorted like the keys of `map_entries`
+pub fn set_elements(set: &Hamt) -> Vec<MalVal> {
+    let mut entries: Vec<_> = set.iter().collect();
+    entries.sort_by_key(|(k, _)| *k);
+    entries.into_iter().map(|(_, v)| v.clone()).collect()
+}
@@ -0,0 +1,267 @@
+// Persistent vectors, as 32-way tries with a tail. The trie holds the
+// elements in leaves of 32, indexed 5 bits of the index per level; the last
+// elements are kept apart in the tail until there are 32 of them. `conj`
+// adds to the tail, or moves a full tail into the trie, and `assoc` copies
+// only the nodes on the path to the index, so both, and `nth`, are
+// O(log n). Updating a vector nothing else shares is done in place.
+
+use std::{fmt, rc::Rc};
+
+use crate::types::{MalVal, Meta};
+
+const BITS: u32 = 5;
+const WIDTH: usize = 1 << BITS;
+const MASK: usize = WIDTH - 1;
+
+#[derive(Clone)]
+pub enum Node {
+    Branch(Vec<Rc<Node>>),
+    // 32 elements
+    Leaf(Vec<MalVal>),
+}
+
+#[derive(Clone)]
+pub struct Vector {
+    root: Rc<Node>,
+    // the elements after the trie's
+    tail: Rc<Vec<MalVal>>,
+    len: usize,
+    // how far the index is shifted for the root's children
+    shift: u32,
+    // kept by `push` and `set`
+    meta: Meta,
+}
+
+impl Node {
+    // call `nodes` on each child node and `values` on each value held here
+    pub fn for_each_child(
+        &self,
+        mut nodes: impl FnMut(&Rc<Node>),
+        mut values: impl FnMut(&MalVal),
+    ) {
+        match self {
+            Node::Branch(children) => children.iter().for_each(&mut nodes),
+            Node::Leaf(vals) => vals.iter().for_each(&mut values),
+        }
+    }
+
+    fn children(&mut self) -> &mut Vec<Rc<Node>> {
+        match self {
+            Node::Branch(children) => children,
+            Node::Leaf(_) => unreachable!("a leaf above the bottom level"),
+        }
+    }
+}
+
+// `leaf` under as many single-child branches as make `level` levels
+fn new_path(level: u32, leaf: Rc<Node>) -> Rc<Node> {
+    match level {
+        0 => leaf,
+        _ => Rc::new(Node::Branch(vec![new_path(level - BITS, leaf)])),
+    }
+}
+
+// add `leaf`, holding the elements from `idx` on, to the trie at `node`
+fn push_leaf(node: &mut Rc<Node>, level: u32, idx: usize, leaf: Rc<Node>) {
+    let children = Rc::make_mut(node).children();
+    let sub = (idx >> level) & MASK;
+    if level == BITS {
+        children.push(leaf);
+    } else if sub < children.len() {
+        push_leaf(&mut children[sub], level - BITS, idx, leaf);
+    } else {
+        children.push(new_path(level - BITS, leaf));
+    }
+}
+
+fn set(node: &mut Rc<Node>, level: u32, idx: usize, val: MalVal) {
+    match Rc::make_mut(node) {
+        Node::Branch(children) => set(&mut children[(idx >> level) & MASK], level - BITS, idx, val),
+        Node::Leaf(vals) => vals[idx & MASK] = val,
+    }
+}
+
+impl Vector {
+    pub fn new() -> Vector {
+        Vector {
+            root: Rc::new(Node::Branch(vec![])),
+            tail: Rc::new(vec![]),
+            len: 0,
+            shift: BITS,
+            meta: None,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // the index of the first element in the tail
+    fn tail_offset(&self) -> usize {
+        self.len - self.tail.len()
+    }
+
+    // the leaf holding the element at `idx`, which is before the tail
+    fn leaf(&self, idx: usize) -> &[MalVal] {
+        let mut node = &self.root;
+        let mut level = self.shift;
+        loop {
+            match &**node {
+                Node::Branch(children) => node = &children[(idx >> level) & MASK],
+                Node::Leaf(vals) => return vals,
+            }
+            level -= BITS;
+        }
+    }
+
+    pub fn get(&self, idx: usize) -> Option<&MalVal> {
+        if idx >= self.len {
+            None
+        } else if idx >= self.tail_offset() {
+            self.tail.get(idx - self.tail_offset())
+        } else {
+            Some(&self.leaf(idx)[idx & MASK])
+        }
+    }
+
+    pub fn push(&mut self, val: MalVal) {
+        if self.tail.len() == WIDTH {
+            let idx = self.tail_offset();
+            let tail = std::mem::replace(&mut self.tail, Rc::new(Vec::with_capacity(WIDTH)));
+            let leaf = Rc::new(Node::Leaf(Rc::unwrap_or_clone(tail)));
+            if idx >> BITS == 1 << self.shift {
+                // the trie is full: it becomes the first child of a new root
+                let root = self.root.clone();
+                let path = new_path(self.shift, leaf);
+                self.root = Rc::new(Node::Branch(vec![root, path]));
+                self.shift += BITS;
+            } else {
+                push_leaf(&mut self.root, self.shift, idx, leaf);
+            }
+        }
+        Rc::make_mut(&mut self.tail).push(val);
+        self.len += 1;
+    }
+
+    // replace the element at `idx`, which must be in range
+    pub fn set(&mut self, idx: usize, val: MalVal) {
+        assert!(idx < self.len, "index {} out of range", idx);
+        match idx.checked_sub(self.tail_offset()) {
+            Some(i) => Rc::make_mut(&mut self.tail)[i] = val,
+            None => set(&mut self.root, self.shift, idx, val),
+        }
+    }
+
+    pub fn iter(&self) -> Iter<'_> {
+        Iter {
+            vector: self,
+            idx: 0,
+            leaf: [].iter(),
+        }
+    }
+
+    // the root of the trie and the tail, for the cycle collector
+    pub fn root(&self) -> &Rc<Node> {
+        &self.root
+    }
+
+    pub fn tail(&self) -> &Rc<Vec<MalVal>> {
+        &self.tail
+    }
+
+    pub fn meta(&self) -> &Meta {
+        &self.meta
+    }
+
+    // the same vector with `meta` instead of its metadata
+    pub fn with_meta(&self, meta: Meta) -> Vector {
+        Vector {
+            meta,
+            ..self.clone()
+        }
+    }
+}
+
+impl Default for Vector {
+    fn default() -> Vector {
+        Vector::new()
+    }
+}
+
+// reads a leaf at a time
+pub struct Iter<'a> {
+    vector: &'a Vector,
+    // the index of the first element after `leaf`
+    idx: usize,
+    leaf: std::slice::Iter<'a, MalVal>,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = &'a MalVal;
+
+    fn next(&mut self) -> Option<&'a MalVal> {
+        if let Some(val) = self.leaf.next() {
+            return Some(val);
+        }
+        let vector = self.vector;
+        if self.idx >= vector.len {
+            return None;
+        }
+        let leaf = match self.idx >= vector.tail_offset() {
+            true => &vector.tail[..],
+            false => vector.leaf(self.idx),
+        };
+        self.idx += leaf.len();
+        self.leaf = leaf.iter();
+        self.leaf.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.vector.len - self.idx + self.leaf.len();
+        (len, Some(len))
+    }
+}
+
+impl ExactSizeIterator for Iter<'_> {}
+
+impl<'a> IntoIterator for &'a Vector {
+    type Item = &'a MalVal;
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Iter<'a> {
+        self.iter()
+    }
+}
+
+impl FromIterator<MalVal> for Vector {
+    fn from_iter<I: IntoIterator<Item = MalVal>>(iter: I) -> Vector {
+        let mut vector = Vector::new();
+        for val in iter {
+            vector.push(val);
+        }
+        vector
+    }
+}
+
+impl From<Vec<MalVal>> for Vector {
+    fn from(vals: Vec<MalVal>) -> Vector {
+        vals.into_iter().collect()
+    }
+}
+
+// metadata doesn't take part in equality
+impl PartialEq for Vector {
+    fn eq(&self, other: &Vector) -> bool {
+        self.len == other.len && self.iter().eq(other.iter())
+    }
+}
+
+impl fmt::Debug for Vector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
@@ -0,0 +1,937 @@
+// A bytecode compiler and stack-based VM, selectable instead of the
+// tree-walking `eval` with `Backend::Vm`.
+//
+// Each top-level form is analyzed and compiled on its own; the analyzer
+// resolves every local variable to a (depth, index) slot in a chain of
+// frames, and only globals are looked up by name. Every scope (function call,
+// `let*`, `loop` iteration, `catch*`) gets its own frame, so closures capture
+// exactly what they would in the tree-walker. Calls between VM functions
+// don't recurse on the Rust stack, and calls in tail position reuse the
+// caller's activation.
+//
+// The VM shares values with the tree-walker: it calls `MalFunc`s and
+// builtins, the tree-walker calls `VmFunc`s, and forms the compiler doesn't
+// handle (`define-syntax`, `macroexpand`...) are run by the tree-walker in
+// an env holding the visible locals.
+
+use std::{cell::RefCell, rc::Rc};
+
+use anyhow::{bail, Result};
+
+use crate::{
+    analyze::{analyze, Node, Pattern},
+    env::{arity, bind_fn, bind_into, describe_arity, get_env, new_env, set_env, Env, Target},
+    exec,
+    expand::symbols_in,
+    gc,
+    hamt::Hamt,
+    limits::{allocate, tick},
+    profile::{self, Timer},
+    stack::{max_depth, Depth},
+    symbol::{self, Symbol},
+    types::{
+        MalRet,
+        MalVal::{self, Bool, List, MalFunc, Map, Nil, RustFunc, Str, Sym, VmFunc},
+        Meta,
+    },
+};
+
+// locals of one scope; a slot is empty until its name is bound
+#[derive(Debug)]
+pub struct Frame {
+    slots: RefCell<Vec<Option<MalVal>>>,
+    parent: Option<Rc<Frame>>,
+}
+
+impl Frame {
+    pub fn parent(&self) -> Option<&Rc<Frame>> {
+        self.parent.as_ref()
+    }
+
+    // call `f` on each local, without cloning them
+    pub fn for_each_value(&self, f: impl FnMut(&MalVal)) {
+        self.slots.borrow().iter().flatten().for_each(f);
+    }
+
+    pub fn clear(&self) {
+        let slots = std::mem::take(&mut *self.slots.borrow_mut());
+        drop(slots);
+    }
+}
+
+fn new_frame(nslots: usize, parent: Option<Rc<Frame>>) -> Result<Rc<Frame>> {
+    allocate(nslots)?;
+    let frame = Rc::new(Frame {
+        slots: RefCell::new(vec![None; nslots]),
+        parent,
+    });
+    gc::register_frame(&frame);
+    Ok(frame)
+}
+
+// the frame `depth` levels up from `frame`
+fn frame_at(frame: &Option<Rc<Frame>>, depth: usize) -> &Rc<Frame> {
+    let mut frame = frame.as_ref().expect("local outside of a frame");
+    for _ in 0..depth {
+        frame = frame.parent.as_ref().expect("local outside of a frame");
+    }
+    frame
+}
+
+#[derive(Debug, Clone)]
+pub struct Closure {
+    proto: Rc<Proto>,
+    frame: Option<Rc<Frame>>,
+    // where globals are looked up
+    env: Env,
+    pub is_macro: bool,
+    meta: Meta,
+}
+
+impl Closure {
+    pub fn as_macro(&self) -> Closure {
+        Closure {
+            is_macro: true,
+            ..self.clone()
+        }
+    }
+
+    pub fn meta(&self) -> &Meta {
+        &self.meta
+    }
+
+    pub fn with_meta(&self, meta: Meta) -> Closure {
+        Closure {
+            meta,
+            ..self.clone()
+        }
+    }
+
+    pub fn frame(&self) -> Option<&Rc<Frame>> {
+        self.frame.as_ref()
+    }
+
+    pub fn env(&self) -> &Env {
+        &self.env
+    }
+
+    // the same for every closure of one `fn*`
+    pub fn id(&self) -> usize {
+        Rc::as_ptr(&self.proto) as usize
+    }
+}
+
+// the same function, whether or not it is a macro or has metadata
+impl PartialEq for Closure {
+    fn eq(&self, other: &Closure) -> bool {
+        let frame = |c: &Closure| c.frame.as_ref().map(Rc::as_ptr);
+        Rc::ptr_eq(&self.proto, &other.proto)
+            && frame(self) == frame(other)
+            && Rc::ptr_eq(&self.env, &other.env)
+    }
+}
+
+// a compiled `fn*`
+#[derive(Debug)]
+struct Proto {
+    arities: Vec<Arity>,
+}
+
+#[derive(Debug)]
+struct Arity {
+    min: usize,
+    max: Option<usize>,
+    // only plain symbols and `& rest`: args go straight into slots;
+    // otherwise the chunk starts by binding the list of args
+    simple: bool,
+    nslots: usize,
+    chunk: Chunk,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Op {
+    Const(usize),
+    // (depth, index, name), the name for errors
+    Local(usize, usize, usize),
+    StoreLocal(usize, usize),
+    // store into the current frame, keeping the value
+    DefLocal(usize),
+    Global(usize),
+    DefGlobal(usize),
+    MakeMacro,
+    MakeMap(usize),
+    Pop,
+    Jump(usize),
+    JumpIfFalse(usize),
+    Call(usize),
+    TailCall(usize),
+    Return,
+    Closure(usize),
+    PushFrame(usize),
+    PopFrame,
+    // bind a pattern in the current frame; pops the value
+    Bind(usize),
+    // jump to a `loop`'s rebinding code with a fresh frame for it,
+    // `up` frames above the current one
+    Recur { up: usize, target: usize },
+    Try(usize),
+    EndTry,
+    Fallback(usize),
+}
+
+#[derive(Debug, Default)]
+struct Chunk {
+    ops: Vec<Op>,
+    consts: Vec<MalVal>,
+    // names of globals, and of locals for errors
+    names: Vec<Symbol>,
+    protos: Vec<Rc<Proto>>,
+    binders: Vec<Binder>,
+    // encoded keys of map literals
+    map_keys: Vec<Vec<String>>,
+    // forms left to the tree-walker, with the locals they can see
+    fallbacks: Vec<(Rc<Node>, Visible)>,
+}
+
+// (name, depth, index) of the locals in scope, outermost first
+type Visible = Vec<(Symbol, usize, usize)>;
+
+// a destructuring pattern, bound with `bind_into` in the current frame
+#[derive(Debug)]
+struct Binder {
+    pattern: MalVal,
+    // its defaults, run in the frame as it is filled
+    defaults: Vec<Rc<Proto>>,
+    // (name, index) of the slots of the current frame to fill
+    targets: Vec<(Symbol, usize)>,
+}
+
+// the current frame, filled by `bind_into`
+struct FrameTarget<'a> {
+    binder: &'a Binder,
+    frame: &'a Option<Rc<Frame>>,
+    env: &'a Env,
+}
+
+impl Target for FrameTarget<'_> {
+    fn set(&mut self, name: Symbol, val: MalVal) -> Result<()> {
+        let mut slots = frame_at(self.frame, 0).slots.borrow_mut();
+        for (_, idx) in self.binder.targets.iter().filter(|(n, _)| *n == name) {
+            slots[*idx] = Some(val.clone());
+        }
+        Ok(())
+    }
+
+    fn default(&mut self, idx: usize) -> MalRet {
+        run(Activation {
+            proto: self.binder.defaults[idx].clone(),
+            arity: 0,
+            pc: 0,
+            frame: self.frame.clone(),
+            env: self.env.clone(),
+            base: 0,
+            _timer: None,
+        })
+    }
+}
+
+// scratch env for the tree-walker holding the visible locals
+fn visible_env(visible: &Visible, frame: &Option<Rc<Frame>>, env: &Env) -> Result<Env> {
+    let scratch = new_env(Some(env.clone()));
+    for (name, depth, idx) in visible.iter() {
+        if let Some(val) = frame_at(frame, *depth).slots.borrow()[*idx].clone() {
+            set_env(&scratch, Sym(*name, None), val)?;
+        }
+    }
+    Ok(scratch)
+}
+
+//==================================================================
+// compiler
+
+struct Scope {
+    // the analyzer's slots for a `let*`, `loop` or `catch*`; a function's
+    // are declared as it is compiled, in the same order
+    names: Vec<Symbol>,
+    // slots of `let*` and `loop` names whose value isn't bound yet
+    unbound: Vec<usize>,
+    // the `loop` whose body this scope is: (pc of its rebinding code, arity)
+    recur: Option<(usize, usize)>,
+}
+
+struct Compiler {
+    scopes: Vec<Scope>,
+    // scopes below this belong to enclosing functions
+    fn_base: usize,
+    chunk: Chunk,
+}
+
+impl Compiler {
+    fn emit(&mut self, op: Op) -> usize {
+        self.chunk.ops.push(op);
+        self.chunk.ops.len() - 1
+    }
+
+    fn constant(&mut self, val: MalVal) {
+        self.chunk.consts.push(val);
+        let idx = self.chunk.consts.len() - 1;
+        self.emit(Op::Const(idx));
+    }
+
+    fn patch(&mut self, at: usize) {
+        let here = self.chunk.ops.len();
+        match &mut self.chunk.ops[at] {
+            Op::Jump(to) | Op::JumpIfFalse(to) | Op::Try(to) => *to = here,
+            _ => unreachable!(),
+        }
+    }
+
+    // the locals code compiled here sees, for the tree-walker
+    fn visible(&self) -> Visible {
+        let mut res = vec![];
+        for (i, scope) in self.scopes.iter().enumerate() {
+            let depth = self.scopes.len() - 1 - i;
+            for (idx, name) in scope.names.iter().enumerate() {
+                if i < self.fn_base || !scope.unbound.contains(&idx) {
+                    res.push((*name, depth, idx));
+                }
+            }
+        }
+        res
+    }
+
+    fn declare(&mut self, name: Symbol) -> usize {
+        let scope = self.scopes.last_mut().unwrap();
+        scope.names.push(name);
+        scope.names.len() - 1
+    }
+
+    // open a scope with `slots`, compile `f` in it and close it
+    fn scoped(&mut self, slots: &[Symbol], f: impl FnOnce(&mut Self) -> Result<()>) -> Result<()> {
+        self.emit(Op::PushFrame(slots.len()));
+        self.scopes.push(Scope {
+            names: slots.to_vec(),
+            unbound: vec![],
+            recur: None,
+        });
+        let ret = f(self);
+        self.scopes.pop();
+        ret?;
+        self.emit(Op::PopFrame);
+        Ok(())
+    }
+
+    // the first slot of each pattern of `binds`, whose names take the
+    // first slots of the scope in order; all are unbound
+    fn declare_unbound(&mut self, binds: &[(Pattern, Rc<Node>)]) -> Vec<usize> {
+        let mut firsts = vec![];
+        let mut next = 0;
+        for (pattern, _) in binds.iter() {
+            firsts.push(next);
+            let mut names = vec![];
+            symbols_in(&pattern.form, &mut names);
+            next += names.len();
+        }
+        self.scopes.last_mut().unwrap().unbound = (0..next).collect();
+        firsts
+    }
+
+    // bind `pattern` to the value on the stack, in the slots of the
+    // current scope from `first` on
+    fn bind(&mut self, pattern: &Pattern, first: usize) -> Result<()> {
+        let mut names = vec![];
+        symbols_in(&pattern.form, &mut names);
+        let defaults = pattern
+            .defaults
+            .iter()
+            .map(|default| self.compile_default(default))
+            .collect::<Result<_>>()?;
+        let slots = first..first + names.len();
+        self.scopes
+            .last_mut()
+            .unwrap()
+            .unbound
+            .retain(|idx| !slots.contains(idx));
+        if let Sym(..) = pattern.form {
+            self.emit(Op::StoreLocal(0, first));
+            return Ok(());
+        }
+        let targets = names.into_iter().zip(slots).collect();
+        self.chunk.binders.push(Binder {
+            pattern: pattern.form.clone(),
+            defaults,
+            targets,
+        });
+        let idx = self.chunk.binders.len() - 1;
+        self.emit(Op::Bind(idx));
+        Ok(())
+    }
+
+    // a default of a pattern, to be run in the frame it binds
+    fn compile_default(&mut self, node: &Rc<Node>) -> Result<Rc<Proto>> {
+        let saved_chunk = std::mem::take(&mut self.chunk);
+        let ret = self.compile(node, false);
+        self.emit(Op::Return);
+        let chunk = std::mem::replace(&mut self.chunk, saved_chunk);
+        ret?;
+        Ok(thunk(chunk))
+    }
+
+    // `tail` is whether `node` is in tail position of the function, so a
+    // call can reuse its activation
+    fn compile(&mut self, node: &Rc<Node>, tail: bool) -> Result<()> {
+        match &**node {
+            Node::Const(val) => self.constant(val.clone()),
+            Node::Var(s) => {
+                self.chunk.names.push(*s);
+                self.emit(Op::Global(self.chunk.names.len() - 1));
+            }
+            Node::Local(name, depth, idx) => {
+                debug_assert_eq!(
+                    self.scopes[self.scopes.len() - 1 - depth].names[*idx],
+                    *name,
+                    "the compiler's slots differ from the analyzer's"
+                );
+                self.chunk.names.push(*name);
+                self.emit(Op::Local(*depth, *idx, self.chunk.names.len() - 1));
+            }
+            Node::Map(entries) => {
+                let mut keys = vec![];
+                for (k, v) in entries.iter() {
+                    self.compile(v, false)?;
+                    keys.push(k.clone());
+                }
+                self.chunk.map_keys.push(keys);
+                self.emit(Op::MakeMap(self.chunk.map_keys.len() - 1));
+            }
+            Node::Def(name, val) | Node::DefMacro(name, val) => {
+                // declared before the value is compiled, so it can refer
+                // to itself
+                let local = self
+                    .scopes
+                    .last()
+                    .map(|scope| scope.names.iter().rposition(|n| n == name));
+                let local = match local {
+                    Some(Some(idx)) => Some(idx),
+                    Some(None) => Some(self.declare(*name)),
+                    None => None,
+                };
+                self.compile(val, false)?;
+                if matches!(**node, Node::DefMacro(..)) {
+                    self.emit(Op::MakeMacro);
+                }
+                match local {
+                    Some(idx) => self.emit(Op::DefLocal(idx)),
+                    None => {
+                        self.chunk.names.push(*name);
+                        self.emit(Op::DefGlobal(self.chunk.names.len() - 1))
+                    }
+                };
+            }
+            Node::Let(slots, binds, body) => {
+                self.scoped(slots, |c| {
+                    let firsts = c.declare_unbound(binds);
+                    for ((pattern, val), first) in binds.iter().zip(firsts) {
+                        c.compile(val, false)?;
+                        c.bind(pattern, first)?;
+                    }
+                    c.compile(body, tail)
+                })?;
+            }
+            Node::Loop(target) => {
+                self.scoped(&target.slots, |c| {
+                    let firsts = c.declare_unbound(&target.binds);
+                    for ((pattern, init), &first) in target.binds.iter().zip(firsts.iter()) {
+                        c.compile(init, false)?;
+                        c.bind(pattern, first)?;
+                    }
+                    let skip = c.emit(Op::Jump(0));
+                    // `recur` leaves the new values on the stack, the last
+                    // one on top; each is bound to the same slots as the
+                    // first time round
+                    let rebind = c.chunk.ops.len();
+                    for ((pattern, _), &first) in target.binds.iter().zip(firsts.iter()).rev() {
+                        c.bind(pattern, first)?;
+                    }
+                    c.patch(skip);
+                    c.scopes.last_mut().unwrap().recur = Some((rebind, target.binds.len()));
+                    c.compile(&target.body, tail)
+                })?;
+            }
+            Node::Recur(args) => {
+                // the analyzer only allows `recur` in tail position of a `loop`
+                let (up, target) = self.scopes[self.fn_base..]
+                    .iter()
+                    .rev()
+                    .enumerate()
+                    .find_map(|(up, scope)| Some((up, scope.recur?.0)))
+                    .expect("`recur` outside of a `loop`");
+                for arg in args.iter() {
+                    self.compile(arg, false)?;
+                }
+                self.emit(Op::Recur { up, target });
+            }
+            Node::Try(body, pattern, slots, handler) => {
+                let to_handler = self.emit(Op::Try(0));
+                self.compile(body, false)?;
+                self.emit(Op::EndTry);
+                let to_end = self.emit(Op::Jump(0));
+                // the error message is on the stack
+                self.patch(to_handler);
+                self.scoped(slots, |c| {
+                    c.bind(pattern, 0)?;
+                    c.compile(handler, tail)
+                })?;
+                self.patch(to_end);
+            }
+            Node::Do(forms) => {
+                let (last, forms) = forms.split_last().unwrap();
+                for form in forms {
+                    self.compile(form, false)?;
+                    self.emit(Op::Pop);
+                }
+                self.compile(last, tail)?;
+            }
+            Node::If(test, then, other) => {
+                self.compile(test, false)?;
+                let to_else = self.emit(Op::JumpIfFalse(0));
+                self.compile(then, tail)?;
+                let to_end = self.emit(Op::Jump(0));
+                self.patch(to_else);
+                self.compile(other, tail)?;
+                self.patch(to_end);
+            }
+            Node::Fn(arities) => {
+                let proto = self.compile_fn(arities)?;
+                self.chunk.protos.push(Rc::new(proto));
+                self.emit(Op::Closure(self.chunk.protos.len() - 1));
+            }
+            Node::DefineSyntax(..) | Node::Expand(..) => {
+                self.chunk.fallbacks.push((node.clone(), self.visible()));
+                self.emit(Op::Fallback(self.chunk.fallbacks.len() - 1));
+            }
+            Node::Call(func, args) => {
+                self.compile(func, false)?;
+                for arg in args.iter() {
+                    self.compile(arg, false)?;
+                }
+                self.emit(if tail {
+                    Op::TailCall(args.len())
+                } else {
+                    Op::Call(args.len())
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn compile_fn(&mut self, clauses: &[(Pattern, Rc<Node>)]) -> Result<Proto> {
+        let mut arities = vec![];
+        for (pattern, body) in clauses.iter() {
+            let params = &pattern.form;
+            let (min, max) = arity(params)?;
+            let simple = match params {
+                Sym(..) => true,
+                List(l) => {
+                    let n = l.len();
+                    l.iter().enumerate().all(|(i, p)| match p {
+                        Sym(symbol::AMP, _) => i + 2 == n,
+                        Sym(s, _) => !matches!(*s, symbol::AMP_OPTIONAL | symbol::AMP_KEY),
+                        _ => false,
+                    })
+                }
+                _ => false,
+            };
+
+            let saved_chunk = std::mem::take(&mut self.chunk);
+            let saved_base = std::mem::replace(&mut self.fn_base, self.scopes.len());
+            self.scopes.push(Scope {
+                names: vec![],
+                unbound: vec![],
+                recur: None,
+            });
+            let ret: Result<()> = (|| {
+                match (params, simple) {
+                    (Sym(s, _), _) => {
+                        self.declare(*s);
+                    }
+                    (List(l), true) => l
+                        .iter()
+                        .filter(|p| !matches!(p, Sym(symbol::AMP, _)))
+                        .for_each(|p| {
+                            let Sym(s, _) = p else { unreachable!() };
+                            self.declare(*s);
+                        }),
+                    _ => {
+                        let mut names = vec![];
+                        symbols_in(params, &mut names);
+                        names.into_iter().for_each(|name| {
+                            self.declare(name);
+                        });
+                        self.bind(pattern, 0)?;
+                    }
+                }
+                self.compile(body, true)?;
+                self.emit(Op::Return);
+                Ok(())
+            })();
+            let nslots = self.scopes.pop().unwrap().names.len();
+            self.fn_base = saved_base;
+            let chunk = std::mem::replace(&mut self.chunk, saved_chunk);
+            ret?;
+            arities.push(Arity {
+                min,
+                max,
+                simple: simple || matches!(params, Sym(..)),
+                nslots,
+                chunk,
+            });
+        }
+        Ok(Proto { arities })
+    }
+}
+
+// compile an analyzed top-level form to a function of no arguments
+fn compile_top(node: &Rc<Node>) -> Result<Rc<Proto>> {
+    let mut compiler = Compiler {
+        scopes: vec![],
+        fn_base: 0,
+        chunk: Chunk::default(),
+    };
+    compiler.compile(node, true)?;
+    compiler.emit(Op::Return);
+    Ok(thunk(compiler.chunk))
+}
+
+// a function of no arguments running `chunk` in the frame it is run with
+fn thunk(chunk: Chunk) -> Rc<Proto> {
+    Rc::new(Proto {
+        arities: vec![Arity {
+            min: 0,
+            max: Some(0),
+            simple: true,
+            nslots: 0,
+            chunk,
+        }],
+    })
+}
+
+//==================================================================
+// VM
+
+#[derive(Clone)]
+struct Activation {
+    proto: Rc<Proto>,
+    arity: usize,
+    pc: usize,
+    frame: Option<Rc<Frame>>,
+    env: Env,
+    // start of its part of the operand stack
+    base: usize,
+    // for the profiler; shared with the activations of its `catch*`
+    // handlers, so the call is timed until all of them are gone
+    _timer: Option<Rc<Timer>>,
+}
+
+impl Activation {
+    fn chunk(&self) -> &Chunk {
+        &self.proto.arities[self.arity].chunk
+    }
+}
+
+struct Handler {
+    // activations below it
+    depth: usize,
+    stack: usize,
+    act: Activation,
+}
+
+struct Vm {
+    stack: Vec<MalVal>,
+    calls: Vec<Activation>,
+    handlers: Vec<Handler>,
+}
+
+// evaluate `ast` with the VM; `do` forms at top level are evaluated one
+// form at a time, so a macro they define can be used by the forms after it
+pub fn eval_vm(ast: MalVal, env: Env) -> MalRet {
+    match &ast {
+        List(list) if matches!(list.first(), Some(Sym(symbol::DO, _))) => {
+            let mut ret = Nil;
+            for form in list[1..].iter() {
+                ret = eval_vm(form.clone(), env.clone())?;
+            }
+            Ok(ret)
+        }
+        _ => {
+            let proto = compile_top(&analyze(&ast, &env)?)?;
+            run(Activation {
+                proto,
+                arity: 0,
+                pc: 0,
+                frame: None,
+                env,
+                base: 0,
+                _timer: None,
+            })
+        }
+    }
+}
+
+// call a VM function from Rust
+pub fn call(closure: &Closure, args: Vec<MalVal>) -> MalRet {
+    let mut stack = vec![];
+    let act = enter(closure, args, &mut stack, 0)?;
+    let mut vm = Vm {
+        stack,
+        calls: vec![],
+        handlers: vec![],
+    };
+    vm.execute(act)
+}
+
+fn run(act: Activation) -> MalRet {
+    let mut vm = Vm {
+        stack: vec![],
+        calls: vec![],
+        handlers: vec![],
+    };
+    vm.execute(act)
+}
+
+// the activation for calling `closure` with `args`; a non-simple arity finds
+// its args as a list on the stack
+fn enter(
+    closure: &Closure,
+    args: Vec<MalVal>,
+    stack: &mut Vec<MalVal>,
+    base: usize,
+) -> Result<Activation> {
+    let n = args.len();
+    let Some(ai) = closure
+        .proto
+        .arities
+        .iter()
+        .position(|a| n >= a.min && a.max.is_none_or(|max| n <= max))
+    else {
+        let accepted: Vec<String> = closure
+            .proto
+            .arities
+            .iter()
+            .map(|a| describe_arity((a.min, a.max)))
+            .collect();
+        bail!(
+            "wrong number of args ({}) passed to function accepting {}",
+            n,
+            accepted.join(", ")
+        );
+    };
+    let arity = &closure.proto.arities[ai];
+    let frame = new_frame(arity.nslots, closure.frame.clone())?;
+    if arity.simple {
+        let mut slots = frame.slots.borrow_mut();
+        let mut args = args.into_iter();
+        for slot in slots.iter_mut().take(arity.min) {
+            *slot = args.next();
+        }
+        if arity.max.is_none() {
+            slots[arity.min] = Some(List(args.collect()));
+        }
+    } else {
+        stack.push(List(args.into()));
+    }
+    Ok(Activation {
+        proto: closure.proto.clone(),
+        arity: ai,
+        pc: 0,
+        frame: Some(frame),
+        env: closure.env.clone(),
+        base,
+        _timer: profile::enter_id(closure.id()).map(Rc::new),
+    })
+}
+
+impl Vm {
+    fn execute(&mut self, mut act: Activation) -> MalRet {
+        let _depth = Depth::enter()?;
+        loop {
+            match self.run_until_error(&mut act) {
+                Ok(val) => return Ok(val),
+                Err(err) => match self.handlers.pop() {
+                    Some(handler) => {
+                        self.calls.truncate(handler.depth);
+                        self.stack.truncate(handler.stack);
+                        self.stack.push(Str(format!("{:#}", err)));
+                        act = handler.act;
+                    }
+                    None => return Err(err),
+                },
+            }
+        }
+    }
+
+    fn pop_args(&mut self, argc: usize) -> (MalVal, Vec<MalVal>) {
+        let args = self.stack.split_off(self.stack.len() - argc);
+        let func = self.stack.pop().unwrap();
+        (func, args)
+    }
+
+    fn run_until_error(&mut self, act: &mut Activation) -> MalRet {
+        loop {
+            let op = act.chunk().ops[act.pc];
+            act.pc += 1;
+            match op {
+                Op::Const(i) => self.stack.push(act.chunk().consts[i].clone()),
+                Op::Local(depth, idx, name) => {
+                    let val = frame_at(&act.frame, depth).slots.borrow()[idx].clone();
+                    match val {
+                        Some(val) => self.stack.push(val),
+                        None => bail!("`{}` used before it is bound", act.chunk().names[name]),
+                    }
+                }
+                Op::StoreLocal(depth, idx) => {
+                    let val = self.stack.pop().unwrap();
+                    frame_at(&act.frame, depth).slots.borrow_mut()[idx] = Some(val);
+                }
+                Op::DefLocal(idx) => {
+                    let val = self.stack.last().unwrap().clone();
+                    frame_at(&act.frame, 0).slots.borrow_mut()[idx] = Some(val);
+                }
+                Op::Global(i) => {
+                    let name = Sym(act.chunk().names[i], None);
+                    self.stack.push(get_env(&act.env, &name)?);
+                }
+                Op::DefGlobal(i) => {
+                    let name = act.chunk().names[i];
+                    let val = self.stack.last().unwrap().clone();
+                    profile::name(&val, name);
+                    set_env(&act.env, Sym(name, None), val)?;
+                }
+                Op::MakeMacro => {
+                    let val = match self.stack.pop().unwrap() {
+                        MalFunc {
+                            arities, env, meta, ..
+                        } => MalFunc {
+                            arities,
+                            is_macro: true,
+                            env,
+                            meta,
+                        },
+                        VmFunc(c) => VmFunc(Rc::new(c.as_macro())),
+                        _ => bail!("set macro on non-func"),
+                    };
+                    self.stack.push(val);
+                }
+                Op::MakeMap(i) => {
+                    let keys = &act.chunk().map_keys[i];
+                    allocate(keys.len())?;
+                    let vals = self.stack.split_off(self.stack.len() - keys.len());
+                    let map: Hamt = keys.iter().cloned().zip(vals).collect();
+                    self.stack.push(Map(map));
+                }
+                Op::Pop => {
+                    self.stack.pop();
+                }
+                Op::Jump(to) => act.pc = to,
+                Op::JumpIfFalse(to) => {
+                    if matches!(self.stack.pop().unwrap(), Nil | Bool(false)) {
+                        act.pc = to;
+                    }
+                }
+                Op::Call(argc) | Op::TailCall(argc) => {
+                    tick()?;
+                    let (func, args) = self.pop_args(argc);
+                    match func {
+                        VmFunc(closure) => {
+                            let tail = matches!(op, Op::TailCall(_));
+                            let base = if tail { act.base } else { self.stack.len() };
+                            if tail {
+                                self.stack.truncate(act.base);
+                            } else if self.calls.len() >= max_depth() {
+                                bail!("stack depth exceeded (limit {})", max_depth());
+                            }
+                            let callee = enter(&closure, args, &mut self.stack, base)?;
+                            let caller = std::mem::replace(act, callee);
+                            if !tail {
+                                self.calls.push(caller);
+                            }
+                        }
+                        RustFunc(f, _) => {
+                            let _timer = profile::enter(&func);
+                            self.stack.push(f(args)?)
+                        }
+                        MalFunc {
+                            ref arities,
+                            ref env,
+                            ..
+                        } => {
+                            let _timer = profile::enter(&func);
+                            let (fn_env, body) = bind_fn(env, arities, &args)?;
+                            self.stack.push(exec(body, fn_env)?);
+                        }
+                        _ => bail!("apttempt to call non-function"),
+                    }
+                }
+                Op::Return => {
+                    let val = self.stack.pop().unwrap();
+                    match self.calls.pop() {
+                        Some(caller) => {
+                            self.stack.truncate(act.base);
+                            self.stack.push(val);
+                            *act = caller;
+                        }
+                        None => return Ok(val),
+                    }
+                }
+                Op::Closure(i) => {
+                    let closure = Closure {
+                        proto: act.chunk().protos[i].clone(),
+                        frame: act.frame.clone(),
+                        env: act.env.clone(),
+                        is_macro: false,
+                        meta: None,
+                    };
+                    self.stack.push(VmFunc(Rc::new(closure)));
+                }
+                Op::PushFrame(nslots) => act.frame = Some(new_frame(nslots, act.frame.take())?),
+                Op::PopFrame => act.frame = act.frame.take().unwrap().parent.clone(),
+                Op::Bind(i) => {
+                    let val = self.stack.pop().unwrap();
+                    let binder = &act.chunk().binders[i];
+                    let mut target = FrameTarget {
+                        binder,
+                        frame: &act.frame,
+                        env: &act.env,
+                    };
+                    bind_into(&mut target, &binder.pattern, val)?;
+                }
+                Op::Recur { up, target } => {
+                    tick()?;
+                    let loop_frame = frame_at(&act.frame, up);
+                    let nslots = loop_frame.slots.borrow().len();
+                    act.frame = Some(new_frame(nslots, loop_frame.parent.clone())?);
+                    act.pc = target;
+                }
+                Op::Try(handler) => self.handlers.push(Handler {
+                    depth: self.calls.len(),
+                    stack: self.stack.len(),
+                    act: Activation {
+                        pc: handler,
+                        ..act.clone()
+                    },
+                }),
+                Op::EndTry => {
+                    self.handlers.pop();
+                }
+                Op::Fallback(i) => {
+                    let (node, visible) = &act.chunk().fallbacks[i];
+                    let env = match visible.is_empty() {
+                        true => act.env.clone(),
+                        false => visible_env(visible, &act.frame, &act.env)?,
+                    };
+                    self.stack.push(exec(node.clone(), env)?);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,51 @@
+// Builtins fail with an error on bad input instead of panicking, and the
+// names a program makes up count against its limits.
+
+use lisp_rs::{interpreter::Interpreter, limits::Limits};
+
+fn eval_err(src: &str) -> String {
+    let interp = Interpreter::new().unwrap();
+    match interp.eval_str(src) {
+        Ok(val) => panic!("expected an error from {}, got {:?}", src, val),
+        Err(err) => format!("{:#}", err),
+    }
+}
+
+#[test]
+fn arithmetic_errors() {
+    for src in [
+        "(/ 1 0)",
+        "(/ -9223372036854775808 -1)",
+        "(+ 9223372036854775807 1)",
+        "(- -9223372036854775808 1)",
+        "(* 9223372036854775807 2)",
+    ] {
+        assert!(
+            eval_err(src).contains("overflow or division by zero"),
+            "{}",
+            src
+        );
+    }
+}
+
+#[test]
+fn count_arity() {
+    assert!(eval_err("(count)").contains("count expects one argument"));
+    assert!(eval_err("(count '(1) '(2))").contains("count expects one argument"));
+}
+
+#[test]
+fn new_symbols_count_as_allocations() {
+    let interp = Interpreter::new().unwrap();
+    let names: Vec<String> = (0..100).map(|i| format!("fresh-name-{}", i)).collect();
+    let src = format!("(quote ({}))", names.join(" "));
+    let limits = Limits {
+        allocations: Some(50),
+        ..Limits::default()
+    };
+    let err = interp.eval_with_limits(&src, &limits).unwrap_err();
+    assert!(err.to_string().contains("allocation limit exceeded"));
+    // once interned, they are free
+    interp.eval_str(&src).unwrap();
+    interp.eval_with_limits(&src, &limits).unwrap();
+}
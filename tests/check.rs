@@ -0,0 +1,22 @@
+// `check` reports syntax errors where a file has them, and only there.
+
+use lisp_rs::reader::read_all_recovering;
+
+fn diagnostics(src: &str) -> Vec<String> {
+    let (_, diags) = read_all_recovering(src).unwrap();
+    diags.iter().map(|diag| diag.describe(src)).collect()
+}
+
+#[test]
+fn multi_line_lists_are_valid() {
+    assert!(diagnostics("(def! x '(\n(1 2)\n(3 4)))\n").is_empty());
+    assert!(diagnostics("(do\n(def! y 1)\n(+ y 1))\n").is_empty());
+}
+
+#[test]
+fn missing_paren_stops_at_the_next_line() {
+    let diags = diagnostics("(def! x (+ 1 2)\n(def! y 3)\n(def! z\n");
+    assert_eq!(diags.len(), 2, "{:?}", diags);
+    assert!(diags[0].starts_with("1:1: expected `)`"), "{:?}", diags);
+    assert!(diags[1].starts_with("3:1: expected `)`"), "{:?}", diags);
+}
@@ -0,0 +1,301 @@
+// The persistent collections behave like their std counterparts, for every
+// version kept along the way. The property tests build random histories;
+// the small tests are sized to run under Miri as well.
+
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+use lisp_rs::{
+    hamt::Hamt,
+    seq::Seq,
+    types::MalVal::{self, Num},
+    vector::Vector,
+};
+use proptest::prelude::*;
+
+fn nums(vals: &[i64]) -> Vec<MalVal> {
+    vals.iter().map(|n| Num(*n)).collect()
+}
+
+fn check_seq(seq: &Seq, model: &[i64]) {
+    let model = nums(model);
+    assert_eq!(seq.len(), model.len());
+    assert_eq!(seq.is_empty(), model.is_empty());
+    assert_eq!(seq.first(), model.first());
+    for (i, val) in model.iter().enumerate() {
+        assert_eq!(seq.get(i), Some(val));
+    }
+    assert_eq!(seq.get(model.len()), None);
+    assert!(seq.iter().eq(model.iter()));
+    assert_eq!(seq.iter().len(), model.len());
+    assert_eq!(*seq, Seq::from(model.clone()));
+    // read as a slice, through the flattened copy
+    assert_eq!(&seq[..], &model[..]);
+}
+
+fn check_vector(vector: &Vector, model: &[i64]) {
+    let model = nums(model);
+    assert_eq!(vector.len(), model.len());
+    for (i, val) in model.iter().enumerate() {
+        assert_eq!(vector.get(i), Some(val));
+    }
+    assert_eq!(vector.get(model.len()), None);
+    assert!(vector.iter().eq(model.iter()));
+    assert_eq!(vector.iter().len(), model.len());
+    assert_eq!(*vector, model.into_iter().collect::<Vector>());
+}
+
+fn check_hamt(map: &Hamt, model: &HashMap<String, i64>) {
+    assert_eq!(map.len(), model.len());
+    for (k, v) in model {
+        assert_eq!(map.get(k), Some(&Num(*v)));
+    }
+    let entries: HashMap<String, MalVal> =
+        map.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+    let expected: HashMap<String, MalVal> =
+        model.iter().map(|(k, v)| (k.clone(), Num(*v))).collect();
+    assert_eq!(entries, expected);
+    let rebuilt: Hamt = model.iter().map(|(k, v)| (k.clone(), Num(*v))).collect();
+    assert_eq!(*map, rebuilt);
+}
+
+#[derive(Debug, Clone)]
+enum SeqOp {
+    Cons(usize, i64),
+    Rest(usize),
+    // read a version as a slice, which flattens its buffers
+    Flatten(usize),
+}
+
+fn seq_op() -> impl Strategy<Value = SeqOp> {
+    prop_oneof![
+        4 => (any::<usize>(), any::<i64>()).prop_map(|(v, n)| SeqOp::Cons(v, n)),
+        2 => any::<usize>().prop_map(SeqOp::Rest),
+        1 => any::<usize>().prop_map(SeqOp::Flatten),
+    ]
+}
+
+#[derive(Debug, Clone)]
+enum VectorOp {
+    Push(usize, i64),
+    // push this many elements, to reach deeper tries
+    Extend(usize, usize),
+    Set(usize, usize, i64),
+}
+
+fn vector_op() -> impl Strategy<Value = VectorOp> {
+    prop_oneof![
+        4 => (any::<usize>(), any::<i64>()).prop_map(|(v, n)| VectorOp::Push(v, n)),
+        1 => (any::<usize>(), 0..1200usize).prop_map(|(v, n)| VectorOp::Extend(v, n)),
+        3 => (any::<usize>(), any::<usize>(), any::<i64>())
+            .prop_map(|(v, i, n)| VectorOp::Set(v, i, n)),
+    ]
+}
+
+#[derive(Debug, Clone)]
+enum HamtOp {
+    Insert(usize, String, i64),
+    Remove(usize, String),
+}
+
+// few enough keys that they are often replaced and removed again
+fn key() -> impl Strategy<Value = String> {
+    prop_oneof!["[a-d]{0,2}", "[a-z0-9]{1,8}"]
+}
+
+fn hamt_op() -> impl Strategy<Value = HamtOp> {
+    prop_oneof![
+        3 => (any::<usize>(), key(), any::<i64>()).prop_map(|(v, k, n)| HamtOp::Insert(v, k, n)),
+        2 => (any::<usize>(), key()).prop_map(|(v, k)| HamtOp::Remove(v, k)),
+    ]
+}
+
+proptest! {
+    // each operation applies to an earlier version, chosen by its index
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn seq_matches_vec(ops in prop::collection::vec(seq_op(), 0..200)) {
+        let mut versions = vec![(Seq::default(), vec![])];
+        for op in ops {
+            let pick = |v: usize| v % versions.len();
+            let (seq, model): (Seq, Vec<i64>) = match op {
+                SeqOp::Cons(v, n) => {
+                    let (seq, model) = &versions[pick(v)];
+                    let mut model = model.clone();
+                    model.insert(0, n);
+                    (seq.cons(Num(n)), model)
+                }
+                SeqOp::Rest(v) => {
+                    let (seq, model) = &versions[pick(v)];
+                    (seq.rest(), model.iter().skip(1).copied().collect())
+                }
+                SeqOp::Flatten(v) => {
+                    let (seq, model) = &versions[pick(v)];
+                    let _ = &seq[..];
+                    (seq.clone(), model.clone())
+                }
+            };
+            versions.push((seq, model));
+        }
+        for (seq, model) in &versions {
+            check_seq(seq, model);
+        }
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn vector_matches_vec(ops in prop::collection::vec(vector_op(), 0..60)) {
+        let mut versions = vec![(Vector::new(), vec![])];
+        for op in ops {
+            let pick = |v: usize| v % versions.len();
+            let (mut vector, mut model): (Vector, Vec<i64>) = match op {
+                VectorOp::Push(v, _) | VectorOp::Extend(v, _) | VectorOp::Set(v, ..) => {
+                    versions[pick(v)].clone()
+                }
+            };
+            match op {
+                VectorOp::Push(_, n) => {
+                    vector.push(Num(n));
+                    model.push(n);
+                }
+                VectorOp::Extend(_, len) => {
+                    for n in 0..len as i64 {
+                        vector.push(Num(n));
+                        model.push(n);
+                    }
+                }
+                VectorOp::Set(_, i, n) if !model.is_empty() => {
+                    let i = i % model.len();
+                    vector.set(i, Num(n));
+                    model[i] = n;
+                }
+                VectorOp::Set(..) => (),
+            }
+            versions.push((vector, model));
+        }
+        for (vector, model) in &versions {
+            check_vector(vector, model);
+        }
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn hamt_matches_hash_map(ops in prop::collection::vec(hamt_op(), 0..200)) {
+        let mut versions = vec![(Hamt::new(), HashMap::new())];
+        for op in ops {
+            let pick = |v: usize| v % versions.len();
+            let (mut map, mut model) = match &op {
+                HamtOp::Insert(v, ..) | HamtOp::Remove(v, _) => versions[pick(*v)].clone(),
+            };
+            match op {
+                HamtOp::Insert(_, k, n) => {
+                    map.insert(k.clone(), Num(n));
+                    model.insert(k, n);
+                }
+                HamtOp::Remove(_, k) => {
+                    map.remove(&k);
+                    model.remove(&k);
+                }
+            }
+            versions.push((map, model));
+        }
+        for (map, model) in &versions {
+            check_hamt(map, model);
+        }
+    }
+}
+
+#[test]
+fn cons_onto_a_shared_list() {
+    let base = Seq::from(nums(&[3, 4]));
+    // the first cons claims the free slot before `base`; the second has to
+    // start a new buffer continuing with `base`
+    let a = base.cons(Num(2));
+    let b = base.cons(Num(20));
+    let c = b.cons(Num(10));
+    check_seq(&base, &[3, 4]);
+    check_seq(&a, &[2, 3, 4]);
+    check_seq(&b, &[20, 3, 4]);
+    check_seq(&c, &[10, 20, 3, 4]);
+    check_seq(&c.rest().rest(), &[3, 4]);
+}
+
+#[test]
+fn a_flattened_list_still_conses() {
+    let mut seq = Seq::default();
+    for n in (0..20).rev() {
+        seq = seq.cons(Num(n));
+        // chain a few buffers
+        let _ = seq.cons(Num(-1));
+    }
+    let model: Vec<i64> = (0..20).collect();
+    check_seq(&seq, &model);
+    let consed = seq.cons(Num(-2));
+    check_seq(
+        &consed,
+        &[-2].iter().chain(&model).copied().collect::<Vec<_>>(),
+    );
+    check_seq(&seq, &model);
+}
+
+#[test]
+fn lists_drop_their_values() {
+    let cell = Rc::new(RefCell::new(Num(0)));
+    {
+        let base = Seq::from(vec![MalVal::Atom(cell.clone())]);
+        let a = base.cons(MalVal::Atom(cell.clone()));
+        let b = base.cons(MalVal::Atom(cell.clone()));
+        let _ = (&a[..], b.rest());
+        assert!(Rc::strong_count(&cell) > 1);
+    }
+    assert_eq!(Rc::strong_count(&cell), 1);
+}
+
+#[test]
+fn vector_across_levels() {
+    // past 32 + 32 * 32 elements, the root has to grow a level
+    let n = if cfg!(miri) { 1100 } else { 40_000 };
+    let model: Vec<i64> = (0..n).collect();
+    let vector: Vector = nums(&model).into_iter().collect();
+    check_vector(&vector, &model);
+
+    let mut changed = vector.clone();
+    changed.set(0, Num(-1));
+    changed.set(n as usize - 1, Num(-1));
+    changed.push(Num(n));
+    check_vector(&vector, &model);
+    let mut expected = model.clone();
+    expected[0] = -1;
+    expected[n as usize - 1] = -1;
+    expected.push(n);
+    check_vector(&changed, &expected);
+}
+
+#[test]
+fn vectors_share_until_changed() {
+    let mut a: Vector = nums(&[1, 2, 3]).into_iter().collect();
+    let b = a.clone();
+    a.set(1, Num(20));
+    a.push(Num(4));
+    check_vector(&a, &[1, 20, 3, 4]);
+    check_vector(&b, &[1, 2, 3]);
+}
+
+#[test]
+fn hamt_versions() {
+    let mut a = Hamt::new();
+    for n in 0..100 {
+        a.insert(format!("k{}", n), Num(n));
+    }
+    let mut b = a.clone();
+    for n in 0..50 {
+        b.remove(&format!("k{}", n));
+    }
+    b.insert("k99".to_owned(), Num(-1));
+    let model_a: HashMap<_, _> = (0..100).map(|n| (format!("k{}", n), n)).collect();
+    let mut model_b: HashMap<_, _> = (50..100).map(|n| (format!("k{}", n), n)).collect();
+    model_b.insert("k99".to_owned(), -1);
+    check_hamt(&a, &model_a);
+    check_hamt(&b, &model_b);
+    b.remove("absent");
+    check_hamt(&b, &model_b);
+}
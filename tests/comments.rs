@@ -0,0 +1,68 @@
+// Everything the reader skips: `#| |#` blocks, which nest; `#_` and the form
+// after it; and a `#!` line, but only at the very start of the source.
+
+use lisp_rs::{printer::print_readably, reader::read_all};
+
+// each form read from `src`, printed
+fn forms(src: &str) -> Vec<String> {
+    read_all(src).unwrap().iter().map(print_readably).collect()
+}
+
+fn error(src: &str) -> String {
+    read_all(src).unwrap_err().to_string()
+}
+
+#[test]
+fn block_comments() {
+    assert_eq!(forms("1 #| a |# 2"), ["1", "2"]);
+    assert_eq!(forms("(a #| ) |# b)"), ["(a b)"]);
+    assert_eq!(forms("#|\n(f 1)\n|#\n3"), ["3"]);
+    // `#|#` opens a comment, and the `|#` after it closes it
+    assert_eq!(forms("#|# |# 5"), ["5"]);
+}
+
+#[test]
+fn block_comments_nest() {
+    assert_eq!(forms("1 #| a #| b |# c |# 2"), ["1", "2"]);
+    assert_eq!(forms("#| #| #| |# |# |# x"), ["x"]);
+    // the inner `|#` doesn't end the outer comment
+    assert_eq!(error("#| a #| b |# c"), "1:1: unterminated block comment");
+}
+
+#[test]
+fn unterminated_block_comments() {
+    assert_eq!(error("#| a"), "1:1: unterminated block comment");
+    assert_eq!(
+        error("(f 1)\n  #| (g 2)"),
+        "2:3: unterminated block comment"
+    );
+    // a stray `|#` is the start of a name
+    assert_eq!(error("x |#"), "1:3: unterminated `|` in name");
+}
+
+#[test]
+fn datum_comments() {
+    assert_eq!(forms("(1 #_ 2 3)"), ["(1 3)"]);
+    assert_eq!(forms("#_(1 #| x |# (2)) 4"), ["4"]);
+    assert_eq!(forms("{:a #_ :b 1}"), ["{:a 1}"]);
+    assert_eq!(forms("[1 #_ 'x]"), ["[1]"]);
+    // each `#_` discards one form, so two discard two
+    assert_eq!(forms("#_ #_ 1 2 3"), ["3"]);
+    assert_eq!(forms("1 #_ ; a comment is not a form\n 2 3"), ["1", "3"]);
+}
+
+#[test]
+fn datum_comments_need_a_form() {
+    assert_eq!(error("#_"), "1:1: expected a form after `#_`");
+    assert_eq!(error("(1 #_)"), "1:4: expected a form after `#_`");
+    assert_eq!(error("(1\n #_ )"), "2:2: expected a form after `#_`");
+}
+
+#[test]
+fn shebang_on_the_first_line() {
+    assert_eq!(forms("#!/usr/bin/env lisp-rs\n(+ 1 2)"), ["(+ 1 2)"]);
+    assert_eq!(forms("#!/usr/bin/env lisp-rs"), Vec::<String>::new());
+    // anywhere else, `#!` starts a symbol
+    assert_eq!(forms("1\n#!x"), ["1", "|#!x|"]);
+    assert_eq!(forms(" #!x"), ["|#!x|"]);
+}
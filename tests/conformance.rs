@@ -0,0 +1,177 @@
+// Every example gives the same result on both backends, the one expected.
+
+use lisp_rs::{interpreter::Interpreter, printer::print_readably, Backend};
+
+const EXAMPLES: &[(&str, &str)] = &[
+    // values and builtins
+    ("(+ 1 (* 2 3))", "7"),
+    ("(/ 7 2)", "3"),
+    ("(/ 1 0)", "error: integer overflow or division by zero"),
+    ("(list 1 \"a\" :b \\c nil true)", "(1 \"a\" :b \\c nil true)"),
+    ("{:a (+ 1 2)}", "{:a 3}"),
+    ("(str \"a\" 1 :b)", "\"a1:b\""),
+    ("(count \"héllo\")", "5"),
+    ("(conj '(2 3) 1)", "(1 2 3)"),
+    ("(nth '(1 2 3) 5)", "error: index 5 out of range"),
+    ("(undefined-name)", "error: `undefined-name` not found"),
+    // vectors and sets
+    ("[1 (+ 1 1) #{:a \"x\"}]", "[1 2 #{:a \"x\"}]"),
+    ("(let* ((x 2)) #{x 2 3})", "#{2 3}"),
+    ("(conj [1 2] 3 4)", "[1 2 3 4]"),
+    ("(let* ((v [1 2 3])) (list (nth v 2) (count v) (first v) (rest v) (get v 1) (get v 3 :none)))", "(3 3 1 (2 3) 2 :none)"),
+    ("(let* ((v [1 2 3])) (list (assoc v 0 :x) (assoc v 3 4) v))", "([:x 2 3] [1 2 3 4] [1 2 3])"),
+    ("(assoc [1] 2 3)", "error: index 2 out of range"),
+    ("(nth [1] 1)", "error: index 1 out of range"),
+    ("(list (= [1 2] '(1 2)) (= [1 2] [1 2 3]) (= #{1 2} #{2 1}) (= #{1} [1]))", "(true false true false)"),
+    ("(list (conj #{1} 1 2) (disj #{1 2} 1) (contains? #{:a} :a) (get #{:a} :b))", "(#{1 2} #{2} true nil)"),
+    ("(list (vec '(1 2)) (set [1 1 2]) (seq [1 2]) (seq []) (vector? [1]) (set? #{}) (empty? []))", "([1 2] #{1 2} (1 2) nil true true true)"),
+    ("(list (concat [1] '(2) [3]) (cons 0 [1]))", "((1 2 3) (0 1))"),
+    ("(let* (((a & r) [1 2 3])) (list a r))", "(1 (2 3))"),
+    ("(let* ((x 2)) `[1 ~x ~@(list 3 4)])", "[1 2 3 4]"),
+    ("(meta ^:a [(+ 1 1)])", "{:a true}"),
+    ("(macroexpand-all [(when x 1)])", "[(if x (do 1))]"),
+    ("(hash-set '(1))", "error: invalid set element `(1)`"),
+    // special forms
+    ("(if nil 1 2)", "2"),
+    ("(do (def! x 1) (def! x (+ x 1)) x)", "2"),
+    ("(let* ((x 1) (y (+ x 1))) (list x y))", "(1 2)"),
+    ("(let* ((x 1) (x (+ x 1))) x)", "2"),
+    ("(do (def! x 10) (let* ((y x) (x 2)) (list x y)))", "(2 10)"),
+    ("(let* ((f (fn* (n) (if (= n 0) 0 (+ n (f (- n 1))))))) (f 4))", "10"),
+    ("(let* ((g (fn* () h)) (h 5)) (g))", "5"),
+    ("(let* ((even? (fn* (n) (if (= n 0) true (odd? (- n 1))))) (odd? (fn* (n) (if (= n 0) false (even? (- n 1)))))) (even? 10))", "true"),
+    ("(let* ((x 1)) (let* ((f (fn* () x)) (x 2)) (f)))", "2"),
+    ("(let* ((x 1)) (let* ((y x) (x 2)) y))", "1"),
+    ("(let* ((a (def! b 3))) (list a b))", "(3 3)"),
+    ("(let* ((f (fn* () x)) (y (f)) (x 1)) y)", "error: `x` used before it is bound"),
+    ("(let* () (do (if (first '(false)) (def! y 1)) y))", "error: `y` used before it is bound"),
+    ("(loop ((i 0)) (if (= i 0) (do (def! z 1) (recur 1)) z))", "error: `z` used before it is bound"),
+    ("(loop ((i 0) (acc ())) (if (< i 3) (recur (+ i 1) (cons i acc)) acc))", "(2 1 0)"),
+    // each iteration binds in a new scope
+    ("(loop ((f (fn* () n)) (n 1)) (if (< n 3) (recur f (+ n 1)) (f)))", "1"),
+    ("(loop ((i 0)) (+ 1 (recur i)))", "error: `recur` must be in tail position of a `loop`"),
+    ("(loop ((i 0)) (if (< i 3) (do (recur (+ i 1)) 1) i))", "error: `recur` must be in tail position of a `loop`"),
+    ("(fn* () (recur))", "error: `recur` must be in tail position of a `loop`"),
+    ("(loop ((i 0)) (loop ((j 0)) (if (< j 2) (recur (+ j 1)) (list i j))))", "(0 2)"),
+    ("(loop ((i 100000)) (if (= i 0) :done (recur (- i 1))))", ":done"),
+    // atoms and the iteration macros built on loop
+    ("(let* ((a (atom 1))) (list (reset! a 2) (swap! a + 3) @a (deref a)))", "(2 5 5 5)"),
+    ("(let* ((a (atom 1))) (list (atom? a) (atom? 1) (= a a) (= a (atom 1))))", "(true false true false)"),
+    ("(atom '(1 \"a\"))", "#<atom (1 \"a\")>"),
+    ("(let* ((n (atom 0))) (list (while (< @n 3) (swap! n + 1)) @n))", "(nil 3)"),
+    ("(let* ((acc (atom ()))) (do (dotimes (i 3) (swap! acc conj i)) @acc))", "(2 1 0)"),
+    ("(let* ((acc (atom ()))) (do (doseq (x '(a b c)) (swap! acc conj x)) @acc))", "(c b a)"),
+    ("(let* ((acc (atom 0))) (do (doseq (x ()) (reset! acc 1)) @acc))", "0"),
+    ("(try* (throw \"boom\") (catch* e (str \"caught \" e)))", "\"caught boom\""),
+    ("(try* (nth () 1) (catch* e :caught))", ":caught"),
+    ("(quote (a b))", "(a b)"),
+    ("(do (def! x 2) `(1 ~x ~@(list 3 4)))", "(1 2 3 4)"),
+    // functions and destructuring
+    ("((fn* (& xs) xs) 1 2)", "(1 2)"),
+    ("((fn* ((a) a) ((a b) (+ a b))) 1 2)", "3"),
+    ("((fn* (a &optional (b 10)) (+ a b)) 1)", "11"),
+    ("(do (def! y 5) ((fn* (&optional (x y)) x)))", "5"),
+    ("((fn* (&key a (b 2)) (list a b)) :a 1)", "(1 2)"),
+    ("((fn* ((a (b c))) (list a b c)) '(1 (2 3)))", "(1 2 3)"),
+    ("((fn* ({:keys (a b) :or {b 5}}) (+ a b)) {:a 1})", "6"),
+    ("(let* (((a & r) '(1 2 3))) (list a r))", "(1 (2 3))"),
+    ("(let* (((a & r :as all) '(1 2 3))) (list a r all))", "(1 (2 3) (1 2 3))"),
+    ("((fn* (a & r &key k) (list a r k)) 1 :k 2)", "(1 (:k 2) 2)"),
+    ("((fn* (a & r b) (list a r b)) 1 2 3)", "error: unexpected `b` after the rest pattern in `(a & r b)`"),
+    // defaults see the names bound before them, and are analyzed once
+    ("((fn* (a &optional (b (+ a 1))) b) 1)", "2"),
+    ("(let* ((c 10)) ((fn* (&optional (a c) (c 5)) (list a c))))", "(10 5)"),
+    ("((fn* (&optional ((x &optional (y (* x 2))) (list 3))) (list x y)))", "(3 6)"),
+    ("((fn* (a &key (k (* a 10))) k) 2)", "20"),
+    ("((fn* ({:keys (a b) :or {b (+ a 1)}}) b) {:a 1})", "2"),
+    ("(((fn* (a &optional (f (fn* () a))) f) 7))", "7"),
+    ("(let* (((a &optional (b (* a 3))) '(2))) b)", "6"),
+    ("(loop (((i &optional (j (* i 2))) '(1))) (if (< i 3) (recur (list (+ i 1))) (list i j)))", "(3 6)"),
+    ("(let* ((n (atom 0)) (f (fn* (&optional (x (swap! n + 1))) x))) (list (f) (f 10) (f) @n))", "(1 10 2 2)"),
+    ("(do (def! n (atom 0)) (defmacro! m (fn* () (do (swap! n + 1) 1))) (def! f (fn* (&optional (x (m))) x)) (f) (f) @n)", "1"),
+    ("((fn* (& r & s) r) 1)", "error: unexpected `&` after the rest pattern in `(& r & s)`"),
+    ("((fn* (a) a))", "error: wrong number of args (0) passed to function accepting 1"),
+    ("(do (def! make (fn* (n) (fn* () n))) ((make 7)))", "7"),
+    ("(do (def! f (fn* () (def! z 4) z)) (f))", "4"),
+    // macros
+    ("(do (defmacro! unless2 (fn* (c a b) `(if ~c ~b ~a))) (unless2 false 1 2))", "1"),
+    ("(do (defmacro! m (fn* () 1)) (let* ((m (fn* () 2))) (m)))", "2"),
+    ("(do (def! mk (fn* () 2)) (let* () (defmacro! m3 mk)) (m3))", "error: `m3` not found"),
+    ("(do (def! mk (fn* () 2)) (let* () (do (defmacro! m3 mk) (m3))))", "2"),
+    ("(cond false 1 :else 2)", "2"),
+    ("(-> 1 (+ 2) (* 3))", "9"),
+    // `comment` evaluates nothing in its body, and `#_` doesn't even read it
+    ("(comment (undefined-name) (/ 1 0))", "nil"),
+    ("(list 1 (comment 2) 3)", "(1 nil 3)"),
+    ("(list 1 #_ (undefined-name) 3)", "(1 3)"),
+    ("(macroexpand (comment x))", "nil"),
+    ("(let ((x 1) (y 2)) (+ x y))", "3"),
+    ("(let lp ((i 0) (acc 0)) (if (< i 5) (lp (+ i 1) (+ acc i)) acc))", "10"),
+    ("(do (define-syntax swap (syntax-rules () ((_ a b) (list b a)))) (swap 1 2))", "(2 1)"),
+    ("(macroexpand (when x 1))", "(if x (do 1))"),
+    // one step, the head until it is no macro call, or every subform
+    ("(do (defmacro! m2 (fn* (x) `(+ ~x 1))) (defmacro! m1 (fn* (x) `(m2 ~x))) (macroexpand-1 (m1 5)))", "(m2 5)"),
+    ("(do (defmacro! m2 (fn* (x) `(+ ~x 1))) (defmacro! m1 (fn* (x) `(m2 ~x))) (macroexpand (m1 5)))", "(+ 5 1)"),
+    ("(do (defmacro! m2 (fn* (x) `(+ ~x 1))) (defmacro! m1 (fn* (x) `(m2 ~x))) (macroexpand (list (m1 5))))", "(list (m1 5))"),
+    ("(do (defmacro! m2 (fn* (x) `(+ ~x 1))) (defmacro! m1 (fn* (x) `(m2 ~x))) (macroexpand-all (let* ((a (m1 1))) (when a (m2 a)))))", "(let* ((a (+ 1 1))) (if a (do (+ a 1))))"),
+    ("(macroexpand-1 (list 1))", "(list 1)"),
+    ("(macroexpand-all (quote (when x 1)))", "(quote (when x 1))"),
+    // constant folding
+    ("(do (def! f (fn* () (+ 1 2))) (def! + -) (f))", "-1"),
+    ("(if true (do (def! + *) (+ 2 3)))", "6"),
+    ("(let* ((+ -)) (+ 1 2))", "-1"),
+    // gensyms
+    ("(= (gensym) (gensym))", "false"),
+    ("(starts-with? (pr-str (gensym \"x\")) \"#<gensym x__\")", "true"),
+    ("(starts-with? (str (gensym \"x\")) \"x__\")", "true"),
+    ("(read-string (pr-str (gensym)))", "error: 1:1: `#<gensym` starts a value that can't be read back"),
+    ("(let* ((g (gensym))) (get (assoc {} g 1) g))", "1"),
+    ("(let* ((g (gensym))) (= (keys (assoc {} g 1)) (list g)))", "true"),
+    // metadata and characters
+    ("(meta (with-meta (fn* () 1) {:a 1}))", "{:a 1}"),
+    ("(meta '^:a (1 2))", "{:a true}"),
+    ("(meta (quote ^Tag ^{:b 1} (x)))", "{:b 1 :tag Tag}"),
+    ("(= '^:a (1 2) '(1 2))", "true"),
+    ("(meta ^{:doc \"d\"} {:b 1})", "{:doc \"d\"}"),
+    ("(meta ^:a {:b (+ 1 2)})", "{:a true}"),
+    ("(meta ^{:doc \"f\"} (fn* () 1))", "{:doc \"f\"}"),
+    ("(meta (read-string \"^:a x\"))", "{:a true}"),
+    ("(list (meta '^Long x) (= '^:a x 'x))", "({:tag Long} true)"),
+    ("(read-string \"^:a 1\")", "error: 1:1: cannot attach metadata to `1`"),
+    ("(list (meta (with-meta + {:a 1})) ((with-meta + {:a 1}) 1 2) (= (with-meta + {:a 1}) +))", "({:a 1} 3 true)"),
+    // hints on the names a pattern binds are ignored
+    ("(do (defn f (^Long x) (+ x 1)) (f 2))", "3"),
+    ("((fn* (^Long a &optional ^Long (b 3) &key ^String k & ^:r r) (list a b k r)) 1 2 :k 4)", "(1 2 4 (:k 4))"),
+    ("(let* ((^Long x 1) (^:v (a ^Long b) '(2 3))) (list x a b))", "(1 2 3)"),
+    ("(loop ((^Long i 0)) (if (< i 3) (recur (+ i 1)) i))", "3"),
+    // macroexpand-all keeps the metadata of the forms it rebuilds
+    ("(list (meta (macroexpand-all ^:a (f (when x 1)))) (meta (macroexpand-all ^:m {:k (when x 1)})))", "({:a true} {:m true})"),
+    ("(meta (nth (macroexpand-all (g ^:b (f (when x 1)))) 1))", "{:b true}"),
+    ("(meta (nth (nth (macroexpand-all `(a ^:q (b ~(when x 1)))) 1) 1))", "{:q true}"),
+    ("(char->int \\a)", "97"),
+];
+
+fn eval(backend: Backend, src: &str) -> String {
+    let interp = Interpreter::with_backend(backend).unwrap();
+    match interp.eval_str(src) {
+        Ok(val) => print_readably(&val),
+        Err(err) => format!("error: {:#}", err),
+    }
+}
+
+#[test]
+fn both_backends_agree() {
+    let mut failures = vec![];
+    for (src, expected) in EXAMPLES {
+        for backend in [Backend::TreeWalker, Backend::Vm] {
+            let got = eval(backend, src);
+            if got != *expected {
+                failures.push(format!(
+                    "{:?} on {:?}: expected {}, got {}",
+                    src, backend, expected, got
+                ));
+            }
+        }
+    }
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
@@ -0,0 +1,105 @@
+// `fmt` keeps comments, reads back to the same forms, is idempotent, and
+// `--check` reports files it would change.
+
+use std::{
+    fs,
+    io::Write,
+    process::{Command, Stdio},
+};
+
+use lisp_rs::{fmt::format_source, reader::read_all};
+
+const MESSY: &str = "\
+; leading comment
+
+(def! f (fn* (x)   ; trailing
+     (+ x
+  1)))
+
+
+(def! x '
+; why
+(1 2))
+#| block |# (f 1) #_ (ignored)
+{:a 1 :b (list 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16 17 18 19 20 21 22 23 24)}
+(def! v [1 #{:a :b}
+  [2 3]])
+";
+
+#[test]
+fn keeps_comments_and_reads_back() {
+    let out = format_source(MESSY, 80).unwrap();
+    for comment in ["; leading comment", "; trailing", "; why"] {
+        assert!(out.contains(comment), "{} missing from:\n{}", comment, out);
+    }
+    assert_eq!(read_all(&out).unwrap(), read_all(MESSY).unwrap());
+}
+
+#[test]
+fn comment_after_a_prefix_goes_before_it() {
+    let out = format_source("(def! x '\n; why\n(1 2))\n", 80).unwrap();
+    assert_eq!(out, "(def! x\n  ; why\n  '(1 2))\n");
+}
+
+#[test]
+fn vectors_and_sets_line_up_their_elements() {
+    assert_eq!(
+        format_source("[aaaa bbbb cccc]\n", 10).unwrap(),
+        "[aaaa\n bbbb\n cccc]\n"
+    );
+    assert_eq!(
+        format_source("#{aaaa bbbb cccc}\n", 10).unwrap(),
+        "#{aaaa\n  bbbb\n  cccc}\n"
+    );
+}
+
+#[test]
+fn idempotent() {
+    for src in [MESSY, include_str!("../src/prelude.mal")] {
+        for width in [20, 80] {
+            let once = format_source(src, width).unwrap();
+            assert_eq!(format_source(&once, width).unwrap(), once);
+        }
+    }
+}
+
+fn fmt(args: &[&str], stdin: &str) -> (i32, String) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_lisp_rs"))
+        .arg("fmt")
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    let out = child.wait_with_output().unwrap();
+    (
+        out.status.code().unwrap(),
+        String::from_utf8(out.stdout).unwrap(),
+    )
+}
+
+#[test]
+fn check_exit_status() {
+    let formatted = format_source(MESSY, 80).unwrap();
+    assert_eq!(fmt(&["--check"], &formatted), (0, String::new()));
+    assert_eq!(fmt(&["--check"], MESSY), (1, String::new()));
+    assert_eq!(fmt(&[], MESSY), (0, formatted.clone()));
+
+    let path = std::env::temp_dir().join(format!("lisp_rs-fmt-{}.mal", std::process::id()));
+    let file = path.to_str().unwrap();
+    fs::write(&path, MESSY).unwrap();
+    let (code, out) = fmt(&["--check", file], "");
+    assert_eq!(code, 1);
+    assert_eq!(out, format!("would reformat {}\n", file));
+    assert_eq!(fs::read_to_string(&path).unwrap(), MESSY);
+    assert_eq!(fmt(&[file], ""), (0, String::new()));
+    assert_eq!(fs::read_to_string(&path).unwrap(), formatted);
+    assert_eq!(fmt(&["--check", file], ""), (0, String::new()));
+    fs::remove_file(&path).unwrap();
+}
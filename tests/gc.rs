@@ -0,0 +1,131 @@
+// The cycle collector frees envs only reachable from reference cycles, and
+// nothing that is still in use.
+
+use lisp_rs::{
+    apply,
+    gc::{self, HeapStats},
+    interpreter::Interpreter,
+    printer::print_readably,
+    types::MalVal::Num,
+    Backend,
+};
+
+const BACKENDS: [Backend; 2] = [Backend::TreeWalker, Backend::Vm];
+
+fn eval(interp: &Interpreter, src: &str) -> String {
+    print_readably(&interp.eval_str(src).unwrap())
+}
+
+// each test runs on its own thread, so has a heap of its own
+fn stats() -> HeapStats {
+    gc::stats()
+}
+
+#[test]
+fn recursive_functions_made_in_a_loop_are_freed() {
+    for backend in BACKENDS {
+        let interp = Interpreter::with_backend(backend).unwrap();
+        eval(
+            &interp,
+            "(dotimes (i 100)
+               (let* () (do (def! f (fn* (n) (if (= n 0) 0 (f (- n 1))))) (f 3))))",
+        );
+        let before = stats();
+        let freed = gc::collect();
+        let after = stats();
+        assert!(freed >= 100, "{:?}: freed {}", backend, freed);
+        assert_eq!(after.freed, before.freed + freed as u64);
+        assert_eq!(after.collections, before.collections + 1);
+        assert!(after.envs + 100 <= before.envs, "{:?}", backend);
+    }
+}
+
+#[test]
+fn cycles_through_atoms_are_freed() {
+    for backend in BACKENDS {
+        let interp = Interpreter::with_backend(backend).unwrap();
+        eval(
+            &interp,
+            "(dotimes (i 100) (let* ((a (atom nil))) (reset! a (fn* () a))))",
+        );
+        assert!(gc::collect() >= 100, "{:?}", backend);
+    }
+}
+
+// closures in the metadata of a builtin and of a symbol
+#[test]
+fn cycles_through_metadata_are_freed() {
+    for backend in BACKENDS {
+        let interp = Interpreter::with_backend(backend).unwrap();
+        eval(
+            &interp,
+            "(dotimes (i 50)
+               (let* ((a (atom nil)))
+                 (reset! a (list (with-meta + {:f (fn* () a)}) (with-meta 'x {:f (fn* () a)})))))",
+        );
+        assert!(gc::collect() >= 50, "{:?}", backend);
+    }
+}
+
+// closures in a vector's tail, and in the leaves of its trie
+#[test]
+fn cycles_through_vectors_are_freed() {
+    for backend in BACKENDS {
+        let interp = Interpreter::with_backend(backend).unwrap();
+        eval(
+            &interp,
+            "(dotimes (i 20)
+               (let* ((a (atom nil)))
+                 (reset! a (loop ((j 0) (v [])) (if (< j 40) (recur (+ j 1) (conj v (fn* () a))) v)))))",
+        );
+        assert!(gc::collect() >= 20, "{:?}", backend);
+    }
+}
+
+#[test]
+fn closures_held_from_rust_or_the_env_survive() {
+    let make = "(let* () (do (def! g (fn* (n) (if (= n 0) :done (g (- n 1))))) g))";
+    for backend in BACKENDS {
+        let interp = Interpreter::with_backend(backend).unwrap();
+        let held = interp.eval_str(make).unwrap();
+        eval(&interp, &format!("(def! h {})", make));
+        let cell = interp
+            .eval_str(&format!("(def! cell (atom {}))", make))
+            .unwrap();
+        drop(cell);
+        eval(
+            &interp,
+            &format!(
+                "(def! v (loop ((j 0) (v [])) (if (< j 40) (recur (+ j 1) (conj v {})) v)))",
+                make
+            ),
+        );
+        gc::collect();
+        let ret = apply(&held, vec![Num(3)]).unwrap();
+        assert_eq!(print_readably(&ret), ":done", "{:?}", backend);
+        assert_eq!(eval(&interp, "(h 3)"), ":done", "{:?}", backend);
+        assert_eq!(eval(&interp, "(@cell 3)"), ":done", "{:?}", backend);
+        assert_eq!(eval(&interp, "((nth v 0) 3)"), ":done", "{:?}", backend);
+        assert_eq!(eval(&interp, "((nth v 39) 3)"), ":done", "{:?}", backend);
+    }
+}
+
+#[test]
+fn heap_stats() {
+    let interp = Interpreter::new().unwrap();
+    assert_eq!(
+        eval(&interp, "(keys (heap-stats))"),
+        "(:collections :envs :freed)"
+    );
+    eval(&interp, "(dotimes (i 10) (let* () (def! f (fn* () f))))");
+    let envs = eval(&interp, "(get (heap-stats) :envs)");
+    assert_eq!(envs, stats().envs.to_string());
+    let freed = eval(&interp, "(gc)");
+    assert_eq!(
+        eval(&interp, "(heap-stats)"),
+        format!("{{:collections 1 :envs {} :freed {}}}", stats().envs, freed)
+    );
+    assert!(freed.parse::<usize>().unwrap() >= 10);
+    assert_eq!(eval(&interp, "(gc)"), "0");
+    assert_eq!(eval(&interp, "(get (heap-stats) :collections)"), "2");
+}
@@ -0,0 +1,53 @@
+// `syntax-rules` hygiene, on both backends.
+
+use lisp_rs::{interpreter::Interpreter, printer::print_readably, Backend};
+
+fn eval(src: &str) -> String {
+    let mut results = [Backend::TreeWalker, Backend::Vm].map(|backend| {
+        let interp = Interpreter::with_backend(backend).unwrap();
+        match interp.eval_str(src) {
+            Ok(val) => print_readably(&val),
+            Err(err) => format!("error: {:#}", err),
+        }
+    });
+    assert_eq!(results[0], results[1], "backends disagree on {}", src);
+    std::mem::take(&mut results[0])
+}
+
+#[test]
+fn template_binders_do_not_capture() {
+    let src = "
+        (define-syntax my-or
+          (syntax-rules () ((_ a b) (let* ((t a)) (if t t b)))))
+        (let* ((t 5)) (my-or false t))";
+    assert_eq!(eval(src), "5");
+}
+
+#[test]
+fn definition_bindings_are_not_shadowed() {
+    let src = "
+        (def! double (fn* (x) (* 2 x)))
+        (define-syntax dbl (syntax-rules () ((_ x) (double x))))
+        (let* ((double (fn* (x) x))) (dbl 4))";
+    assert_eq!(eval(src), "8");
+}
+
+#[test]
+fn forward_reference_to_a_function() {
+    let src = "
+        (define-syntax twice (syntax-rules () ((_ x) (helper x x))))
+        (defn use-it () (twice 1))
+        (defn helper (a b) (+ a b))
+        (use-it)";
+    assert_eq!(eval(src), "2");
+}
+
+#[test]
+fn named_let_in_a_template() {
+    let src = "
+        (define-syntax sum-to
+          (syntax-rules ()
+            ((_ n) (let lp ((i 0) (acc 0)) (if (> i n) acc (lp (+ i 1) (+ acc i)))))))
+        (let* ((i 100) (acc 7)) (sum-to 4))";
+    assert_eq!(eval(src), "10");
+}
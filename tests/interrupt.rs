@@ -0,0 +1,64 @@
+// Interruption: from Ctrl-C in the REPL, or with `interrupt` from another
+// thread. The flag is process-wide, so these tests have a binary to
+// themselves.
+
+use std::{thread, time::Duration};
+
+use lisp_rs::{
+    interpreter::Interpreter,
+    limits::{interrupt, with_limits, Limits},
+};
+
+const SPIN: &str = "(loop () (recur))";
+
+#[test]
+fn interrupt_from_another_thread() {
+    let interp = Interpreter::new().unwrap();
+    let waker = thread::spawn(|| {
+        thread::sleep(Duration::from_millis(50));
+        interrupt();
+    });
+    let err = interp.eval_str(SPIN).unwrap_err();
+    waker.join().unwrap();
+    assert_eq!(format!("{:#}", err), "interrupted");
+    // the next evaluation starts afresh
+    assert!(interp.eval_str("(+ 1 2)").is_ok());
+
+    // a nested evaluation doesn't clear the interruption of the outer one
+    let ret = with_limits(&Limits::default(), || {
+        interrupt();
+        interp.eval_str("(+ 1 2)")
+    });
+    assert_eq!(format!("{:#}", ret.unwrap_err()), "interrupted");
+}
+
+#[cfg(unix)]
+#[test]
+fn ctrl_c_cancels_the_evaluation() {
+    use std::{
+        io::Write,
+        process::{Command, Stdio},
+    };
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_lisp_rs"))
+        .env(
+            "MAL_HISTORY",
+            std::env::temp_dir().join("lisp_rs-interrupt-history"),
+        )
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    writeln!(stdin, "{}", SPIN).unwrap();
+    thread::sleep(Duration::from_millis(500));
+    unsafe { libc::kill(child.id() as libc::pid_t, libc::SIGINT) };
+    writeln!(stdin, "(+ 1 2)").unwrap();
+    drop(stdin);
+    let out = child.wait_with_output().unwrap();
+    assert_eq!(
+        String::from_utf8(out.stdout).unwrap(),
+        "Error: interrupted\n3\n"
+    );
+}
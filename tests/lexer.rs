@@ -0,0 +1,121 @@
+// The lexer's tokens borrow from the source with their spans, and its
+// errors say where they are, as `line:col` with columns counted in chars.
+
+use lisp_rs::{
+    lexer::{Lexer, Span, TokenKind},
+    reader::{read_all, read_all_recovering},
+};
+
+// the error reading `src`
+fn error(src: &str) -> String {
+    read_all(src).unwrap_err().to_string()
+}
+
+#[test]
+fn tokens_have_kinds_and_spans() {
+    let src = "(f [x] #{:k} \"s\\n\" \\a) ; c";
+    let tokens: Vec<_> = Lexer::new(src)
+        .map(|token| {
+            let token = token.unwrap();
+            assert_eq!(&src[token.span.start..token.span.end], token.text);
+            (token.kind, token.text)
+        })
+        .collect();
+    use TokenKind::*;
+    assert_eq!(
+        tokens,
+        [
+            (Open, "("),
+            (Atom, "f"),
+            (OpenBracket, "["),
+            (Atom, "x"),
+            (CloseBracket, "]"),
+            (OpenSet, "#{"),
+            (Atom, ":k"),
+            (CloseBrace, "}"),
+            (Str, "\"s\\n\""),
+            (Char, "\\a"),
+            (Close, ")"),
+            (Comment, "; c"),
+        ]
+    );
+}
+
+#[test]
+fn unterminated_strings() {
+    assert_eq!(error("\"abc"), "1:1: unterminated string");
+    // the string runs to the end, so the list is left open too
+    assert_eq!(
+        error("(a\n  b \"abc)"),
+        "1:1: expected `)` to close this `(`, got EOF\n2:5: unterminated string"
+    );
+    // a backslash at the very end escapes nothing
+    assert_eq!(error("é \"\\"), "1:3: unterminated string");
+}
+
+#[test]
+fn invalid_escapes() {
+    assert_eq!(
+        error("(a\n  \"x\\qy\")"),
+        "2:5: invalid escape `\\q` in string"
+    );
+    // a tab counts as one column
+    assert_eq!(
+        error("x\n\ty \"a\\tb\""),
+        "2:6: invalid escape `\\t` in string"
+    );
+}
+
+#[test]
+fn stray_characters() {
+    assert_eq!(error("(a \u{7} b)"), "1:4: unexpected character '\\u{7}'");
+    assert_eq!(error("(a\n b))"), "2:4: unexpected `)`");
+    assert_eq!(
+        error("[1 2)"),
+        "1:1: expected `]` to close this `[`, got EOF\n1:5: unexpected `)`"
+    );
+    assert_eq!(error("\\ "), "1:1: expected a character after `\\`");
+    assert_eq!(error("|ab"), "1:1: unterminated `|` in name");
+}
+
+#[test]
+fn spans_of_errors() {
+    let diags: Vec<_> = Lexer::new("(a \"x\\qy\" \u{7} \"open")
+        .filter_map(Result::err)
+        .map(|diag| (diag.message, diag.span))
+        .collect();
+    assert_eq!(
+        diags,
+        [
+            (
+                "invalid escape `\\q` in string".to_owned(),
+                Span { start: 5, end: 7 }
+            ),
+            (
+                "unexpected character '\\u{7}'".to_owned(),
+                Span { start: 10, end: 11 }
+            ),
+            (
+                "unterminated string".to_owned(),
+                Span { start: 12, end: 17 }
+            ),
+        ]
+    );
+}
+
+// after an error the lexer carries on, and every error is reported
+#[test]
+fn carries_on_after_errors() {
+    let src = "(1 \"a\\qb\" 2)\n(\u{7} 3)\n\"open";
+    let (forms, diags) = read_all_recovering(src).unwrap();
+    assert_eq!(forms.len(), 2);
+    let described: Vec<_> = diags.iter().map(|diag| diag.describe(src)).collect();
+    assert_eq!(
+        described,
+        [
+            "1:6: invalid escape `\\q` in string",
+            "2:2: unexpected character '\\u{7}'",
+            "3:1: unterminated string",
+        ]
+    );
+}
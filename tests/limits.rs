@@ -0,0 +1,144 @@
+// Each budget stops a runaway evaluation, and a nested evaluation stays
+// within the budget of the one it runs in.
+
+use std::time::{Duration, Instant};
+
+use lisp_rs::{
+    interpreter::Interpreter,
+    limits::{with_limits, Limits},
+    Backend,
+};
+
+const SPIN: &str = "(loop () (recur))";
+
+fn eval_err(src: &str, limits: Limits) -> String {
+    let mut errs = vec![];
+    for backend in [Backend::TreeWalker, Backend::Vm] {
+        let interp = Interpreter::with_backend(backend).unwrap();
+        match interp.eval_with_limits(src, &limits) {
+            Ok(val) => panic!("{:?}: expected an error, got {:?}", backend, val),
+            Err(err) => errs.push(format!("{:#}", err)),
+        }
+    }
+    assert_eq!(errs[0], errs[1]);
+    errs.pop().unwrap()
+}
+
+#[test]
+fn fuel() {
+    let limits = Limits {
+        fuel: Some(1000),
+        ..Limits::default()
+    };
+    assert_eq!(eval_err(SPIN, limits), "out of fuel after 1000 steps");
+    let interp = Interpreter::new().unwrap();
+    assert!(interp.eval_with_limits("(+ 1 2)", &limits).is_ok());
+    // the budget is for one call, not used up by the ones before
+    assert!(interp.eval_with_limits("(+ 1 2)", &limits).is_ok());
+}
+
+#[test]
+fn timeout() {
+    let limits = Limits {
+        timeout: Some(Duration::from_millis(50)),
+        ..Limits::default()
+    };
+    let start = Instant::now();
+    assert_eq!(eval_err(SPIN, limits), "evaluation timed out");
+    assert!(start.elapsed() < Duration::from_secs(5));
+}
+
+#[test]
+fn allocations() {
+    let limits = Limits {
+        allocations: Some(1000),
+        ..Limits::default()
+    };
+    let src = "(loop ((acc ())) (recur (cons 1 acc)))";
+    assert_eq!(eval_err(src, limits), "allocation limit exceeded");
+}
+
+#[test]
+fn max_depth() {
+    let limits = Limits {
+        max_depth: Some(50),
+        ..Limits::default()
+    };
+    let src = "(do (def! f (fn* (n) (+ 1 (f n)))) (f 1))";
+    assert!(eval_err(src, limits).contains("stack depth exceeded (limit 50)"));
+}
+
+#[test]
+fn limits_cover_all_the_forms() {
+    let interp = Interpreter::new().unwrap();
+    let limits = Limits {
+        fuel: Some(1000),
+        ..Limits::default()
+    };
+    let loop_600_steps = "(loop ((i 0)) (if (< i 60) (recur (+ i 1)) i))";
+    assert!(interp.eval_with_limits(loop_600_steps, &limits).is_ok());
+    let twice = format!("{} {}", loop_600_steps, loop_600_steps);
+    let err = interp.eval_with_limits(&twice, &limits).unwrap_err();
+    assert!(format!("{:#}", err).starts_with("out of fuel"));
+}
+
+#[test]
+fn nested_calls_stay_within_the_outer_limits() {
+    let interp = Interpreter::new().unwrap();
+    let fuel = |fuel| Limits {
+        fuel: Some(fuel),
+        ..Limits::default()
+    };
+
+    // an unlimited inner call is still limited by the outer one
+    let ret = with_limits(&fuel(1000), || interp.eval_str(SPIN));
+    assert!(format!("{:#}", ret.unwrap_err()).starts_with("out of fuel"));
+    let ret = with_limits(
+        &Limits {
+            timeout: Some(Duration::from_millis(50)),
+            ..Limits::default()
+        },
+        || interp.eval_str(SPIN),
+    );
+    assert_eq!(format!("{:#}", ret.unwrap_err()), "evaluation timed out");
+
+    // a stricter inner limit applies
+    let ret = with_limits(&fuel(100_000), || interp.eval_with_limits(SPIN, &fuel(10)));
+    assert_eq!(
+        format!("{:#}", ret.unwrap_err()),
+        "out of fuel after 10 steps"
+    );
+
+    // what inner calls use is charged to the outer budget
+    let loop_600_steps = "(loop ((i 0)) (if (< i 60) (recur (+ i 1)) i))";
+    let ret = with_limits(&fuel(1000), || {
+        interp.eval_str(loop_600_steps)?;
+        interp.eval_str(loop_600_steps)
+    });
+    assert!(format!("{:#}", ret.unwrap_err()).starts_with("out of fuel"));
+    let ret = with_limits(
+        &Limits {
+            allocations: Some(1000),
+            ..Limits::default()
+        },
+        || {
+            for _ in 0..10 {
+                interp.eval_str(
+                    "(loop ((i 0) (acc ())) (if (< i 200) (recur (+ i 1) (cons i acc)) i))",
+                )?;
+            }
+            Ok(())
+        },
+    );
+    assert_eq!(
+        format!("{:#}", ret.unwrap_err()),
+        "allocation limit exceeded"
+    );
+
+    // and the outer budget is back once they return
+    let ret = with_limits(&fuel(1000), || {
+        with_limits(&fuel(10), || interp.eval_str("(+ 1 2)"))?;
+        interp.eval_str(loop_600_steps)
+    });
+    assert!(ret.is_ok());
+}
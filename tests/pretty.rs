@@ -0,0 +1,63 @@
+// The pretty-printer's layout: what stays on one line, where lines break
+// and how far the rest is indented.
+
+use lisp_rs::{pretty::pprint, printer::PrintConfig, reader::read_str};
+
+fn pp(src: &str, width: usize) -> String {
+    pprint(&read_str(src).unwrap(), &PrintConfig::default(), width).unwrap()
+}
+
+#[test]
+fn fits_on_one_line() {
+    let src = "(def! x (fn* (a) a))";
+    assert_eq!(pp(src, 80), src);
+    assert_eq!(pp(src, 20), src);
+}
+
+#[test]
+fn special_forms_indent_their_body() {
+    let src = "(defn f (x y) (let ((z (+ x y))) (if (> z 0) (println \"positive\" z) (println \"not\" z))))";
+    assert_eq!(
+        pp(src, 30),
+        "\
+(defn f (x y)
+  (let ((z (+ x y)))
+    (if (> z 0)
+        (println \"positive\" z)
+        (println \"not\" z))))"
+    );
+    assert_eq!(pp("(do (a) (b))", 8), "(do\n  (a)\n  (b))");
+}
+
+#[test]
+fn calls_align_with_the_first_argument() {
+    assert_eq!(
+        pp("(foo alpha beta gamma delta)", 15),
+        "(foo alpha\n     beta\n     gamma\n     delta)"
+    );
+}
+
+#[test]
+fn data_fills_lines() {
+    assert_eq!(
+        pp("(1 2 3 4 5 6 7 8 9 10 11 12 13 14 15)", 12),
+        "(1 2 3 4 5 6\n 7 8 9 10 11\n 12 13 14\n 15)"
+    );
+}
+
+#[test]
+fn vectors_and_sets_fill_lines() {
+    assert_eq!(
+        pp("[1 2 3 4 5 6 7 8 9 10 11 12 13 14 15]", 12),
+        "[1 2 3 4 5 6\n 7 8 9 10 11\n 12 13 14\n 15]"
+    );
+    assert_eq!(pp("#{:a :b :c :d :e}", 10), "#{:a :b :c\n  :d :e}");
+}
+
+#[test]
+fn maps_break_between_entries() {
+    assert_eq!(
+        pp("{:a 1 :b (list 1 2 3) :c \"long string here\"}", 20),
+        "{:a 1\n :b (list 1 2 3)\n :c \"long string here\"}"
+    );
+}
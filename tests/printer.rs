@@ -0,0 +1,66 @@
+// Print options apply the same way to the printer and the pretty-printer.
+
+use lisp_rs::{
+    pretty::pprint,
+    printer::{print_with, LiteralStyle, PrintConfig},
+    reader::read_str,
+};
+
+// `src` read and printed with `config`, flat and pretty
+fn print(src: &str, config: &PrintConfig) -> (String, String) {
+    let val = read_str(src).unwrap();
+    (
+        print_with(&val, config).unwrap(),
+        pprint(&val, config, 80).unwrap(),
+    )
+}
+
+fn assert_prints(src: &str, config: &PrintConfig, expected: &str) {
+    let (flat, pretty) = print(src, config);
+    assert_eq!(flat, expected, "printing {}", src);
+    assert_eq!(pretty, expected, "pretty-printing {}", src);
+}
+
+#[test]
+fn literal_styles() {
+    let src = "(nil true false \"a\\nb\" \\c)";
+    assert_prints(src, &PrintConfig::default(), src);
+    let legacy = PrintConfig {
+        style: LiteralStyle::Legacy,
+        ..PrintConfig::default()
+    };
+    assert_prints(src, &legacy, "(NIL TRUE FALSE \"a\\nb\" \\c)");
+    let display = PrintConfig {
+        readably: false,
+        ..PrintConfig::default()
+    };
+    assert_prints(src, &display, "(nil true false a\nb c)");
+}
+
+#[test]
+fn max_depth() {
+    let config = PrintConfig {
+        max_depth: Some(2),
+        ..PrintConfig::default()
+    };
+    assert_prints("(1 (2 (3 (4))))", &config, "(1 (2 ...))");
+    assert_prints("{:a {:b {:c 1}}}", &config, "{:a {:b ...}}");
+    assert_prints("(1 2)", &config, "(1 2)");
+}
+
+#[test]
+fn max_length_counts_elements_and_entries() {
+    let config = PrintConfig {
+        max_length: Some(2),
+        ..PrintConfig::default()
+    };
+    assert_prints("(1 2 3 4)", &config, "(1 2 ...)");
+    assert_prints("(1 2)", &config, "(1 2)");
+    assert_prints("{:a 1 :b 2 :c 3}", &config, "{:a 1 :b 2 ...}");
+    assert_prints("{:a 1 :b 2}", &config, "{:a 1 :b 2}");
+    let config = PrintConfig {
+        max_length: Some(1),
+        ..PrintConfig::default()
+    };
+    assert_prints("{:a 1 :b 2}", &config, "{:a 1 ...}");
+}
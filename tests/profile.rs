@@ -0,0 +1,40 @@
+// The profiler counts the same calls on both backends.
+
+use std::thread;
+
+use lisp_rs::{interpreter::Interpreter, profile, Backend};
+
+// the calls counted for `name`; each backend runs on its own thread, since
+// the profile is per thread
+fn calls(backend: Backend, src: &'static str, name: &'static str) -> u64 {
+    thread::spawn(move || {
+        profile::enable();
+        let interp = Interpreter::with_backend(backend).unwrap();
+        interp.eval_str(src).unwrap();
+        let report = profile::report();
+        report
+            .lines()
+            .find(|line| line.split_whitespace().last() == Some(name))
+            .map_or(0, |line| {
+                line.split_whitespace().next().unwrap().parse().unwrap()
+            })
+    })
+    .join()
+    .unwrap()
+}
+
+#[test]
+fn macro_calls_are_counted() {
+    let src = "(do (defmacro! twice (fn* (x) `(do ~x ~x))) (twice 1) (twice 2))";
+    for backend in [Backend::TreeWalker, Backend::Vm] {
+        assert_eq!(calls(backend, src, "twice"), 2, "{:?}", backend);
+    }
+}
+
+#[test]
+fn applied_functions_are_counted() {
+    let src = "(do (def! id (fn* (m) m)) (vary-meta '(1) id))";
+    for backend in [Backend::TreeWalker, Backend::Vm] {
+        assert_eq!(calls(backend, src, "id"), 1, "{:?}", backend);
+    }
+}
@@ -0,0 +1,120 @@
+// The REPL, driven through the binary with its input piped in.
+
+use std::{
+    fs,
+    io::Write,
+    path::PathBuf,
+    process::{Command, Stdio},
+};
+
+// the output of the REPL for `input`, with `args`; `history` is where it
+// keeps its history, through `$MAL_HISTORY`
+fn run_with(args: &[&str], history: Option<&PathBuf>, input: &str) -> String {
+    let mut cmd = Command::new(env!("CARGO_BIN_EXE_lisp_rs"));
+    cmd.args(args)
+        .env("RUST_BACKTRACE", "1")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null());
+    match history {
+        Some(path) => cmd.env("MAL_HISTORY", path),
+        None => cmd.env("MAL_HISTORY", temp("unused-history")),
+    };
+    let mut child = cmd.spawn().unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    let out = child.wait_with_output().unwrap();
+    String::from_utf8(out.stdout).unwrap()
+}
+
+fn run(input: &str) -> String {
+    run_with(&[], None, input)
+}
+
+fn temp(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("lisp_rs-repl-{}-{}", std::process::id(), name));
+    let _ = fs::remove_file(&path);
+    path
+}
+
+#[test]
+fn result_history() {
+    let out = run("(+ 1 2)\n(* *1 2)\n(list *1 *2 *3)\n(nth () 1)\n*e\n");
+    assert_eq!(
+        out,
+        "3\n6\n(6 3 nil)\nError: index 1 out of range\n\"index 1 out of range\"\n"
+    );
+}
+
+#[test]
+fn errors_have_no_backtrace() {
+    let out = run("(undefined-name)\n(\n");
+    assert_eq!(out.lines().count(), 2, "{}", out);
+    assert!(
+        out.starts_with("Error: `undefined-name` not found\n"),
+        "{}",
+        out
+    );
+    assert!(out.contains("Parse Error: "), "{}", out);
+}
+
+#[test]
+fn meta_commands() {
+    let out = run(":expand (when x 1)\n:step (when x 1)\n:pp (def! x 1)\n:quit\n(+ 1 2)\n");
+    assert_eq!(out, "(if x (do 1))\n(when x 1)\n=> (if x (do 1))\n1\n");
+    let out = run("(def! y 7)\n:env\n:reset\ny\n:nope\n");
+    assert!(out.contains("y = 7\n"), "{}", out);
+    assert!(out.contains("Error: `y` not found\n"), "{}", out);
+    assert!(
+        out.ends_with("Unknown command `:nope`. Try :help\n"),
+        "{}",
+        out
+    );
+    assert!(run(":help\n").contains(":step <expr>"));
+}
+
+#[test]
+fn meta_commands_respect_limits() {
+    let spin = "(defmacro! spin (fn* () (loop ((i 0)) (if (< i 100000) (recur (+ i 1)) 1))))\n";
+    for cmd in [":expand (spin)", ":step (spin)"] {
+        let out = run_with(&["--fuel", "10000"], None, &format!("{}{}\n", spin, cmd));
+        assert!(out.contains("Error: out of fuel"), "{}: {}", cmd, out);
+    }
+}
+
+#[test]
+fn history_file() {
+    let from_env = temp("env-history");
+    run_with(&[], Some(&from_env), "(+ 1 2)\n");
+    assert!(fs::read_to_string(&from_env).unwrap().contains("(+ 1 2)"));
+
+    let from_flag = temp("flag-history");
+    let path = from_flag.to_str().unwrap();
+    run_with(&["--history", path], Some(&from_env), "(+ 3 4)\n");
+    assert!(fs::read_to_string(&from_flag).unwrap().contains("(+ 3 4)"));
+    assert!(!fs::read_to_string(&from_env).unwrap().contains("(+ 3 4)"));
+}
+
+#[test]
+fn step_expands_nested_macros_one_at_a_time() {
+    let out = run("(defmacro! m2 (fn* (x) `(+ ~x 1)))\n\
+         (defmacro! m1 (fn* (x) `(m2 ~x)))\n\
+         :step (list (m1 5) (when 1 (m1 2)))\n");
+    assert_eq!(
+        out,
+        "\
+<macro>
+<macro>
+(list (m1 5) (when 1 (m1 2)))
+=> (list (m2 5) (when 1 (m1 2)))
+=> (list (+ 5 1) (when 1 (m1 2)))
+=> (list (+ 5 1) (if 1 (do (m1 2))))
+=> (list (+ 5 1) (if 1 (do (m2 2))))
+=> (list (+ 5 1) (if 1 (do (+ 2 1))))
+"
+    );
+}
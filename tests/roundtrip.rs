@@ -0,0 +1,86 @@
+// Printing a value readably and reading the output back gives the same value.
+
+use lisp_rs::{
+    hamt::Hamt,
+    printer::print_readably,
+    reader::read_str,
+    symbol::Symbol,
+    types::{map_key, MalVal},
+};
+use proptest::prelude::*;
+
+fn key() -> impl Strategy<Value = MalVal> {
+    prop_oneof![
+        any::<i64>().prop_map(MalVal::Num),
+        any::<String>().prop_map(MalVal::Str),
+        any::<char>().prop_map(MalVal::Char),
+        any::<String>().prop_map(MalVal::Kw),
+        any::<String>().prop_map(|s| MalVal::Sym(Symbol::new(&s), None)),
+    ]
+}
+
+fn value() -> impl Strategy<Value = MalVal> {
+    let leaf = prop_oneof![
+        Just(MalVal::Nil),
+        any::<bool>().prop_map(MalVal::Bool),
+        key(),
+    ];
+    leaf.prop_recursive(4, 64, 8, |inner| {
+        prop_oneof![
+            prop::collection::vec(inner.clone(), 0..8).prop_map(|v| MalVal::List(v.into())),
+            prop::collection::vec(inner.clone(), 0..8).prop_map(|v| MalVal::Vector(v.into())),
+            prop::collection::vec(key(), 0..8).prop_map(|elts| {
+                let mut set = Hamt::new();
+                for elt in elts {
+                    set.insert(map_key(&elt).unwrap(), elt);
+                }
+                MalVal::Set(set)
+            }),
+            prop::collection::vec((key(), inner), 0..8).prop_map(|entries| {
+                let mut map = Hamt::new();
+                for (k, v) in entries {
+                    map.insert(map_key(&k).unwrap(), v);
+                }
+                MalVal::Map(map)
+            }),
+        ]
+    })
+}
+
+proptest! {
+    #[test]
+    fn read_back_printed_value(val in value()) {
+        let printed = print_readably(&val);
+        let read = read_str(&printed);
+        prop_assert!(read.is_ok(), "`{}` does not read: {:?}", printed, read);
+        prop_assert_eq!(read.unwrap(), val, "printed as `{}`", printed);
+    }
+}
+
+#[test]
+fn names_that_need_bars() {
+    for (val, printed) in [
+        (MalVal::Kw("a b".to_owned()), ":|a b|"),
+        (MalVal::Kw(String::new()), ":||"),
+        (MalVal::Sym(Symbol::new("12"), None), "|12|"),
+        (MalVal::Sym(Symbol::new("nil"), None), "|nil|"),
+        (MalVal::Sym(Symbol::new("a|b"), None), "|a\\|b|"),
+        (MalVal::Sym(Symbol::new("f(x)"), None), "|f(x)|"),
+        (MalVal::Sym(Symbol::new("#<a>"), None), "|#<a>|"),
+    ] {
+        assert_eq!(print_readably(&val), printed);
+        assert_eq!(read_str(printed).unwrap(), val);
+    }
+}
+
+// a gensym reads back as no symbol, so it isn't printed as one
+#[test]
+fn gensyms_are_unreadable() {
+    let g = Symbol::gensym(Symbol::new("x"));
+    let printed = print_readably(&MalVal::List(vec![MalVal::Sym(g, None)].into()));
+    assert_eq!(
+        printed,
+        format!("(#<gensym x__{}>)", g.gensym_number().unwrap())
+    );
+    assert!(read_str(&printed).is_err());
+}
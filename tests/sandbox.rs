@@ -0,0 +1,158 @@
+// Sandboxes only reach the builtins they were given: no value handed in and
+// nothing evaluated inside one gets anything else.
+
+use lisp_rs::{
+    core,
+    interpreter::Interpreter,
+    limits::Limits,
+    sandbox::{Sandbox, CAPABILITIES, DEFAULT_CAPABILITIES, DENIED},
+};
+
+fn new_sandbox(capabilities: &[&str]) -> Sandbox {
+    Sandbox::new(capabilities, Limits::default()).unwrap()
+}
+
+fn eval_err(sandbox: &Sandbox, src: &str) -> String {
+    match sandbox.eval_str(src) {
+        Ok(val) => panic!("expected an error from {}, got {:?}", src, val),
+        Err(err) => format!("{:#}", err),
+    }
+}
+
+// so that a new builtin doesn't silently go missing from every sandbox
+#[test]
+fn every_builtin_is_in_a_capability_set_or_denied() {
+    let granted: Vec<&str> = CAPABILITIES
+        .iter()
+        .flat_map(|(_, names)| names.iter().copied())
+        .collect();
+    for (name, _) in core::ns() {
+        assert!(
+            granted.contains(&name) != DENIED.contains(&name),
+            "`{}` must be in exactly one of CAPABILITIES or DENIED",
+            name
+        );
+    }
+    for name in granted.iter().chain(DENIED) {
+        assert!(
+            core::ns().iter().any(|(n, _)| n == name),
+            "`{}` is no builtin",
+            name
+        );
+    }
+}
+
+#[test]
+fn unknown_capability() {
+    assert!(Sandbox::new(&["nope"], Limits::default()).is_err());
+}
+
+#[test]
+fn builtins_not_granted_are_missing() {
+    let sandbox = new_sandbox(DEFAULT_CAPABILITIES);
+    assert_eq!(
+        eval_err(&sandbox, "(slurp \"/etc/passwd\")"),
+        "`slurp` not found"
+    );
+    assert_eq!(eval_err(&sandbox, "(time-ms)"), "`time-ms` not found");
+    // constant folding doesn't bring back a builtin the sandbox lacks
+    let data_only = new_sandbox(&["data"]);
+    assert_eq!(eval_err(&data_only, "(+ 1 2)"), "`+` not found");
+}
+
+#[test]
+fn closures_from_outside_are_rejected() {
+    let sandbox = new_sandbox(DEFAULT_CAPABILITIES);
+    let outside = Interpreter::new().unwrap();
+    let closure = outside.eval_str("(fn* (path) (slurp path))").unwrap();
+    assert!(sandbox.define("f", closure).is_err());
+    let other = new_sandbox(DEFAULT_CAPABILITIES);
+    let closure = other.eval_str("(fn* () 1)").unwrap();
+    assert!(sandbox.define("f", closure).is_err());
+    let builtin = outside.eval_str("slurp").unwrap();
+    assert!(sandbox.define("f", builtin).is_err());
+    let nested = outside.eval_str("(list 1 {:f slurp})").unwrap();
+    assert!(sandbox.define("f", nested).is_err());
+    let atom = outside.eval_str("(atom slurp)").unwrap();
+    assert!(sandbox.define("f", atom).is_err());
+
+    // the sandbox's own values can go back in
+    let own = sandbox.eval_str("(fn* (x) (+ x 1))").unwrap();
+    sandbox.define("inc", own).unwrap();
+    assert_eq!(
+        sandbox.eval_str("(inc 1)").unwrap(),
+        outside.eval_str("2").unwrap()
+    );
+}
+
+#[test]
+fn def_of_a_core_name_stays_inside() {
+    let sandbox = new_sandbox(DEFAULT_CAPABILITIES);
+    sandbox.eval_str("(def! + (fn* (a b) 0))").unwrap();
+    sandbox.eval_str("(def! slurp (fn* (path) path))").unwrap();
+    assert_eq!(
+        sandbox.eval_str("(list (+ 1 2) (slurp \"x\"))").unwrap(),
+        sandbox.eval_str("'(0 \"x\")").unwrap()
+    );
+    let outside = Interpreter::new().unwrap();
+    assert_eq!(
+        outside.eval_str("(+ 1 2)").unwrap(),
+        outside.eval_str("3").unwrap()
+    );
+    let fresh = new_sandbox(DEFAULT_CAPABILITIES);
+    assert_eq!(
+        fresh.eval_str("(+ 1 2)").unwrap(),
+        outside.eval_str("3").unwrap()
+    );
+    assert_eq!(eval_err(&fresh, "(slurp \"x\")"), "`slurp` not found");
+}
+
+#[test]
+fn limits_apply_to_each_call() {
+    let limits = Limits {
+        allocations: Some(1000),
+        fuel: Some(100_000),
+        ..Limits::default()
+    };
+    let sandbox = Sandbox::new(DEFAULT_CAPABILITIES, limits).unwrap();
+    assert_eq!(
+        eval_err(&sandbox, "(loop ((acc ())) (recur (cons 1 acc)))"),
+        "allocation limit exceeded"
+    );
+    assert!(eval_err(&sandbox, "(loop () (recur))").starts_with("out of fuel"));
+    assert!(sandbox.eval_str("(count (list 1 2 3))").is_ok());
+}
+
+#[test]
+fn metadata_is_checked() {
+    let sandbox = new_sandbox(DEFAULT_CAPABILITIES);
+    let outside = Interpreter::new().unwrap();
+    let val = outside.eval_str("(with-meta '(1) {:read slurp})").unwrap();
+    assert!(sandbox.define("x", val).is_err());
+    let val = outside.eval_str("(with-meta '(1) {:a 1})").unwrap();
+    sandbox.define("x", val).unwrap();
+    assert_eq!(
+        sandbox.eval_str("(meta x)").unwrap(),
+        outside.eval_str("{:a 1}").unwrap()
+    );
+}
+
+#[test]
+fn default_capabilities_cover_the_builtins() {
+    let sandbox = new_sandbox(DEFAULT_CAPABILITIES);
+    for src in [
+        "(conj '(2) 1)",
+        "(char? (int->char (char->int \\a)))",
+        "(seq \"ab\")",
+        "(upper-case (trim \" a \"))",
+        "(replace \"abc\" \"b\" \"x\")",
+        "(index-of \"abc\" \"c\")",
+        "(starts-with? (lower-case (str (char \"A\"))) \"a\")",
+        "(ends-with? \"ab\" \"b\")",
+        "(keyword \"k\")",
+    ] {
+        if let Err(err) = sandbox.eval_str(src) {
+            panic!("{}: {:#}", src, err);
+        }
+    }
+}
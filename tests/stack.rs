@@ -0,0 +1,83 @@
+// Deep recursion and deep nesting fail with an error instead of overflowing
+// the stack, on a thread with the default stack size.
+
+use lisp_rs::{
+    interpreter::Interpreter,
+    printer::{print_with, PrintConfig},
+    types::MalVal::{self, List},
+    Backend,
+};
+
+fn thread<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> T {
+    std::thread::Builder::new()
+        .stack_size(2 * 1024 * 1024)
+        .spawn(f)
+        .unwrap()
+        .join()
+        .unwrap()
+}
+
+fn eval_err(backend: Backend, src: String) -> String {
+    thread(move || {
+        let interp = Interpreter::with_backend(backend).unwrap();
+        match interp.eval_str(&src) {
+            Ok(val) => panic!("expected an error, got {:?}", val),
+            Err(err) => format!("{:#}", err),
+        }
+    })
+}
+
+#[test]
+fn deep_recursion() {
+    let src = "(def! f (fn* (n) (if (= n 0) 0 (+ 1 (f (- n 1)))))) (f 100000)".to_owned();
+    for backend in [Backend::TreeWalker, Backend::Vm] {
+        let err = eval_err(backend, src.clone());
+        assert!(err.contains("stack depth exceeded"), "{}", err);
+    }
+}
+
+#[test]
+fn deeply_nested_source() {
+    let src = format!("{}1{}", "(quote ".repeat(9000), ")".repeat(9000));
+    for backend in [Backend::TreeWalker, Backend::Vm] {
+        let err = eval_err(backend, src.clone());
+        assert!(err.contains("stack depth exceeded"), "{}", err);
+    }
+}
+
+fn nested(depth: usize) -> MalVal {
+    let mut val = List(vec![].into());
+    for _ in 0..depth {
+        val = List(vec![val].into());
+    }
+    val
+}
+
+#[test]
+fn printing_a_deep_list() {
+    let err = thread(|| {
+        let val = nested(100_000);
+        print_with(&val, &PrintConfig::default()).map_err(|err| err.to_string())
+    });
+    assert!(err.unwrap_err().contains("stack depth exceeded"));
+}
+
+#[test]
+fn dropping_a_deep_list() {
+    thread(|| drop(nested(3_000_000)));
+}
+
+#[test]
+fn map_over_a_long_list() {
+    let src =
+        "(let* ((xs (loop ((i 0) (acc ())) (if (< i 20000) (recur (+ i 1) (cons i acc)) acc)))
+                      (ys (map (fn* (x) (+ x 1)) xs)))
+                 (list (count ys) (first ys) (nth ys 19999) (first (reverse ys))))";
+    for backend in [Backend::TreeWalker, Backend::Vm] {
+        let ret = thread(move || {
+            let interp = Interpreter::with_backend(backend).unwrap();
+            print_with(&interp.eval_str(src).unwrap(), &PrintConfig::default()).unwrap()
+        });
+        assert_eq!(ret, "(20000 20000 1 1)", "{:?}", backend);
+    }
+}